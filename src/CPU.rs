@@ -1,5 +1,8 @@
+use crate::bus::Bus;
 use crate::opcodes;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
 use std::collections::HashMap;
+use std::fmt;
 
 bitflags! {
     pub struct CpuFlags: u8 {
@@ -16,14 +19,206 @@ bitflags! {
 
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
-pub struct CPU {
+const RESET_CYCLES: u8 = 7;                                                   //the reset sequence takes as long as an interrupt
+const RESET_VECTOR: u16 = 0xFFFC;
+
+mod interrupt {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub enum InterruptType {
+        Nmi,
+        Irq,
+        Brk,
+    }
+
+    #[derive(PartialEq, Eq)]
+    pub(super) struct Interrupt {
+        pub(super) itype: InterruptType,
+        pub(super) vector_addr: u16,
+        pub(super) b_flag_mask: u8,                                           //BRK pushes the status with B set, hardware interrupts push it clear
+        pub(super) cpu_cycles: u8,
+    }
+
+    pub(super) const NMI: Interrupt = Interrupt {
+        itype: InterruptType::Nmi,
+        vector_addr: 0xFFFA,
+        b_flag_mask: 0b0010_0000,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const IRQ: Interrupt = Interrupt {
+        itype: InterruptType::Irq,
+        vector_addr: 0xFFFE,
+        b_flag_mask: 0b0010_0000,
+        cpu_cycles: 7,
+    };
+
+    pub(super) const BRK: Interrupt = Interrupt {
+        itype: InterruptType::Brk,
+        vector_addr: 0xFFFE,                                                  //BRK shares the IRQ vector
+        b_flag_mask: 0b0011_0000,
+        cpu_cycles: 7,
+    };
+}
+
+pub use interrupt::InterruptType;
+
+fn page_crossed(base: u16, addr: u16) -> bool {
+    base & 0xFF00 != addr & 0xFF00
+}
+
+//works out the effective address for the operand bytes at operand and whether indexing crossed into a new page.
+//every byte comes through read, the CPU hands in mem_read and the trace hands in Bus::peek
+pub(crate) fn operand_address(
+    mode: &AddressMode,
+    operand: u16,
+    register_x: u8,
+    register_y: u8,
+    mut read: impl FnMut(u16) -> u8,
+) -> (u16, bool) {
+    let mut read_u16 = |addr: u16| {
+        let lo = read(addr) as u16;
+        let hi = read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    };
+
+    match mode {
+        AddressMode::Immeditate => (operand, false),                                    //For immeditate addressing we load in a value into a register (ie LDX #$01 loads $01 into X reg)
+
+        AddressMode::ZeroPage => (read(operand) as u16, false),                  //For zero page addressing mode we load in the value at an address into a register (ie LDX $01 loads the value at address $01 into X reg)
+
+        AddressMode::Absolute => (read_u16(operand), false),                    //For Absolute addressing mode we store an value at an entire 16bit memory location (ie STA $1234 stores the value in A at $1234)
+
+        //in zero page only first page of addresses are allowed (first 256 bytes have 3 cpu cycle retrieve time rather than 4-7)
+        //For zero page a zero page address is given and then the value of reg x is added to it
+        AddressMode::ZeroPageX => {
+            let pos = read(operand);
+            (pos.wrapping_add(register_x) as u16, false)                 //wrapping add is used if sum is larger than single byte
+        }
+
+        AddressMode::ZeroPageY => {
+            let pos = read(operand);
+            (pos.wrapping_add(register_y) as u16, false)
+        }
+
+        //Absolute version of zero page, uses full memory location rather than just zero page
+        AddressMode::AbsoluteX => {
+            let base = read_u16(operand);
+            let addr = base.wrapping_add(register_x as u16);
+            (addr, page_crossed(base, addr))
+        }
+
+        AddressMode::AbsoluteY => {
+            let base = read_u16(operand);
+            let addr = base.wrapping_add(register_y as u16);
+            (addr, page_crossed(base, addr))
+        }
+
+        //Indirect uses absolute address to look up another address, ie first address gives least sig byte of address and following gives most sig byte
+        AddressMode::IndirectX => {
+            let base = read(operand);
+            let ptr: u8 = base.wrapping_add(register_x);
+            let lo = read(ptr as u16);
+            let hi = read(ptr.wrapping_add(1) as u16);
+            ((hi as u16) << 8 | (lo as u16), false)
+        }
+
+        AddressMode::IndirectY => {
+            let base = read(operand);
+            let lo = read(base as u16);
+            let hi = read(base.wrapping_add(1) as u16);
+            let deref_base = (hi as u16) << 8 | (lo as u16);
+            let deref = deref_base.wrapping_add(register_y as u16);
+            (deref, page_crossed(deref_base, deref))
+        }
+
+        AddressMode::NoneAddress => {
+            panic!("Mode {:?} is not suppoeted", mode);
+        }
+    }
+}
+
+//what the CPU does when it fetches one of the undocumented opcodes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalOpcodeMode {
+    Execute,                                                                  //run them the way a real 2A03 does
+    Trap,                                                                     //stop and report a CpuError::IllegalOpcode
+    Nop,                                                                      //skip over them like a NOP of the same length
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuConfig {
+    pub illegal_opcodes: IllegalOpcodeMode,
+    pub halt_on_brk: bool,                                                    //stop running at BRK instead of taking the interrupt, handy for small test programs
+}
+
+impl Default for CpuConfig {
+    fn default() -> Self {
+        CpuConfig {
+            illegal_opcodes: IllegalOpcodeMode::Execute,
+            halt_on_brk: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    IllegalOpcode { code: u8, address: u16 },                                //only raised with IllegalOpcodeMode::Trap
+    Jammed { code: u8, address: u16 },                                       //a KIL/JAM opcode locked up the CPU
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::IllegalOpcode { code, address } => {
+                write!(f, "illegal opcode {:02x} at {:04x}", code, address)
+            }
+            CpuError::Jammed { code, address } => {
+                write!(f, "CPU jammed by opcode {:02x} at {:04x}", code, address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+//why one of the step wise run helpers handed control back to the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Brk { address: u16 },                                                     //only with CpuConfig::halt_on_brk, the PC is left after the opcode
+    Jammed { code: u8, address: u16 },                                       //a KIL/JAM opcode locked up the CPU, the PC stays on it
+    IllegalOpcode { code: u8, address: u16 },                                //only raised with IllegalOpcodeMode::Trap
+    HostRequest,                                                              //CPU::request_stop was called
+    Condition,                                                                //the run_until predicate returned true
+    CyclesElapsed,
+    FrameComplete,
+}
+
+impl StopReason {
+    pub fn is_halt(&self) -> bool {                                           //the CPU can't go on without the host changing its state
+        matches!(self, StopReason::Brk { .. } | StopReason::Jammed { .. } | StopReason::IllegalOpcode { .. })
+    }
+
+    fn into_result(self) -> Result<(), CpuError> {
+        match self {
+            StopReason::Jammed { code, address } => Err(CpuError::Jammed { code, address }),
+            StopReason::IllegalOpcode { code, address } => Err(CpuError::IllegalOpcode { code, address }),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub struct CPU<M: Mem = Bus> {                                              //the CPU only sees memory through Mem so any backend can be plugged in
     pub register_a: u8,
     pub register_x: u8,
     pub register_y: u8,
     pub status: CpuFlags,
     pub program_counter: u16,
     pub stack_pointer: u8,
-    memory: [u8; 0xFFFF]
+    pub config: CpuConfig,
+    pub cycles: u64,                                                          //total CPU cycles since power on, the PPU and APU are timed off this
+    pub bus: M,
+    stop_requested: bool,
+    pc_written: bool,                                                         //the running instruction set the PC itself, so execute doesn't step over its operands
 }
 #[derive(Debug)]
 #[allow(non_camel_case_types)]
@@ -40,41 +235,75 @@ pub enum AddressMode {
     NoneAddress,
 }
 
-fn main() {
-    println!("");
-}
-
-trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+pub trait Mem {
+    fn mem_read(&mut self, addr: u16) -> u8;                                 //reads take &mut self since some registers change state when they are read
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn peek(&self, addr: u16) -> u8;                                          //what a read would return, without its side effects, for tools looking at memory
+
+    fn tick(&mut self, _cycles: u8) {}                                        //called with the CPU cycles of every instruction so devices on the bus can keep in step
+
+    fn take_stall_cycles(&mut self) -> u16 {                                  //cycles the bus stole from the CPU (OAM DMA), collected after every instruction
+        0
+    }
+
+    fn poll_nmi(&mut self) -> bool {                                          //NMI is edge triggered, this returns true once for every edge
+        false
+    }
+
+    fn irq_line(&mut self) -> bool {                                          //IRQ is level triggered, it stays asserted until the device acknowledges it
+        false
+    }
+
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let lo = self.mem_read(pos) as u16;
-        let hi = self.mem_read(pos + 1) as u16;
-        (hi << 8) | (lo as u16)
+        let hi = self.mem_read(pos.wrapping_add(1)) as u16;
+        (hi << 8) | lo
     }
 
     fn mem_write_u16(&mut self, pos: u16, data: u16) {
         let hi = (data >> 8) as u8;
         let lo = (data & 0xff) as u8;
         self.mem_write(pos, lo);
-        self.mem_write(pos + 1, hi);
+        self.mem_write(pos.wrapping_add(1), hi);
     }
 }
 
-impl Mem for CPU {
-    fn mem_read(&self, addr: u16) -> u8 {                                    //this is to read address space of 8bits
-        self.memory[addr as usize]
+impl<M: Mem> Mem for CPU<M> {
+    fn mem_read(&mut self, addr: u16) -> u8 {                                //this is to read address space of 8bits
+        self.bus.mem_read(addr)
     }
     fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+        self.bus.mem_write(addr, data);
+    }
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-impl CPU {   
+impl CPU {
     pub fn new() -> Self {
+        CPU::with_bus(Bus::new())
+    }
+
+    pub fn run_frame(&mut self) -> StopReason {                               //runs until the PPU starts its next vblank
+        let frame = self.bus.ppu().frame_count;
+        match self.run_until(|cpu| cpu.bus.ppu().frame_count != frame) {
+            StopReason::Condition => StopReason::FrameComplete,
+            reason => reason,
+        }
+    }
+}
+
+impl<M: Mem> CPU<M> {
+    pub fn with_bus(bus: M) -> Self {
         CPU{
             register_a: 0,                                                    //initialize the registers
             register_x: 0,
@@ -82,76 +311,33 @@ impl CPU {
             stack_pointer: STACK_RESET,                                                        //initialize the ccr
             program_counter: 0,                                               //initialize the program counter to point to memory addresses
             status: CpuFlags::from_bits_truncate(0b100100),
-            memory: [0; 0xFFFF]
+            config: CpuConfig::default(),
+            cycles: 0,
+            bus,
+            stop_requested: false,
+            pc_written: false,
         }
     }
 
 
-    fn get_operand_address(&self, mode: &AddressMode) -> u16{
-
-        match mode {
-            AddressMode::Immeditate => self.program_counter,                                    //For immeditate addressing we load in a value into a register (ie LDX #$01 loads $01 into X reg)
-
-            AddressMode::ZeroPage => self.mem_read(self.program_counter) as u16,          //For zero page addressing mode we load in the value at an address into a register (ie LDX $01 loads the value at address $01 into X reg)
-
-            AddressMode::Absolute => self.mem_read_u16(self.program_counter) as u16,       //For Absolute addressing mode we store an value at an entire 16bit memory location (ie STA $1234 stores the value in A at $1234)
-
-            //in zero page only first page of addresses are allowed (first 256 bytes have 3 cpu cycle retrieve time rather than 4-7)
-            //For zero page a zero page address is given and then the value of reg x is added to it 
-            AddressMode::ZeroPageX => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;        //wrapping add is used if sum is larger than single byte
-                addr
-            }
-
-            AddressMode::ZeroPageY => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
-            }
-
-            //Absolute version of zero page, uses full memory location rather than just zero page
-            AddressMode::AbsoluteX => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_x as u16);
-                addr
-            }
-
-            AddressMode::AbsoluteY => {
-                let base = self.mem_read_u16(self.program_counter);
-                let addr = base.wrapping_add(self.register_y as u16);
-                addr
-            }
-
-            //Indirect uses absolute address to look up another address, ie first address gives least sig byte of address and following gives most sig byte
-            AddressMode::IndirectX => {
-                let base = self.mem_read(self.program_counter);
-                let ptr: u8 = (base as u8).wrapping_add(self.register_x);
-                let lo = self.mem_read(ptr as u16);
-                let hi = self.mem_read(ptr.wrapping_add(1) as u16);
-                (hi as u16) << 8 | (lo as u16)
-            }
-
-            AddressMode::IndirectY => {
-                let base = self.mem_read(self.program_counter);
-                let lo = self.mem_read(base as u16);
-                let hi = self.mem_read((base as u8).wrapping_add(1) as u16);
-                let deref_base = (hi as u16) << 8 | (lo as u16);
-                let deref = deref_base.wrapping_add(self.register_y as u16);
-                deref
-            }
+    //returns the effective address and whether indexing crossed into a new page, reads pay an extra cycle for that
+    fn get_operand_address(&mut self, mode: &AddressMode) -> (u16, bool) {
+        let (operand, x, y) = (self.program_counter, self.register_x, self.register_y);
+        operand_address(mode, operand, x, y, |addr| self.mem_read(addr))
+    }
 
-            AddressMode::NoneAddress => {
-                panic!("Mode {:?} is not suppoeted", mode);
-            }
+    fn get_read_address(&mut self, mode: &AddressMode) -> u16 {               //instructions that only read their operand take a cycle longer when crossing a page
+        let (addr, page_cross) = self.get_operand_address(mode);
+        if page_cross {
+            self.tick(1);
         }
-
+        addr
     }
 
     /*
-    
+
         MEMORY COMMANDS
-    
+
     */
     pub fn load_and_run(&mut self, program: Vec<u8>){
         self.load(program);
@@ -160,11 +346,13 @@ impl CPU {
     }
 
     pub fn load(&mut self, program: Vec<u8>){
-        let end_address = 0x8000 + program.len();                        //This is to load the ROM into memory. In the NES System the program ROM 
-        self.memory[0x8000 .. end_address].copy_from_slice(&program[..]);  //program ROM Starts at address of 0x8000 and should end at however long the ROM is
-        self.program_counter = 0x8000;                                         //copy_from_slice will copy the program from the program file into memory
-        self.mem_write_u16(0xFFFC, 0x8000);
-    }                                                                          //side note: a slice is essentially a block of memory represented as a pointer and length of said block
+        assert!(program.len() <= 0x8000, "a {} byte program doesn't fit in $8000-$FFFF", program.len());
+        for (i, byte) in program.iter().enumerate() {                         //This is to load the ROM into memory. In the NES System the program ROM
+            self.mem_write(0x8000 + i as u16, *byte);                          //program ROM Starts at address of 0x8000 and should end at however long the ROM is
+        }
+        self.program_counter = 0x8000;
+        self.mem_write_u16(RESET_VECTOR, 0x8000);
+    }
 
     pub fn reset(&mut self) {
         self.register_a = 0;                                                    //initialize the registers
@@ -172,157 +360,1669 @@ impl CPU {
         self.register_y = 0;
         self.stack_pointer = STACK_RESET;
         self.status = CpuFlags::from_bits_truncate(0b100100);                  //Default state of CPU Flags                                                        //initialize the ccr
-        self.program_counter = self.mem_read_u16(0xFFFC);                 
+        self.program_counter = self.mem_read_u16(RESET_VECTOR);
+        self.tick(RESET_CYCLES);
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+        self.bus.tick(cycles);
+    }
+
+
+    /*
+        STACK
+        The stack lives on page one ($0100 - $01FF) and grows downward from the stack pointer
+
+    */
+    fn stack_push(&mut self, data: u8) {
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    fn stack_pop(&mut self) -> u8 {
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    fn stack_push_u16(&mut self, data: u16) {                                  //high byte is pushed first so the low byte ends up at the lower address
+        let hi = (data >> 8) as u8;
+        let lo = (data & 0xff) as u8;
+        self.stack_push(hi);
+        self.stack_push(lo);
     }
-    
+
+    fn stack_pop_u16(&mut self) -> u16 {
+        let lo = self.stack_pop() as u16;
+        let hi = self.stack_pop() as u16;
+        hi << 8 | lo
+    }
+
 
     /*
         INSTRUCTIONS
         Instructions are all from 6502 chip (http://www.6502.org/tutorials/6502opcodes.html)
-    
+
     */
     fn lda(&mut self, mode: &AddressMode){                                             //implementing the LDA instruction
-        let addr = self.get_operand_address(mode);
+        let addr = self.get_read_address(mode);
         let value = self.mem_read(addr);
 
-        self.register_a = value;                                              
+        self.register_a = value;
         self.change_zero_negative_flag(self.register_a);
     }
 
+    fn ldx(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_x = self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_x);
+    }
+
+    fn ldy(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_y = self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_y);
+    }
+
+    fn sta(&mut self, mode: &AddressMode){                                    //stores don't affect any flags
+        let (addr, _) = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a);
+    }
+
+    fn stx(&mut self, mode: &AddressMode){
+        let (addr, _) = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_x);
+    }
+
+    fn sty(&mut self, mode: &AddressMode){
+        let (addr, _) = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_y);
+    }
+
     fn tax(&mut self){                                                        //implementing the TAX instruction
         self.register_x = self.register_a;                                    //set the x reg to the a reg
         self.change_zero_negative_flag(self.register_x);              //TAX affects the Z and N bits
     }
-       
+
+    fn tay(&mut self){
+        self.register_y = self.register_a;
+        self.change_zero_negative_flag(self.register_y);
+    }
+
+    fn tsx(&mut self){
+        self.register_x = self.stack_pointer;
+        self.change_zero_negative_flag(self.register_x);
+    }
+
+    fn txa(&mut self){
+        self.register_a = self.register_x;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn txs(&mut self){                                                        //TXS is the only transfer that doesn't touch the flags
+        self.stack_pointer = self.register_x;
+    }
+
+    fn tya(&mut self){
+        self.register_a = self.register_y;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
     fn inx(&mut self){                                                        //implementing the INX instruction
         self.register_x = self.register_x.wrapping_add(1);                    //increment x and use wrapping add for overflow case ie: FF -> 00
         self.change_zero_negative_flag(self.register_x);              //inx affects the Z, and N bits
-    }   
-     
-     
-    /*
-        STATUS REGISTER CHANGES
-    
-    */                                                                        //set the ccr bits, LDA affects Z, N (C Z I D B V N)
-    fn change_zero_negative_flag(&mut self, result: u8){                      //generally if we are affecting the zero flag we are also affecting the negative flag so we can group them together
-        if result == 0 {
-            self.status.insert(CpuFlags::ZERO);                         //check to see if number is zero or not 
+    }
+
+    fn iny(&mut self){
+        self.register_y = self.register_y.wrapping_add(1);
+        self.change_zero_negative_flag(self.register_y);
+    }
+
+    fn dex(&mut self){
+        self.register_x = self.register_x.wrapping_sub(1);
+        self.change_zero_negative_flag(self.register_x);
+    }
+
+    fn dey(&mut self){
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.change_zero_negative_flag(self.register_y);
+    }
+
+    fn inc(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr).wrapping_add(1);
+        self.mem_write(addr, data);
+        self.change_zero_negative_flag(data);
+        data
+    }
+
+    fn dec(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr).wrapping_sub(1);
+        self.mem_write(addr, data);
+        self.change_zero_negative_flag(data);
+        data
+    }
+
+    fn add_to_register_a(&mut self, data: u8){                                //shared by ADC and SBC, decimal mode is ignored on the NES
+        let sum = self.register_a as u16
+            + data as u16
+            + (if self.status.contains(CpuFlags::CARRY) { 1 } else { 0 });
+
+        let carry = sum > 0xff;
+        if carry {
+            self.status.insert(CpuFlags::CARRY);
+        } else {
+            self.status.remove(CpuFlags::CARRY);
         }
-        else{
-            self.status.remove(CpuFlags::ZERO);
-        }      
 
-        if result & 0b1000_0000 != 0 {                                          //check to see if first bit is 1, meaning the number is negative if signed
-            self.status.insert(CpuFlags::NEGTAIVE);
+        let result = sum as u8;
+        if (data ^ result) & (result ^ self.register_a) & 0x80 != 0 {          //overflow is set when both inputs have the same sign and the result has a different one
+            self.status.insert(CpuFlags::OVERFLOW);
+        } else {
+            self.status.remove(CpuFlags::OVERFLOW);
         }
-        else{
-            self.status.remove(CpuFlags::NEGTAIVE);
+
+        self.register_a = result;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn adc(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        let value = self.mem_read(addr);
+        self.add_to_register_a(value);
+    }
+
+    fn sbc(&mut self, mode: &AddressMode){                                    //A - M - (1 - C) is the same as A + !M + C
+        let addr = self.get_read_address(mode);
+        let value = self.mem_read(addr);
+        self.add_to_register_a(!value);
+    }
+
+    fn and(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_a &= self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn eor(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_a ^= self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn ora(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_a |= self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn bit(&mut self, mode: &AddressMode){                                    //BIT copies bits 7 and 6 of memory into N and V, Z comes from A & M
+        let addr = self.get_read_address(mode);
+        let data = self.mem_read(addr);
+
+        if self.register_a & data == 0 {
+            self.status.insert(CpuFlags::ZERO);
+        } else {
+            self.status.remove(CpuFlags::ZERO);
         }
+
+        self.status.set(CpuFlags::NEGTAIVE, data & 0b1000_0000 > 0);
+        self.status.set(CpuFlags::OVERFLOW, data & 0b0100_0000 > 0);
     }
 
+    fn compare(&mut self, mode: &AddressMode, compare_with: u8){              //CMP, CPX and CPY all set C if reg >= M and Z, N from reg - M
+        let addr = self.get_read_address(mode);
+        let data = self.mem_read(addr);
+        self.compare_value(data, compare_with);
+    }
 
+    fn compare_value(&mut self, data: u8, compare_with: u8){
+        self.status.set(CpuFlags::CARRY, data <= compare_with);
+        self.change_zero_negative_flag(compare_with.wrapping_sub(data));
+    }
 
     /*
-        INTERPRET OP CODE
-    
+        SHIFTS AND ROTATES
+        The accumulator versions use NoneAddress and work directly on register A
+
     */
-    pub fn run(&mut self){
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+    fn asl_accumulator(&mut self){
+        let data = self.register_a;
+        self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+        self.register_a = data << 1;
+        self.change_zero_negative_flag(self.register_a);
+    }
 
-        loop {
-            let code = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-
-            let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
-            
-            match code {
-                //ADC, Add with Carry
-                0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
-                    self.adc()
-                }
+    fn asl(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+        let result = data << 1;
+        self.mem_write(addr, result);
+        self.change_zero_negative_flag(result);
+        result
+    }
 
-                0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
-                    self.lda(&opcode.mode);
-                }
-                _ => todo!(),
+    fn lsr_accumulator(&mut self){
+        let data = self.register_a;
+        self.status.set(CpuFlags::CARRY, data & 1 == 1);
+        self.register_a = data >> 1;
+        self.change_zero_negative_flag(self.register_a);
+    }
 
-            }
-        }
+    fn lsr(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.status.set(CpuFlags::CARRY, data & 1 == 1);
+        let result = data >> 1;
+        self.mem_write(addr, result);
+        self.change_zero_negative_flag(result);
+        result
     }
-    pub fn interpret(&mut self, program: Vec<u8>){
-        self.program_counter = 0;        
-        loop{
 
-            let opscode = program[self.program_counter as usize];               //gets the op code from where the program counter is pointing in memory
-            self.program_counter += 1;                                          //increase the program counter
+    fn rol_accumulator(&mut self){
+        let data = self.register_a;
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+        self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+        self.register_a = (data << 1) | old_carry as u8;
+        self.change_zero_negative_flag(self.register_a);
+    }
 
-            match opscode{
-                0x00 => {                                                       //0x00 is the op code for BRK  
-                    return;
-                }
+    fn rol(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+        self.status.set(CpuFlags::CARRY, data >> 7 == 1);
+        let result = (data << 1) | old_carry as u8;
+        self.mem_write(addr, result);
+        self.change_zero_negative_flag(result);
+        result
+    }
 
-                0xA9 => {                                                       //0xA9 is the op code for the LDA instruction
-                    let param = program[self.program_counter as usize];
-                    self.program_counter += 1;
-                    self.lda(param);                    
-                }
-                
-                0xAA => {                                                       //0xAA is the op code for TAX
-                    self.tax();
-                }
+    fn ror_accumulator(&mut self){
+        let data = self.register_a;
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+        self.status.set(CpuFlags::CARRY, data & 1 == 1);
+        self.register_a = (data >> 1) | ((old_carry as u8) << 7);
+        self.change_zero_negative_flag(self.register_a);
+    }
 
-                0xE8 => {                                                       //0xE8 is the op code for INX
-                    self.inx();
-                }
-                
-                _ => todo!(),
+    fn ror(&mut self, mode: &AddressMode) -> u8 {
+        let (addr, _) = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        let old_carry = self.status.contains(CpuFlags::CARRY);
+        self.status.set(CpuFlags::CARRY, data & 1 == 1);
+        let result = (data >> 1) | ((old_carry as u8) << 7);
+        self.mem_write(addr, result);
+        self.change_zero_negative_flag(result);
+        result
+    }
+
+    /*
+        STACK INSTRUCTIONS
+
+    */
+    fn pha(&mut self){
+        self.stack_push(self.register_a);
+    }
+
+    fn pla(&mut self){
+        self.register_a = self.stack_pop();
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn php(&mut self){                                                        //PHP always pushes the status with both break bits set
+        let mut flags = self.status;
+        flags.insert(CpuFlags::BREAK);
+        flags.insert(CpuFlags::BREAK2);
+        self.stack_push(flags.bits());
+    }
+
+    fn plp(&mut self){                                                        //the break bits don't exist in the real register so they are ignored when pulled
+        self.status = CpuFlags::from_bits_truncate(self.stack_pop());
+        self.status.remove(CpuFlags::BREAK);
+        self.status.insert(CpuFlags::BREAK2);
+    }
+
+    /*
+        JUMPS AND BRANCHES
+
+    */
+    fn branch(&mut self, condition: bool){                                    //branch offset is a signed byte relative to the next instruction
+        if condition {
+            self.tick(1);                                                     //+1 cycle when the branch is taken
+
+            let jump: i8 = self.mem_read(self.program_counter) as i8;
+            let next_instruction = self.program_counter.wrapping_add(1);
+            let jump_addr = next_instruction.wrapping_add(jump as u16);
+
+            if page_crossed(next_instruction, jump_addr) {                    //+1 more if it lands on a different page
+                self.tick(1);
             }
+
+            self.jump(jump_addr);
         }
-    
-}   
-}
+    }
 
+    fn jump(&mut self, addr: u16) {                                           //every instruction that changes the flow goes through here
+        self.program_counter = addr;
+        self.pc_written = true;
+    }
 
+    fn jmp_absolute(&mut self){
+        let addr = self.mem_read_u16(self.program_counter);
+        self.jump(addr);
+    }
 
-/*
-    TEST CASES
+    fn jmp_indirect(&mut self){
+        let addr = self.mem_read_u16(self.program_counter);
 
-*/
-#[cfg(test)]
-mod test{
-    use super::*;
+        //the 6502 never carries into the high byte when fetching the indirect vector
+        //so JMP ($30FF) reads the low byte from $30FF and the high byte from $3000
+        let indirect_ref = if addr & 0x00FF == 0x00FF {
+            let lo = self.mem_read(addr);
+            let hi = self.mem_read(addr & 0xFF00);
+            (hi as u16) << 8 | (lo as u16)
+        } else {
+            self.mem_read_u16(addr)
+        };
 
-    #[test]
-    fn test_lda() {
-        let mut cpu = CPU::new();
-        cpu.interpret(vec![0xa9, 0x05, 0x00]);
-        assert_eq!(cpu.register_a, 0x05);
-        assert!(cpu.status & 0b0000_0010 == 0b00);
-        assert!(cpu.status & 0b1000_0000 == 0);
+        self.jump(indirect_ref);
     }
 
-    #[test]
-    fn test_lda_ccr() {
-        let mut cpu = CPU::new();
-        cpu.interpret(vec![0xa9, 0x00, 0x00]);
-        assert!(cpu.status & 0b0000_0010 == 0b10);
+    fn jsr(&mut self){                                                        //JSR pushes the address of its last byte, RTS adds the 1 back
+        self.stack_push_u16(self.program_counter.wrapping_add(2 - 1));
+        let addr = self.mem_read_u16(self.program_counter);
+        self.jump(addr);
     }
 
-    #[test]
-    fn test_5_ops_working_together() {
-        let mut cpu = CPU::new();
-        cpu.interpret(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
-  
-        assert_eq!(cpu.register_x, 0xc1)
+    fn rts(&mut self){
+        let addr = self.stack_pop_u16().wrapping_add(1);
+        self.jump(addr);
+    }
+
+    /*
+        INTERRUPTS
+        https://www.nesdev.org/wiki/CPU_interrupts
+
+    */
+    fn interrupt(&mut self, interrupt: interrupt::Interrupt) {
+        self.stack_push_u16(self.program_counter);
+        let mut flag = self.status;
+        flag.remove(CpuFlags::BREAK);
+        flag.remove(CpuFlags::BREAK2);
+        let flag = CpuFlags::from_bits_truncate(flag.bits() | interrupt.b_flag_mask);
+        self.stack_push(flag.bits());
+
+        self.status.insert(CpuFlags::INTERRUPT_DISABLE);
+        self.tick(interrupt.cpu_cycles);
+        let addr = self.mem_read_u16(interrupt.vector_addr);
+        self.jump(addr);
+    }
+
+    fn poll_interrupts(&mut self) -> Option<InterruptType> {                  //NMI wins over IRQ, IRQ is ignored while I is set
+        if self.bus.poll_nmi() {
+            self.interrupt(interrupt::NMI);
+            return Some(InterruptType::Nmi);
+        }
+        if !self.status.contains(CpuFlags::INTERRUPT_DISABLE) && self.bus.irq_line() {
+            self.interrupt(interrupt::IRQ);
+            return Some(InterruptType::Irq);
+        }
+        None
+    }
+
+    fn rti(&mut self){
+        self.plp();
+        let addr = self.stack_pop_u16();
+        self.jump(addr);
+    }
+
+
+    /*
+        UNOFFICIAL INSTRUCTIONS
+        Most of these are two official instructions glued together (https://www.nesdev.org/wiki/CPU_unofficial_opcodes)
+
+    */
+    fn lax(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        let data = self.mem_read(addr);
+        self.register_a = data;
+        self.register_x = data;
+        self.change_zero_negative_flag(data);
+    }
+
+    fn sax(&mut self, mode: &AddressMode){
+        let (addr, _) = self.get_operand_address(mode);
+        self.mem_write(addr, self.register_a & self.register_x);
+    }
+
+    fn dcp(&mut self, mode: &AddressMode){
+        let data = self.dec(mode);
+        self.compare_value(data, self.register_a);
+    }
+
+    fn isb(&mut self, mode: &AddressMode){
+        let data = self.inc(mode);
+        self.add_to_register_a(!data);
+    }
+
+    fn slo(&mut self, mode: &AddressMode){
+        let data = self.asl(mode);
+        self.register_a |= data;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn rla(&mut self, mode: &AddressMode){
+        let data = self.rol(mode);
+        self.register_a &= data;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn sre(&mut self, mode: &AddressMode){
+        let data = self.lsr(mode);
+        self.register_a ^= data;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn rra(&mut self, mode: &AddressMode){
+        let data = self.ror(mode);
+        self.add_to_register_a(data);
+    }
+
+    fn anc(&mut self, mode: &AddressMode){                                    //AND then copy the negative bit into carry
+        self.and(mode);
+        self.status.set(CpuFlags::CARRY, self.status.contains(CpuFlags::NEGTAIVE));
+    }
+
+    fn alr(&mut self, mode: &AddressMode){
+        self.and(mode);
+        self.lsr_accumulator();
+    }
+
+    fn arr(&mut self, mode: &AddressMode){                                    //AND then ROR, but C and V come from bits 6 and 5 of the result
+        self.and(mode);
+        self.ror_accumulator();
+        let result = self.register_a;
+        let bit_6 = (result >> 6) & 1;
+        let bit_5 = (result >> 5) & 1;
+        self.status.set(CpuFlags::CARRY, bit_6 == 1);
+        self.status.set(CpuFlags::OVERFLOW, bit_6 ^ bit_5 == 1);
+    }
+
+    fn axs(&mut self, mode: &AddressMode){                                    //X = (A & X) - M without borrow, flags set like CMP
+        let addr = self.get_read_address(mode);
+        let data = self.mem_read(addr);
+        let and = self.register_a & self.register_x;
+        self.status.set(CpuFlags::CARRY, data <= and);
+        self.register_x = and.wrapping_sub(data);
+        self.change_zero_negative_flag(self.register_x);
+    }
+
+    fn xaa(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_a = self.register_x & self.mem_read(addr);
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn lxa(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        self.register_a = self.mem_read(addr);
+        self.register_x = self.register_a;
+        self.change_zero_negative_flag(self.register_a);
+    }
+
+    fn las(&mut self, mode: &AddressMode){
+        let addr = self.get_read_address(mode);
+        let data = self.mem_read(addr) & self.stack_pointer;
+        self.register_a = data;
+        self.register_x = data;
+        self.stack_pointer = data;
+        self.change_zero_negative_flag(data);
+    }
+
+    fn store_and_high(&mut self, mode: &AddressMode, data: u8){              //TAS, AHX, SHX and SHY AND the stored value with the high byte of the address + 1
+        let (addr, _) = self.get_operand_address(mode);
+        let high = ((addr >> 8) as u8).wrapping_add(1);
+        self.mem_write(addr, data & high);
+    }
+
+    fn nop_read(&mut self, mode: &AddressMode){                               //NOPs with an operand still read it, which matters for side effecting registers
+        let addr = self.get_read_address(mode);
+        self.mem_read(addr);
+    }
+
+
+    /*
+        STATUS REGISTER CHANGES
+
+    */                                                                        //set the ccr bits, LDA affects Z, N (C Z I D B V N)
+    fn change_zero_negative_flag(&mut self, result: u8){                      //generally if we are affecting the zero flag we are also affecting the negative flag so we can group them together
+        if result == 0 {
+            self.status.insert(CpuFlags::ZERO);                         //check to see if number is zero or not
+        }
+        else{
+            self.status.remove(CpuFlags::ZERO);
+        }
+
+        if result & 0b1000_0000 != 0 {                                          //check to see if first bit is 1, meaning the number is negative if signed
+            self.status.insert(CpuFlags::NEGTAIVE);
+        }
+        else{
+            self.status.remove(CpuFlags::NEGTAIVE);
+        }
+    }
+
+
+
+    /*
+        INTERPRET OP CODE
+
+    */
+    pub fn run(&mut self){
+        self.run_with_callback(|_| {});
+    }
+
+    pub fn run_with_callback<F>(&mut self, callback: F)                       //the callback sees the CPU before every instruction, e.g. for tracing
+    where
+        F: FnMut(&mut CPU<M>),
+    {
+        if let Err(err) = self.run_checked_with_callback(callback) {
+            panic!("{}", err);
+        }
+    }
+
+    pub fn run_checked(&mut self) -> Result<(), CpuError> {                  //same as run but hands back traps and jams instead of panicking
+        self.run_checked_with_callback(|_| {})
+    }
+
+    pub fn run_checked_with_callback<F>(&mut self, mut callback: F) -> Result<(), CpuError>
+    where
+        F: FnMut(&mut CPU<M>),
+    {
+        loop {
+            if self.take_stop_request() {
+                return Ok(());
+            }
+            self.poll_interrupts();
+            callback(self);
+            if let Err(reason) = self.execute() {
+                return reason.into_result();
+            }
+        }
+    }
+
+    /*
+        STEPPING
+        step runs exactly one instruction (plus any interrupt taken before it) and hands back
+        the cycles it used, the run_* helpers are built on top of it and always return why they stopped
+
+    */
+    pub fn step(&mut self) -> Result<u64, StopReason> {
+        let start = self.cycles;
+        self.poll_interrupts();
+        self.execute()?;
+        Ok(self.cycles - start)
+    }
+
+    pub fn run_until<F>(&mut self, mut predicate: F) -> StopReason           //the predicate is checked before every instruction
+    where
+        F: FnMut(&mut CPU<M>) -> bool,
+    {
+        loop {
+            if self.take_stop_request() {
+                return StopReason::HostRequest;
+            }
+            if predicate(self) {
+                return StopReason::Condition;
+            }
+            if let Err(reason) = self.step() {
+                return reason;
+            }
+        }
+    }
+
+    pub fn run_cycles(&mut self, cycles: u64) -> StopReason {                //may overshoot by the rest of the last instruction
+        let target = self.cycles + cycles;
+        match self.run_until(|cpu| cpu.cycles >= target) {
+            StopReason::Condition => StopReason::CyclesElapsed,
+            reason => reason,
+        }
+    }
+
+    pub fn request_stop(&mut self) {                                          //run loops return StopReason::HostRequest before the next instruction
+        self.stop_requested = true;
+    }
+
+    fn take_stop_request(&mut self) -> bool {
+        std::mem::replace(&mut self.stop_requested, false)
+    }
+
+    fn execute(&mut self) -> Result<(), StopReason> {                         //fetch, decode and run the instruction at the program counter
+        let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::OPCODES_MAP;
+        let code = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+        self.pc_written = false;
+
+        let opcode = opcodes.get(&code).unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+
+        if opcode.is_unofficial() {
+            match self.config.illegal_opcodes {
+                IllegalOpcodeMode::Execute => {}
+                IllegalOpcodeMode::Trap => {
+                    self.program_counter = self.program_counter.wrapping_sub(1);  //leave the PC on the offending opcode
+                    return Err(StopReason::IllegalOpcode { code, address: self.program_counter });
+                }
+                IllegalOpcodeMode::Nop => {
+                    self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
+                    self.tick(opcode.cycles);
+                    return Ok(());
+                }
+            }
+        }
+
+        match code {
+            //ADC, Add with Carry
+            0x69 | 0x65 | 0x75 | 0x6d | 0x7d | 0x79 | 0x61 | 0x71 => {
+                self.adc(&opcode.mode);
+            }
+
+            //SBC, Subtract with Carry
+            0xe9 | 0xe5 | 0xf5 | 0xed | 0xfd | 0xf9 | 0xe1 | 0xf1 => {
+                self.sbc(&opcode.mode);
+            }
+
+            //AND, Logical AND
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&opcode.mode);
+            }
+
+            //EOR, Exclusive OR
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&opcode.mode);
+            }
+
+            //ORA, Logical Inclusive OR
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&opcode.mode);
+            }
+
+            //ASL, LSR, ROL, ROR on the accumulator
+            0x0a => self.asl_accumulator(),
+            0x4a => self.lsr_accumulator(),
+            0x2a => self.rol_accumulator(),
+            0x6a => self.ror_accumulator(),
+
+            //ASL, LSR, ROL, ROR on memory
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.asl(&opcode.mode);
+            }
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode);
+            }
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.rol(&opcode.mode);
+            }
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.ror(&opcode.mode);
+            }
+
+            //INC, DEC on memory
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode);
+            }
+            0xc6 | 0xd6 | 0xce | 0xde => {
+                self.dec(&opcode.mode);
+            }
+
+            //INX, INY, DEX, DEY
+            0xe8 => self.inx(),
+            0xc8 => self.iny(),
+            0xca => self.dex(),
+            0x88 => self.dey(),
+
+            //CMP, CPX, CPY
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(&opcode.mode, self.register_a);
+            }
+            0xe0 | 0xe4 | 0xec => {
+                self.compare(&opcode.mode, self.register_x);
+            }
+            0xc0 | 0xc4 | 0xcc => {
+                self.compare(&opcode.mode, self.register_y);
+            }
+
+            //BIT, Bit Test
+            0x24 | 0x2c => {
+                self.bit(&opcode.mode);
+            }
+
+            //Branches
+            0x90 => self.branch(!self.status.contains(CpuFlags::CARRY)),           //BCC
+            0xb0 => self.branch(self.status.contains(CpuFlags::CARRY)),            //BCS
+            0xf0 => self.branch(self.status.contains(CpuFlags::ZERO)),             //BEQ
+            0xd0 => self.branch(!self.status.contains(CpuFlags::ZERO)),            //BNE
+            0x30 => self.branch(self.status.contains(CpuFlags::NEGTAIVE)),         //BMI
+            0x10 => self.branch(!self.status.contains(CpuFlags::NEGTAIVE)),        //BPL
+            0x70 => self.branch(self.status.contains(CpuFlags::OVERFLOW)),         //BVS
+            0x50 => self.branch(!self.status.contains(CpuFlags::OVERFLOW)),        //BVC
+
+            //JMP, JSR, RTS, RTI
+            0x4c => self.jmp_absolute(),
+            0x6c => self.jmp_indirect(),
+            0x20 => self.jsr(),
+            0x60 => self.rts(),
+            0x40 => self.rti(),
+
+            //Flag changes
+            0x18 => self.status.remove(CpuFlags::CARRY),                           //CLC
+            0x38 => self.status.insert(CpuFlags::CARRY),                           //SEC
+            0xd8 => self.status.remove(CpuFlags::DECIMAL_MODE),                    //CLD
+            0xf8 => self.status.insert(CpuFlags::DECIMAL_MODE),                    //SED
+            0x58 => self.status.remove(CpuFlags::INTERRUPT_DISABLE),               //CLI
+            0x78 => self.status.insert(CpuFlags::INTERRUPT_DISABLE),               //SEI
+            0xb8 => self.status.remove(CpuFlags::OVERFLOW),                        //CLV
+
+            //LDA, LDX, LDY
+            0xa9 | 0xa5 | 0xb5 | 0xad | 0xbd | 0xb9 | 0xa1 | 0xb1 => {
+                self.lda(&opcode.mode);
+            }
+            0xa2 | 0xa6 | 0xb6 | 0xae | 0xbe => {
+                self.ldx(&opcode.mode);
+            }
+            0xa0 | 0xa4 | 0xb4 | 0xac | 0xbc => {
+                self.ldy(&opcode.mode);
+            }
+
+            //STA, STX, STY
+            0x85 | 0x95 | 0x8d | 0x9d | 0x99 | 0x81 | 0x91 => {
+                self.sta(&opcode.mode);
+            }
+            0x86 | 0x96 | 0x8e => {
+                self.stx(&opcode.mode);
+            }
+            0x84 | 0x94 | 0x8c => {
+                self.sty(&opcode.mode);
+            }
+
+            //Transfers
+            0xaa => self.tax(),
+            0xa8 => self.tay(),
+            0xba => self.tsx(),
+            0x8a => self.txa(),
+            0x9a => self.txs(),
+            0x98 => self.tya(),
+
+            //PHA, PLA, PHP, PLP
+            0x48 => self.pha(),
+            0x68 => self.pla(),
+            0x08 => self.php(),
+            0x28 => self.plp(),
+
+            //NOP
+            0xea => {}
+
+            //BRK
+            0x00 => {
+                if self.config.halt_on_brk {
+                    self.tick(opcode.cycles);
+                    return Err(StopReason::Brk { address: self.program_counter.wrapping_sub(1) });
+                }
+                self.program_counter = self.program_counter.wrapping_add(1);   //BRK skips a padding byte so the handler returns past it
+                self.interrupt(interrupt::BRK);
+                return Ok(());
+            }
+
+            /*
+                UNOFFICIAL OPCODES
+
+            */
+            //*NOP with an operand
+            0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04 | 0x44 | 0x64 | 0x14 | 0x34 | 0x54 | 0x74
+            | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {
+                self.nop_read(&opcode.mode);
+            }
+
+            //*NOP, single byte
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa => {}
+
+            0xa7 | 0xb7 | 0xaf | 0xbf | 0xa3 | 0xb3 => {
+                self.lax(&opcode.mode);
+            }
+            0x87 | 0x97 | 0x8f | 0x83 => {
+                self.sax(&opcode.mode);
+            }
+            0xeb => {
+                self.sbc(&opcode.mode);
+            }
+            0xc7 | 0xd7 | 0xcf | 0xdf | 0xdb | 0xc3 | 0xd3 => {
+                self.dcp(&opcode.mode);
+            }
+            0xe7 | 0xf7 | 0xef | 0xff | 0xfb | 0xe3 | 0xf3 => {
+                self.isb(&opcode.mode);
+            }
+            0x07 | 0x17 | 0x0f | 0x1f | 0x1b | 0x03 | 0x13 => {
+                self.slo(&opcode.mode);
+            }
+            0x27 | 0x37 | 0x2f | 0x3f | 0x3b | 0x23 | 0x33 => {
+                self.rla(&opcode.mode);
+            }
+            0x47 | 0x57 | 0x4f | 0x5f | 0x5b | 0x43 | 0x53 => {
+                self.sre(&opcode.mode);
+            }
+            0x67 | 0x77 | 0x6f | 0x7f | 0x7b | 0x63 | 0x73 => {
+                self.rra(&opcode.mode);
+            }
+            0x0b | 0x2b => self.anc(&opcode.mode),
+            0x4b => self.alr(&opcode.mode),
+            0x6b => self.arr(&opcode.mode),
+            0xcb => self.axs(&opcode.mode),
+            0x8b => self.xaa(&opcode.mode),
+            0xab => self.lxa(&opcode.mode),
+            0xbb => self.las(&opcode.mode),
+            0x9b => {                                                            //*TAS
+                self.stack_pointer = self.register_a & self.register_x;
+                self.store_and_high(&opcode.mode, self.stack_pointer);
+            }
+            0x9f | 0x93 => {                                                     //*AHX
+                self.store_and_high(&opcode.mode, self.register_a & self.register_x);
+            }
+            0x9c => self.store_and_high(&opcode.mode, self.register_y),        //*SHY
+            0x9e => self.store_and_high(&opcode.mode, self.register_x),        //*SHX
+
+            //*JAM
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                self.program_counter = self.program_counter.wrapping_sub(1);
+                return Err(StopReason::Jammed { code, address: self.program_counter });
+            }
+
+        }
+
+        self.tick(opcode.cycles);                                              //base cycles, page cross and branch penalties were added while executing
+        for _ in 0..self.bus.take_stall_cycles() {
+            self.tick(1);
+        }
+
+        if !self.pc_written {                                                  //jumps and taken branches set the program counter themselves
+            self.program_counter = self.program_counter.wrapping_add((opcode.len - 1) as u16);
+        }
+
+        Ok(())
+    }
+}
+
+
+impl<M: Mem> Snapshot for CPU<M> {                                           //only the registers, the bus saves itself and the config belongs to the frontend
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.register_a);
+        w.u8(self.register_x);
+        w.u8(self.register_y);
+        w.u8(self.status.bits());
+        w.u16(self.program_counter);
+        w.u8(self.stack_pointer);
+        w.u64(self.cycles);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.register_a = r.u8()?;
+        self.register_x = r.u8()?;
+        self.register_y = r.u8()?;
+        self.status = CpuFlags::from_bits_truncate(r.u8()?);
+        self.program_counter = r.u16()?;
+        self.stack_pointer = r.u8()?;
+        self.cycles = r.u64()?;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test{
+    use super::*;
+
+    fn test_cpu() -> CPU {                                                    //the test programs end in a BRK that stops them rather than interrupting
+        let mut cpu = CPU::new();
+        cpu.config.halt_on_brk = true;
+        cpu
+    }
+
+    fn flat_cpu() -> CPU<FlatMemory> {
+        let mut cpu = CPU::with_bus(FlatMemory { memory: [0; 0x10000] });
+        cpu.config.halt_on_brk = true;
+        cpu
+    }
+
+    fn run_with(program: Vec<u8>, setup: impl FnOnce(&mut CPU)) -> CPU {     //load a program, let the test tweak the state after reset, then run it
+        let mut cpu = test_cpu();
+        cpu.load(program);
+        cpu.reset();
+        setup(&mut cpu);
+        cpu.run();
+        cpu
+    }
+
+    #[test]
+    fn test_lda() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x05, 0x00]);
+        assert_eq!(cpu.register_a, 0x05);
+        assert!(cpu.status.bits() & 0b0000_0010 == 0b00);
+        assert!(cpu.status.bits() & 0b1000_0000 == 0);
+    }
+
+    #[test]
+    fn test_lda_ccr() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x00, 0x00]);
+        assert!(cpu.status.bits() & 0b0000_0010 == 0b10);
+    }
+
+    #[test]
+    fn test_5_ops_working_together() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+
+        assert_eq!(cpu.register_x, 0xc1)
+    }
+
+    #[test]
+    fn test_assembled_program() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(crate::asm!(
+            "        LDX #0",
+            "loop:   TXA",
+            "        STA $0200,X",
+            "        INX",
+            "        CPX #8",
+            "        BNE loop",
+            "        BRK",
+        ));
+        assert_eq!(cpu.register_x, 8);
+        assert_eq!(cpu.mem_read(0x0207), 7);
+    }
+
+     #[test]
+     fn test_inx_overflow() {
+         let cpu = run_with(vec![0xe8, 0xe8, 0x00], |cpu| cpu.register_x = 0xff);
+
+         assert_eq!(cpu.register_x, 1)
+     }
+
+    #[test]
+    fn test_lda_from_memory() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0x55);
+        cpu.load_and_run(vec![0xa5, 0x10, 0x00]);
+        assert_eq!(cpu.register_a, 0x55);
+    }
+
+    #[test]
+    fn test_ldx_ldy() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa2, 0x80, 0xa0, 0x00, 0x00]);
+        assert_eq!(cpu.register_x, 0x80);
+        assert_eq!(cpu.register_y, 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_sta_stx_sty() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![
+            0xa9, 0x11, 0x85, 0x10,                                          //LDA #$11, STA $10
+            0xa2, 0x22, 0x8e, 0x00, 0x02,                                    //LDX #$22, STX $0200
+            0xa0, 0x33, 0x94, 0x01,                                          //LDY #$33, STY $01,X
+            0x00,
+        ]);
+        assert_eq!(cpu.mem_read(0x10), 0x11);
+        assert_eq!(cpu.mem_read(0x0200), 0x22);
+        assert_eq!(cpu.mem_read(0x23), 0x33);
+    }
+
+    #[test]
+    fn test_indirect_addressing() {
+        let mut cpu = test_cpu();
+        cpu.mem_write_u16(0x20, 0x0300);
+        cpu.mem_write(0x0305, 0x77);
+        cpu.load_and_run(vec![0xa0, 0x05, 0xa2, 0x05, 0xb1, 0x20, 0x81, 0x1b, 0x00]);  //LDY #$05, LDX #$05, LDA ($20),Y, STA ($1B,X)
+        assert_eq!(cpu.register_a, 0x77);
+        assert_eq!(cpu.mem_read(0x0300), 0x77);
+    }
+
+    #[test]
+    fn test_transfers() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x42, 0xa8, 0xaa, 0xa9, 0x00, 0x98, 0x00]);  //LDA, TAY, TAX, LDA #0, TYA
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.register_x, 0x42);
+        assert_eq!(cpu.register_y, 0x42);
+
+        cpu.load_and_run(vec![0xa2, 0x80, 0x8a, 0x00]);                    //LDX #$80, TXA
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(CpuFlags::NEGTAIVE));
+    }
+
+    #[test]
+    fn test_tsx_txs() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xba, 0xa2, 0x40, 0x9a, 0x00]);              //TSX, LDX #$40, TXS
+        assert_eq!(cpu.stack_pointer, 0x40);
+
+        cpu.load_and_run(vec![0xba, 0x00]);
+        assert_eq!(cpu.register_x, STACK_RESET);
+    }
+
+    #[test]
+    fn test_adc() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x50, 0x69, 0x50, 0x00]);
+        assert_eq!(cpu.register_a, 0xa0);
+        assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0xa9, 0xff, 0x69, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x00);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_adc_with_carry_in() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x38, 0xa9, 0x10, 0x69, 0x01, 0x00]);        //SEC, LDA #$10, ADC #$01
+        assert_eq!(cpu.register_a, 0x12);
+    }
+
+    #[test]
+    fn test_sbc() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x38, 0xa9, 0x05, 0xe9, 0x03, 0x00]);        //SEC, LDA #5, SBC #3
+        assert_eq!(cpu.register_a, 0x02);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0x38, 0xa9, 0x03, 0xe9, 0x05, 0x00]);
+        assert_eq!(cpu.register_a, 0xfe);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::NEGTAIVE));
+
+        cpu.load_and_run(vec![0x38, 0xa9, 0x80, 0xe9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x7f);
+        assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_logic_ops() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0b1100, 0x29, 0b1010, 0x00]);          //AND
+        assert_eq!(cpu.register_a, 0b1000);
+
+        cpu.load_and_run(vec![0xa9, 0b1100, 0x09, 0b1010, 0x00]);          //ORA
+        assert_eq!(cpu.register_a, 0b1110);
+
+        cpu.load_and_run(vec![0xa9, 0b1100, 0x49, 0b1100, 0x00]);          //EOR
+        assert_eq!(cpu.register_a, 0);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_asl_and_lsr() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x81, 0x0a, 0x00]);
+        assert_eq!(cpu.register_a, 0x02);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0xa9, 0x03, 0x4a, 0x00]);
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x40);
+        cpu.load_and_run(vec![0x06, 0x10, 0x46, 0x11, 0x00]);
+        assert_eq!(cpu.mem_read(0x10), 0x80);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_rol_and_ror() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x38, 0xa9, 0x80, 0x2a, 0x00]);              //SEC, LDA #$80, ROL A
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0x38, 0xa9, 0x01, 0x6a, 0x00]);              //SEC, LDA #$01, ROR A
+        assert_eq!(cpu.register_a, 0x80);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x81);
+        cpu.load_and_run(vec![0x26, 0x10, 0x66, 0x10, 0x66, 0x10, 0x00]);   //ROL $10, ROR $10, ROR $10
+        assert_eq!(cpu.mem_read(0x10), 0x40);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_inc_dec() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0xff);
+        cpu.mem_write(0x11, 0x01);
+        cpu.load_and_run(vec![0xe6, 0x10, 0xc6, 0x11, 0x00]);
+        assert_eq!(cpu.mem_read(0x10), 0x00);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+
+        cpu.load_and_run(vec![0x88, 0xca, 0xc8, 0xc8, 0x00]);              //DEY, DEX, INY, INY
+        assert_eq!(cpu.register_x, 0xff);
+        assert_eq!(cpu.register_y, 0x01);
+    }
+
+    #[test]
+    fn test_compare() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x10, 0xc9, 0x10, 0x00]);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0xa2, 0x05, 0xe0, 0x06, 0x00]);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::NEGTAIVE));
+
+        cpu.load_and_run(vec![0xa0, 0x07, 0xc0, 0x06, 0x00]);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::ZERO));
+    }
+
+    #[test]
+    fn test_bit() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0xc0);
+        cpu.load_and_run(vec![0xa9, 0x01, 0x24, 0x10, 0x00]);
+        assert!(cpu.status.contains(CpuFlags::ZERO));
+        assert!(cpu.status.contains(CpuFlags::NEGTAIVE));
+        assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_branch_loop() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![
+            0xa2, 0x08,                                                      //LDX #$08
+            0xca,                                                            //loop: DEX
+            0xc8,                                                            //INY
+            0xe0, 0x03,                                                      //CPX #$03
+            0xd0, 0xfa,                                                      //BNE loop
+            0x00,
+        ]);
+        assert_eq!(cpu.register_x, 3);
+        assert_eq!(cpu.register_y, 5);
+    }
+
+    #[test]
+    fn test_branches_on_flags() {
+        //each branch skips over an LDA #$ff when taken, so A stays 0
+        let programs: Vec<Vec<u8>> = vec![
+            vec![0x18, 0x90, 0x02, 0xa9, 0xff, 0x00],                         //CLC, BCC
+            vec![0x38, 0xb0, 0x02, 0xa9, 0xff, 0x00],                         //SEC, BCS
+            vec![0xb8, 0x50, 0x02, 0xa9, 0xff, 0x00],                         //CLV, BVC
+        ];
+        for program in programs {
+            let mut cpu = test_cpu();
+            cpu.load_and_run(program);
+            assert_eq!(cpu.register_a, 0);
+        }
+
+        let cpu = run_with(vec![0x70, 0x02, 0xa9, 0xff, 0x00], |cpu| cpu.status.insert(CpuFlags::OVERFLOW));
+        assert_eq!(cpu.register_a, 0);
+
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa2, 0x00, 0xf0, 0x02, 0xa9, 0xff, 0x00]);   //BEQ taken
+        assert_eq!(cpu.register_a, 0);
+        cpu.load_and_run(vec![0xa2, 0x80, 0x30, 0x02, 0xa9, 0xff, 0x00]);   //BMI taken
+        assert_eq!(cpu.register_a, 0);
+        cpu.load_and_run(vec![0xa2, 0x01, 0x10, 0x02, 0xa9, 0xff, 0x00]);   //BPL taken
+        assert_eq!(cpu.register_a, 0);
+        cpu.load_and_run(vec![0xa2, 0x80, 0x10, 0x02, 0xa9, 0xff, 0x00]);   //BPL not taken
+        assert_eq!(cpu.register_a, 0xff);
+    }
+
+    #[test]
+    fn test_jmp_absolute_and_indirect() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x4c, 0x05, 0x80, 0xa9, 0xff, 0xa9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x01);
+
+        cpu.mem_write(0x02ff, 0x06);                                         //indirect vector crosses a page and wraps to $0200
+        cpu.mem_write(0x0200, 0x80);
+        cpu.load_and_run(vec![0x6c, 0xff, 0x02, 0xa9, 0xff, 0x00, 0xa9, 0x02, 0x00]);
+        assert_eq!(cpu.register_a, 0x02);
+    }
+
+    #[test]
+    fn test_jsr_rts() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![
+            0x20, 0x05, 0x80,                                                //JSR sub
+            0xe8,                                                            //INX
+            0x00,
+            0xa2, 0x10,                                                      //sub: LDX #$10
+            0x60,                                                            //RTS
+        ]);
+        assert_eq!(cpu.register_x, 0x11);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+    }
+
+    #[test]
+    fn test_stack_ops() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x33, 0x48, 0xa9, 0x00, 0x68, 0x00]);  //LDA, PHA, LDA #0, PLA
+        assert_eq!(cpu.register_a, 0x33);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+
+        cpu.load_and_run(vec![0x38, 0x08, 0x18, 0x28, 0x00]);              //SEC, PHP, CLC, PLP
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::BREAK));
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16) & 0b0011_0000, 0b0011_0000);
+    }
+
+    #[test]
+    fn test_rti() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![
+            0xa9, 0x80, 0x48,                                                //push return address $800a
+            0xa9, 0x0a, 0x48,
+            0xa9, 0xc3, 0x48,                                                //push status
+            0x40,                                                            //RTI
+            0x00,
+        ]);
+        assert_eq!(cpu.program_counter, 0x800b);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::NEGTAIVE));
+        assert!(cpu.status.contains(CpuFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_flag_ops() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x38, 0xf8, 0x78, 0x00]);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::DECIMAL_MODE | CpuFlags::INTERRUPT_DISABLE));
+
+        cpu.load_and_run(vec![0x38, 0x18, 0xf8, 0xd8, 0x58, 0xa9, 0x50, 0x69, 0x50, 0xb8, 0xea, 0x00]);
+        assert!(!cpu.status.contains(CpuFlags::CARRY));
+        assert!(!cpu.status.contains(CpuFlags::DECIMAL_MODE));
+        assert!(!cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));
+        assert!(!cpu.status.contains(CpuFlags::OVERFLOW));
+    }
+
+    #[test]
+    fn test_opcode_table_is_complete() {
+        let mnemonics: std::collections::HashSet<&str> =
+            opcodes::CPU_OPS_CODES.iter().map(|op| op.mnemonic).collect();
+        assert_eq!(mnemonics.iter().filter(|m| !m.starts_with('*')).count(), 56);
+        assert_eq!(opcodes::OPCODES_MAP.len(), opcodes::CPU_OPS_CODES.len());
+        assert_eq!(opcodes::OPCODES_MAP.len(), 256);
+    }
+
+    #[test]
+    fn test_every_official_opcode_executes() {
+        let mut executed = 0;
+        for opcode in opcodes::CPU_OPS_CODES.iter().filter(|op| !op.is_unofficial()) {
+            let mut cpu = flat_cpu();
+            cpu.config.illegal_opcodes = IllegalOpcodeMode::Trap;
+            cpu.bus.memory[0x0400..0x0403].copy_from_slice(&[opcode.code, 0x10, 0x02]);
+            cpu.program_counter = 0x0400;
+            match cpu.step() {
+                Ok(cycles) => assert!(cycles >= opcode.cycles as u64, "{:02X}", opcode.code),
+                Err(StopReason::Brk { address: 0x0400 }) if opcode.code == 0x00 => {}
+                Err(reason) => panic!("{:02X} {} stopped: {:?}", opcode.code, opcode.mnemonic, reason),
+            }
+            executed += 1;
+        }
+        assert_eq!(executed, 151);
+    }
+
+    #[test]
+    fn test_jump_to_the_byte_after_the_opcode() {
+        let mut cpu = flat_cpu();
+        cpu.bus.memory[0x8000..0x8003].copy_from_slice(&[0x4c, 0x01, 0x80]);   //JMP $8001
+        cpu.program_counter = 0x8000;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8001);
+
+        cpu.bus.memory[0x8000..0x8002].copy_from_slice(&[0xd0, 0xff]);        //BNE back onto its own operand
+        cpu.program_counter = 0x8000;
+        cpu.status.remove(CpuFlags::ZERO);
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x8001);
+    }
+
+    #[test]
+    fn test_program_counter_wraps_at_the_top_of_memory() {
+        let mut cpu = flat_cpu();
+        cpu.bus.memory[0xFFFF] = 0xea;                                        //NOP
+        cpu.program_counter = 0xFFFF;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0000);
+
+        cpu.bus.memory[0xFFFE] = 0x20;                                        //JSR $0300, the high byte comes from $0000
+        cpu.bus.memory[0xFFFF] = 0x00;
+        cpu.bus.memory[0x0000] = 0x03;
+        cpu.program_counter = 0xFFFE;
+        cpu.step().unwrap();
+        assert_eq!(cpu.program_counter, 0x0300);
+        assert_eq!(cpu.stack_pop_u16(), 0x0000);                              //the address of the JSR's last byte
+    }
+
+    struct FlatMemory {                                                       //a bare 64KiB backend with no mirroring at all
+        memory: [u8; 0x10000],
+    }
+
+    impl Mem for FlatMemory {
+        fn mem_read(&mut self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+        fn mem_write(&mut self, addr: u16, data: u8) {
+            self.memory[addr as usize] = data;
+        }
+        fn peek(&self, addr: u16) -> u8 {
+            self.memory[addr as usize]
+        }
+    }
+
+    #[test]
+    fn test_custom_memory_backend() {
+        let mut cpu = flat_cpu();
+        cpu.load_and_run(vec![0xa9, 0x42, 0x8d, 0x00, 0x08, 0x00]);        //LDA #$42, STA $0800
+        assert_eq!(cpu.bus.memory[0x0800], 0x42);
+        assert_eq!(cpu.bus.memory[0x0000], 0x00);
+    }
+
+    #[test]
+    fn test_load_fills_all_32k() {
+        let mut cpu = flat_cpu();
+        cpu.load(vec![0xea; 0x8000]);
+        assert_eq!(cpu.bus.memory[0xFFFF], 0xea);
+        assert_eq!(cpu.mem_read_u16(0xFFFC), 0x8000);                         //the reset vector still points at the program
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit")]
+    fn test_load_rejects_programs_over_32k() {
+        test_cpu().load(vec![0xea; 0x8001]);
+    }
+
+    #[test]
+    fn test_ram_is_mirrored_through_bus() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x42, 0x8d, 0x00, 0x08, 0x00]);
+        assert_eq!(cpu.mem_read(0x0000), 0x42);
+    }
+
+    #[test]
+    fn test_cycles_simple() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0x05, 0xaa, 0x85, 0x10, 0x00]);        //LDA #imm 2, TAX 2, STA zp 3, BRK 7
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 2 + 2 + 3 + 7);
+    }
+
+    #[test]
+    fn test_cycles_page_cross_on_reads() {
+        let cpu = run_with(vec![0xbd, 0xff, 0x02, 0xbd, 0x00, 0x02, 0x00], |cpu| cpu.register_x = 1);
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 5 + 4 + 7);              //LDA $02ff,X crosses, LDA $0200,X doesn't
+
+        let cpu = run_with(vec![0x9d, 0xff, 0x02, 0x00], |cpu| cpu.register_x = 1);
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 5 + 7);                  //stores always take their listed time
+
+        let mut cpu = test_cpu();
+        cpu.mem_write_u16(0x10, 0x02ff);
+        cpu.load_and_run(vec![0xa0, 0x01, 0xb1, 0x10, 0x00]);              //LDY #1, LDA ($10),Y crosses
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 2 + 6 + 7);
+    }
+
+    #[test]
+    fn test_cycles_branches() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x18, 0xb0, 0x00, 0x90, 0x00, 0x00]);        //CLC 2, BCS not taken 2, BCC taken 3
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 2 + 2 + 3 + 7);
+
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x18, 0x90, 0xfc, 0x00]);                    //BCC -4 lands on the BRK at $7fff, a page back
+        assert_eq!(cpu.program_counter, 0x8000);
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 2 + 4 + 7);
+    }
+
+    fn interrupt_test_cpu() -> CPU {                                          //handlers at $9000 (NMI) and $9100 (IRQ/BRK) that just INX and RTI
+        let mut cpu = CPU::new();
+        cpu.mem_write_u16(0xfffa, 0x9000);
+        cpu.mem_write_u16(0xfffe, 0x9100);
+        cpu.mem_write(0x9000, 0xe8);
+        cpu.mem_write(0x9001, 0x40);
+        cpu.mem_write(0x9100, 0xc8);
+        cpu.mem_write(0x9101, 0x40);
+        cpu
+    }
+
+    #[test]
+    fn test_nmi() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.load(vec![0xa9, 0x01, 0x02]);                                    //LDA #1 then JAM to stop
+        cpu.reset();
+        cpu.status.insert(CpuFlags::CARRY);
+        cpu.bus.set_nmi_line(true);
+        assert!(cpu.run_checked().is_err());
+
+        assert_eq!(cpu.register_x, 1);                                       //NMI ignores the I flag
+        assert_eq!(cpu.register_a, 1);
+        assert_eq!(cpu.stack_pointer, STACK_RESET);
+        let pushed_status = cpu.mem_read(STACK + STACK_RESET as u16 - 2);
+        assert_eq!(pushed_status & 0b0011_0000, 0b0010_0000);               //B clear for hardware interrupts
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+        assert!(cpu.status.contains(CpuFlags::INTERRUPT_DISABLE));           //restored by RTI
+    }
+
+    #[test]
+    fn test_irq_respects_interrupt_disable() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.load(vec![0xe8, 0x58, 0xe8, 0x02]);                              //INX, CLI, INX, JAM
+        cpu.reset();
+        cpu.mem_write(0x9101, 0x02);                                         //IRQ handler: INY, JAM
+        cpu.bus.set_irq(crate::bus::IrqSource::EXTERNAL, true);
+        assert_eq!(cpu.run_checked(), Err(CpuError::Jammed { code: 0x02, address: 0x9101 }));
+
+        assert_eq!(cpu.register_x, 1);                                       //held off by I until CLI
+        assert_eq!(cpu.register_y, 1);
+        assert_eq!(cpu.mem_read_u16(STACK + STACK_RESET as u16 - 1), 0x8002);
+    }
+
+    #[test]
+    fn test_brk_interrupt() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.load(vec![0x00, 0xff, 0xe8, 0x02]);                              //BRK, padding, INX, JAM
+        cpu.reset();
+        assert!(cpu.run_checked().is_err());
+
+        assert_eq!(cpu.register_y, 1);
+        assert_eq!(cpu.register_x, 1);                                       //RTI came back after the padding byte
+        assert_eq!(cpu.mem_read(STACK + STACK_RESET as u16 - 2) & 0b0011_0000, 0b0011_0000);
+        assert_eq!(cpu.cycles, RESET_CYCLES as u64 + 7 + 2 + 6 + 2);
+    }
+
+    #[test]
+    fn test_lax_sax() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0x8f);
+        cpu.load_and_run(vec![0xa7, 0x10, 0xa9, 0xf0, 0x87, 0x11, 0x00]);  //LAX $10, LDA #$f0, SAX $11
+        assert_eq!(cpu.register_x, 0x8f);
+        assert_eq!(cpu.mem_read(0x11), 0x80);
+    }
+
+    #[test]
+    fn test_dcp_isb() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0x06);
+        cpu.load_and_run(vec![0xa9, 0x05, 0xc7, 0x10, 0x00]);              //LDA #5, DCP $10
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+        assert!(cpu.status.contains(CpuFlags::ZERO | CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x01);
+        cpu.load_and_run(vec![0x38, 0xa9, 0x05, 0xe7, 0x10, 0x00]);        //SEC, LDA #5, ISB $10
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+    }
+
+    #[test]
+    fn test_shift_combos() {
+        let mut cpu = test_cpu();
+        cpu.mem_write(0x10, 0x81);
+        cpu.load_and_run(vec![0xa9, 0x01, 0x07, 0x10, 0x00]);              //SLO $10
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.register_a, 0x03);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.mem_write(0x10, 0x03);
+        cpu.load_and_run(vec![0xa9, 0xff, 0x47, 0x10, 0x00]);              //SRE $10
+        assert_eq!(cpu.register_a, 0xfe);
+
+        cpu.mem_write(0x10, 0x40);
+        cpu.load_and_run(vec![0x38, 0xa9, 0x81, 0x27, 0x10, 0x00]);        //SEC, RLA $10
+        assert_eq!(cpu.mem_read(0x10), 0x81);
+        assert_eq!(cpu.register_a, 0x81);
+
+        cpu.mem_write(0x10, 0x02);
+        cpu.load_and_run(vec![0xa9, 0x10, 0x67, 0x10, 0x00]);              //RRA $10
+        assert_eq!(cpu.register_a, 0x11);
+    }
+
+    #[test]
+    fn test_immediate_combos() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0xa9, 0xff, 0x0b, 0x80, 0x00]);              //ANC #$80
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::NEGTAIVE));
+
+        cpu.load_and_run(vec![0xa9, 0xff, 0x4b, 0x03, 0x00]);              //ALR #$03
+        assert_eq!(cpu.register_a, 0x01);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+
+        cpu.load_and_run(vec![0x38, 0xa9, 0xff, 0x6b, 0x80, 0x00]);        //SEC, ARR #$80
+        assert_eq!(cpu.register_a, 0xc0);
+        assert!(cpu.status.contains(CpuFlags::CARRY | CpuFlags::OVERFLOW));
+
+        cpu.load_and_run(vec![0xa9, 0x0f, 0xa2, 0x07, 0xcb, 0x02, 0x00]);  //AXS #$02
+        assert_eq!(cpu.register_x, 0x05);
+        assert!(cpu.status.contains(CpuFlags::CARRY));
+    }
+
+    #[test]
+    fn test_multi_byte_nops() {
+        let mut cpu = test_cpu();
+        cpu.load_and_run(vec![0x80, 0xff, 0x0c, 0x00, 0x02, 0x1a, 0xa9, 0x01, 0x00]);
+        assert_eq!(cpu.register_a, 0x01);
+    }
+
+    #[test]
+    fn test_illegal_opcode_trap() {
+        let mut cpu = test_cpu();
+        cpu.config.illegal_opcodes = IllegalOpcodeMode::Trap;
+        cpu.load(vec![0xa9, 0x01, 0xa7, 0x10, 0x00]);
+        cpu.reset();
+        assert_eq!(cpu.run_checked(), Err(CpuError::IllegalOpcode { code: 0xa7, address: 0x8002 }));
+        assert_eq!(cpu.program_counter, 0x8002);
+    }
+
+    #[test]
+    fn test_illegal_opcode_as_nop() {
+        let mut cpu = test_cpu();
+        cpu.config.illegal_opcodes = IllegalOpcodeMode::Nop;
+        cpu.mem_write(0x10, 0x55);
+        cpu.load_and_run(vec![0xa7, 0x10, 0x02, 0xe8, 0x00]);              //LAX $10 and JAM are both skipped
+        assert_eq!(cpu.register_a, 0);
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_jam_halts() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0xe8, 0x02, 0xe8, 0x00]);
+        cpu.reset();
+        assert_eq!(cpu.run_checked(), Err(CpuError::Jammed { code: 0x02, address: 0x8001 }));
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_step_returns_cycles() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0xa9, 0x05, 0x8d, 0x00, 0x02, 0xe8, 0x02]);            //LDA #5, STA $0200, INX, JAM
+        cpu.reset();
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.step(), Ok(4));
+        assert_eq!(cpu.mem_read(0x0200), 5);
+        assert_eq!(cpu.step(), Ok(2));
+        assert_eq!(cpu.step(), Err(StopReason::Jammed { code: 0x02, address: 0x8006 }));
+        assert_eq!(cpu.step(), Err(StopReason::Jammed { code: 0x02, address: 0x8006 }));   //stays jammed
+    }
+
+    #[test]
+    fn test_step_counts_interrupt_entry() {
+        let mut cpu = interrupt_test_cpu();
+        cpu.load(vec![0xea, 0x02]);
+        cpu.reset();
+        cpu.bus.set_nmi_line(true);
+        assert_eq!(cpu.step(), Ok(7 + 2));                                    //NMI entry then INX in the handler
+        assert_eq!(cpu.register_x, 1);
+    }
+
+    #[test]
+    fn test_run_until_and_brk() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0xe8, 0xe0, 0x03, 0xd0, 0xfb, 0x00]);                  //INX until X == 3, then BRK
+        cpu.reset();
+        assert_eq!(cpu.run_until(|cpu| cpu.register_x == 2), StopReason::Condition);
+        assert_eq!(cpu.register_x, 2);
+        assert_eq!(cpu.run_until(|_| false), StopReason::Brk { address: 0x8005 });
+        assert_eq!(cpu.register_x, 3);
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0x4c, 0x00, 0x80]);                                    //JMP $8000 forever
+        cpu.reset();
+        let start = cpu.cycles;
+        assert_eq!(cpu.run_cycles(10), StopReason::CyclesElapsed);
+        assert_eq!(cpu.cycles - start, 12);                                   //finishes the fourth 3 cycle JMP
+    }
+
+    #[test]
+    fn test_request_stop() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0xe8, 0x4c, 0x00, 0x80]);
+        cpu.reset();
+        let reason = cpu.run_until(|cpu| {
+            if cpu.register_x == 4 {
+                cpu.request_stop();
+            }
+            false
+        });
+        assert_eq!(reason, StopReason::HostRequest);
+        assert_eq!(cpu.register_x, 4);
+
+        cpu.request_stop();
+        assert_eq!(cpu.run_checked(), Ok(()));                                //run_checked honours it as well
+        assert_eq!(cpu.register_x, 4);
+        assert_eq!(cpu.run_cycles(3), StopReason::CyclesElapsed);             //and the request is used up
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut cpu = test_cpu();
+        cpu.load(vec![0x4c, 0x00, 0x80]);
+        cpu.reset();
+        assert_eq!(cpu.run_frame(), StopReason::FrameComplete);
+        assert_eq!(cpu.bus.ppu().frame_count, 1);
+        let first = cpu.cycles;
+        assert_eq!(cpu.run_frame(), StopReason::FrameComplete);
+        let frame = cpu.cycles - first;
+        assert!((29778..=29784).contains(&frame), "frame took {} cycles", frame);  //341 * 262 dots / 3, give or take a JMP
     }
- 
-     #[test]
-     fn test_inx_overflow() {
-         let mut cpu = CPU::new();
-         cpu.register_x = 0xff;
-         cpu.interpret(vec![0xe8, 0xe8, 0x00]);
- 
-         assert_eq!(cpu.register_x, 1)
-     }
 }
@@ -0,0 +1,193 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//https://www.nesdev.org/wiki/APU_DMC, rates are in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+#[derive(Clone)]
+pub struct Dmc {
+    pub irq_enabled: bool,
+    pub irq_flag: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+
+    //memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    pub bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+
+    //output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    pub output_level: u8,
+}
+
+impl Default for Dmc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Dmc {
+    pub fn new() -> Self {
+        Dmc {
+            irq_enabled: false,
+            irq_flag: false,
+            looping: false,
+            timer_period: RATE_TABLE[0],
+            timer: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            sample_buffer: None,
+            shift_register: 0,
+            bits_remaining: 8,
+            silence: true,
+            output_level: 0,
+        }
+    }
+
+    pub fn write_control(&mut self, data: u8) {                               //$4010 IL-- RRRR
+        self.irq_enabled = data & 0b1000_0000 != 0;
+        if !self.irq_enabled {
+            self.irq_flag = false;
+        }
+        self.looping = data & 0b0100_0000 != 0;
+        self.timer_period = RATE_TABLE[(data & 0b1111) as usize];
+    }
+
+    pub fn write_direct_load(&mut self, data: u8) {                           //$4011 -DDD DDDD
+        self.output_level = data & 0b0111_1111;
+    }
+
+    pub fn write_sample_address(&mut self, data: u8) {                        //$4012 samples start at $C000 + A * 64
+        self.sample_address = 0xC000 | ((data as u16) << 6);
+    }
+
+    pub fn write_sample_length(&mut self, data: u8) {                         //$4013 L * 16 + 1 bytes
+        self.sample_length = ((data as u16) << 4) | 1;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq_flag = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn sample_request(&self) -> Option<u16> {                             //the address the memory reader wants the bus to fetch, if any
+        if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    pub fn provide_sample(&mut self, data: u8) {
+        self.sample_buffer = Some(data);
+        self.current_address = if self.current_address == 0xFFFF {
+            0x8000
+        } else {
+            self.current_address + 1
+        };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift_register & 1 == 1 {
+                if self.output_level <= 125 {
+                    self.output_level += 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level -= 2;
+            }
+        }
+        self.shift_register >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift_register = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        self.output_level
+    }
+}
+
+impl Snapshot for Dmc {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_flag);
+        w.bool(self.looping);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u16(self.sample_address);
+        w.u16(self.sample_length);
+        w.u16(self.current_address);
+        w.u16(self.bytes_remaining);
+        w.bool(self.sample_buffer.is_some());
+        w.u8(self.sample_buffer.unwrap_or(0));
+        w.u8(self.shift_register);
+        w.u8(self.bits_remaining);
+        w.bool(self.silence);
+        w.u8(self.output_level);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.irq_enabled = r.bool()?;
+        self.irq_flag = r.bool()?;
+        self.looping = r.bool()?;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.sample_address = r.u16()?;
+        self.sample_length = r.u16()?;
+        self.current_address = r.u16()?;
+        self.bytes_remaining = r.u16()?;
+        let buffer_full = r.bool()?;
+        let sample_buffer = r.u8()?;
+        self.sample_buffer = if buffer_full { Some(sample_buffer) } else { None };
+        self.shift_register = r.u8()?;
+        self.bits_remaining = r.u8()?;
+        self.silence = r.bool()?;
+        self.output_level = r.u8()?;
+        Ok(())
+    }
+}
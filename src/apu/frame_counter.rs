@@ -0,0 +1,114 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    FRAME COUNTER (https://www.nesdev.org/wiki/APU_Frame_Counter)
+    steps are in CPU cycles since the counter was last reset (NTSC)
+    4-step: Q  Q+H  Q  Q+H+IRQ                  (reset after 29830)
+    5-step: Q  Q+H  Q  -  Q+H                   (reset after 37282)
+*/
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const STEP_4: u32 = 29829;
+const STEP_5: u32 = 37281;
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameMode {
+    FourStep,
+    FiveStep,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameClock {                                                       //which units the frame counter clocked this cycle
+    pub quarter: bool,
+    pub half: bool,
+}
+
+#[derive(Clone)]
+pub struct FrameCounter {
+    pub mode: FrameMode,
+    pub irq_inhibit: bool,
+    pub irq_flag: bool,
+    cycle: u32,
+}
+
+impl Default for FrameCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameCounter {
+    pub fn new() -> Self {
+        FrameCounter {
+            mode: FrameMode::FourStep,
+            irq_inhibit: false,
+            irq_flag: false,
+            cycle: 0,
+        }
+    }
+
+    pub fn write(&mut self, data: u8) -> FrameClock {                         //$4017 MI-- ----
+        self.mode = if data & 0b1000_0000 != 0 { FrameMode::FiveStep } else { FrameMode::FourStep };
+        self.irq_inhibit = data & 0b0100_0000 != 0;
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+        self.cycle = 0;
+        match self.mode {                                                     //5-step mode clocks every unit as soon as it is selected
+            FrameMode::FiveStep => FrameClock { quarter: true, half: true },
+            FrameMode::FourStep => FrameClock::default(),
+        }
+    }
+
+    pub fn clock(&mut self) -> FrameClock {
+        self.cycle += 1;
+        let mut clock = FrameClock::default();
+        match (self.cycle, self.mode) {
+            (STEP_1, _) | (STEP_3, _) => clock.quarter = true,
+            (STEP_2, _) => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            (STEP_4, FrameMode::FourStep) => {
+                clock.quarter = true;
+                clock.half = true;
+                if !self.irq_inhibit {
+                    self.irq_flag = true;
+                }
+            }
+            (STEP_5, FrameMode::FiveStep) => {
+                clock.quarter = true;
+                clock.half = true;
+            }
+            _ => {}
+        }
+        let length = match self.mode {
+            FrameMode::FourStep => FOUR_STEP_LENGTH,
+            FrameMode::FiveStep => FIVE_STEP_LENGTH,
+        };
+        if self.cycle >= length {
+            self.cycle = 0;
+        }
+        clock
+    }
+}
+
+impl Snapshot for FrameCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.mode == FrameMode::FiveStep);
+        w.bool(self.irq_inhibit);
+        w.bool(self.irq_flag);
+        w.u32(self.cycle);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mode = if r.bool()? { FrameMode::FiveStep } else { FrameMode::FourStep };
+        self.irq_inhibit = r.bool()?;
+        self.irq_flag = r.bool()?;
+        self.cycle = r.u32()?;
+        Ok(())
+    }
+}
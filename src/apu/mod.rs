@@ -0,0 +1,523 @@
+pub mod dmc;
+pub mod frame_counter;
+pub mod noise;
+pub mod pulse;
+pub mod resampler;
+pub mod triangle;
+pub mod units;
+pub mod wav;
+
+use dmc::Dmc;
+use frame_counter::{FrameClock, FrameCounter};
+use noise::Noise;
+use pulse::Pulse;
+use resampler::{Resampler, DEFAULT_SAMPLE_RATE};
+use triangle::Triangle;
+use wav::AudioCapture;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    APU REGISTERS (https://www.nesdev.org/wiki/APU_registers)
+    $4000 - $4003   pulse 1
+    $4004 - $4007   pulse 2
+    $4008 - $400B   triangle
+    $400C - $400F   noise
+    $4010 - $4013   DMC
+    $4015           channel enable (write) / status (read)
+    $4017           frame counter (write only, reads go to the second joypad)
+*/
+
+lazy_static! {
+    //non-linear mixer as lookup tables (https://www.nesdev.org/wiki/APU_Mixer)
+    static ref PULSE_TABLE: [f32; 31] = {
+        let mut table = [0.0; 31];
+        for (n, out) in table.iter_mut().enumerate().skip(1) {
+            *out = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        table
+    };
+    static ref TND_TABLE: [f32; 203] = {                                      //indexed by 3 * triangle + 2 * noise + dmc
+        let mut table = [0.0; 203];
+        for (n, out) in table.iter_mut().enumerate().skip(1) {
+            *out = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        table
+    };
+}
+
+pub struct Apu {
+    pub pulse1: Pulse,
+    pub pulse2: Pulse,
+    pub triangle: Triangle,
+    pub noise: Noise,
+    pub dmc: Dmc,
+    pub frame_counter: FrameCounter,
+    cycle: u64,                                                               //CPU cycles, the pulse timers only run on every other one  
+    resampler: Resampler,
+    capture: Option<Box<AudioCapture>>,                                       //recording to WAV, see wav.rs
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Apu {
+    pub fn new(sample_rate: u32) -> Self {
+        Apu {
+            pulse1: Pulse::new(true),
+            pulse2: Pulse::new(false),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            frame_counter: FrameCounter::new(),
+            cycle: 0,
+            resampler: Resampler::new(sample_rate),
+            capture: None,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {                     //drops any samples that haven't been taken yet
+        self.resampler = Resampler::new(sample_rate);
+    }
+
+    pub fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4000 => self.pulse1.write_control(data),
+            0x4001 => self.pulse1.write_sweep(data),
+            0x4002 => self.pulse1.write_timer_low(data),
+            0x4003 => self.pulse1.write_timer_high(data),
+            0x4004 => self.pulse2.write_control(data),
+            0x4005 => self.pulse2.write_sweep(data),
+            0x4006 => self.pulse2.write_timer_low(data),
+            0x4007 => self.pulse2.write_timer_high(data),
+            0x4008 => self.triangle.write_linear_counter(data),
+            0x400A => self.triangle.write_timer_low(data),
+            0x400B => self.triangle.write_timer_high(data),
+            0x400C => self.noise.write_control(data),
+            0x400E => self.noise.write_period(data),
+            0x400F => self.noise.write_length(data),
+            0x4010 => self.dmc.write_control(data),
+            0x4011 => self.dmc.write_direct_load(data),
+            0x4012 => self.dmc.write_sample_address(data),
+            0x4013 => self.dmc.write_sample_length(data),
+            0x4015 => {                                                       //---D NT21
+                self.pulse1.length.set_enabled(data & 0b0000_0001 != 0);
+                self.pulse2.length.set_enabled(data & 0b0000_0010 != 0);
+                self.triangle.length.set_enabled(data & 0b0000_0100 != 0);
+                self.noise.length.set_enabled(data & 0b0000_1000 != 0);
+                self.dmc.set_enabled(data & 0b0001_0000 != 0);
+            }
+            0x4017 => {
+                let clock = self.frame_counter.write(data);
+                self.clock_frame_units(clock);
+            }
+            _ => {}                                                           //$4009 and $400D are unused
+        }
+    }
+
+    pub fn read_status(&mut self) -> u8 {                                     //$4015 IF-D NT21, reading acknowledges the frame IRQ
+        let mut status = 0;
+        if self.pulse1.length.is_active() {
+            status |= 0b0000_0001;
+        }
+        if self.pulse2.length.is_active() {
+            status |= 0b0000_0010;
+        }
+        if self.triangle.length.is_active() {
+            status |= 0b0000_0100;
+        }
+        if self.noise.length.is_active() {
+            status |= 0b0000_1000;
+        }
+        if self.dmc.bytes_remaining > 0 {
+            status |= 0b0001_0000;
+        }
+        if self.frame_counter.irq_flag {
+            status |= 0b0100_0000;
+        }
+        if self.dmc.irq_flag {
+            status |= 0b1000_0000;
+        }
+        self.frame_counter.irq_flag = false;
+        status
+    }
+
+    pub fn frame_irq(&self) -> bool {
+        self.frame_counter.irq_flag
+    }
+
+    pub fn dmc_irq(&self) -> bool {
+        self.dmc.irq_flag
+    }
+
+    pub fn dmc_sample_request(&self) -> Option<u16> {                         //the bus answers this with provide_dmc_sample and stalls the CPU
+        self.dmc.sample_request()
+    }
+
+    pub fn provide_dmc_sample(&mut self, data: u8) {
+        self.dmc.provide_sample(data);
+    }
+
+    fn clock_frame_units(&mut self, clock: FrameClock) {
+        if clock.quarter {
+            self.pulse1.envelope.clock();
+            self.pulse2.envelope.clock();
+            self.noise.envelope.clock();
+            self.triangle.clock_linear_counter();
+        }
+        if clock.half {
+            self.pulse1.length.clock();
+            self.pulse2.length.clock();
+            self.triangle.length.clock();
+            self.noise.length.clock();
+            self.pulse1.clock_sweep();
+            self.pulse2.clock_sweep();
+        }
+    }
+
+    pub fn clock(&mut self) {                                                 //advances the APU by one CPU cycle
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        if self.cycle % 2 == 1 {
+            self.pulse1.clock_timer();
+            self.pulse2.clock_timer();
+        }
+        let clock = self.frame_counter.clock();
+        self.clock_frame_units(clock);
+        self.cycle += 1;
+
+        let sample = self.output();
+        self.resampler.push(sample);
+        if let Some(capture) = self.capture.as_mut() {
+            let channels = [
+                self.pulse1.output(),
+                self.pulse2.output(),
+                self.triangle.output(),
+                self.noise.output(),
+                self.dmc.output(),
+            ];
+            capture.push(sample, channels);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.clock();
+        }
+    }
+
+    pub fn channel_outputs(&self) -> [u8; 5] {                               //pulse 1, pulse 2, triangle, noise, DMC
+        [
+            self.pulse1.output(),
+            self.pulse2.output(),
+            self.triangle.output(),
+            self.noise.output(),
+            self.dmc.output(),
+        ]
+    }
+
+    pub fn output(&self) -> f32 {                                             //mixer output before resampling, roughly 0.0 to 1.0
+        let [p1, p2, t, n, d] = self.channel_outputs();
+        PULSE_TABLE[(p1 + p2) as usize] + TND_TABLE[3 * t as usize + 2 * n as usize + d as usize]
+    }
+
+    pub fn pending_samples(&self) -> usize {
+        self.resampler.pending()
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.resampler.take_samples()
+    }
+
+    pub fn start_capture(&mut self, capture: AudioCapture) {
+        self.capture = Some(Box::new(capture));
+    }
+
+    pub fn stop_capture(&mut self) -> Option<AudioCapture> {
+        self.capture.take().map(|capture| *capture)
+    }
+
+    pub fn capture_mut(&mut self) -> Option<&mut AudioCapture> {
+        self.capture.as_deref_mut()
+    }
+}
+
+
+/*
+    SAVE STATE
+    the resampler and a running capture only hold output, they're left as they are
+*/
+impl Snapshot for Apu {
+    fn save(&self, w: &mut StateWriter) {
+        self.pulse1.save(w);
+        self.pulse2.save(w);
+        self.triangle.save(w);
+        self.noise.save(w);
+        self.dmc.save(w);
+        self.frame_counter.save(w);
+        w.u64(self.cycle);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.pulse1.load(r)?;
+        self.pulse2.load(r)?;
+        self.triangle.load(r)?;
+        self.noise.load(r)?;
+        self.dmc.load(r)?;
+        self.frame_counter.load(r)?;
+        self.cycle = r.u64()?;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::frame_counter::FrameMode;
+
+    fn run_cycles(apu: &mut Apu, cycles: u32) {
+        for _ in 0..cycles {
+            apu.clock();
+        }
+    }
+
+    #[test]
+    fn test_length_counter_load_and_status() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4003, 0b0000_1000);                              //ignored while the channel is disabled
+        assert_eq!(apu.read_status() & 0x01, 0);
+
+        apu.write_register(0x4015, 0b0000_1111);
+        apu.write_register(0x4003, 0b0000_1000);                              //index 1 loads 254
+        apu.write_register(0x400F, 0b0001_1000);                              //index 3 loads 2
+        assert_eq!(apu.pulse1.length.counter, 254);
+        assert_eq!(apu.read_status() & 0x0F, 0b1001);
+
+        run_cycles(&mut apu, 29830);                                          //two half frames
+        assert_eq!(apu.pulse1.length.counter, 252);
+        assert_eq!(apu.read_status() & 0x08, 0);
+
+        apu.write_register(0x4015, 0);
+        assert_eq!(apu.read_status() & 0x0F, 0);
+    }
+
+    #[test]
+    fn test_length_counter_halt() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b0010_0000);
+        apu.write_register(0x4003, 0b0000_1000);
+        run_cycles(&mut apu, 29830);
+        assert_eq!(apu.pulse1.length.counter, 254);
+    }
+
+    #[test]
+    fn test_frame_irq_four_step() {
+        let mut apu = Apu::default();
+        run_cycles(&mut apu, 29828);
+        assert!(!apu.frame_irq());
+        run_cycles(&mut apu, 1);
+        assert!(apu.frame_irq());
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.frame_irq());                                            //reading $4015 acknowledges it
+    }
+
+    #[test]
+    fn test_frame_irq_inhibit_and_five_step() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4017, 0b0100_0000);
+        run_cycles(&mut apu, 30000);
+        assert!(!apu.frame_irq());
+
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.frame_counter.mode, FrameMode::FiveStep);
+        run_cycles(&mut apu, 40000);
+        assert!(!apu.frame_irq());                                            //5-step mode never raises the IRQ
+    }
+
+    #[test]
+    fn test_five_step_write_clocks_units() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse1.length.counter, 253);
+    }
+
+    #[test]
+    fn test_envelope_decay() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1000_0000);                              //decaying envelope, divider period 0
+        apu.write_register(0x4003, 0b0000_1000);
+        apu.write_register(0x4017, 0b1000_0000);                              //start flag reloads decay to 15
+        assert_eq!(apu.pulse1.envelope.output(), 15);
+        apu.write_register(0x4017, 0b1000_0000);
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse1.envelope.output(), 13);
+
+        apu.write_register(0x4000, 0b1001_0110);                              //constant volume
+        assert_eq!(apu.pulse1.envelope.output(), 6);
+    }
+
+    #[test]
+    fn test_sweep_adjusts_period_and_mutes() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0011);
+        apu.write_register(0x4002, 0x00);
+        apu.write_register(0x4003, 0b0000_1001);                              //period $100
+        apu.write_register(0x4001, 0b1000_1001);                              //enabled, period 0, negate, shift 1
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse1.timer_period, 0x100 - 0x80 - 1);                //pulse 1 uses ones' complement
+
+        apu.write_register(0x4006, 0x00);
+        apu.write_register(0x4007, 0b0000_1001);
+        apu.write_register(0x4005, 0b1000_1001);
+        apu.write_register(0x4017, 0b1000_0000);
+        assert_eq!(apu.pulse2.timer_period, 0x100 - 0x80);
+
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4001, 0b0000_0000);                              //shift 0 targets twice the period
+        apu.write_register(0x4002, 0xFF);
+        apu.write_register(0x4003, 0b0000_1111);
+        run_cycles(&mut apu, 64);
+        assert_eq!(apu.pulse1.output(), 0);
+    }
+
+    #[test]
+    fn test_pulse_waveform() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);                              //50% duty, constant volume 15
+        apu.write_register(0x4002, 0x08);
+        apu.write_register(0x4003, 0b0000_1000);
+        let mut high = 0;
+        for _ in 0..(18 * 8) {                                                //one full period is 8 steps of (period + 1) * 2 CPU cycles
+            apu.clock();
+            if apu.pulse1.output() == 15 {
+                high += 1;
+            }
+        }
+        assert_eq!(high, 18 * 4);
+    }
+
+    #[test]
+    fn test_triangle_needs_linear_counter() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_0100);
+        apu.write_register(0x400A, 0x10);
+        apu.write_register(0x400B, 0b0000_1000);
+        run_cycles(&mut apu, 100);
+        assert_eq!(apu.triangle.output(), 15);                                //linear counter is still 0
+
+        apu.write_register(0x4008, 0b0111_1111);
+        apu.write_register(0x400B, 0b0000_1000);
+        apu.write_register(0x4017, 0b1000_0000);                              //reload the linear counter
+        run_cycles(&mut apu, 17 * 3);
+        assert_eq!(apu.triangle.output(), 12);
+    }
+
+    #[test]
+    fn test_noise_shift_register() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4015, 0b0000_1000);
+        apu.write_register(0x400C, 0b0011_1010);
+        apu.write_register(0x400E, 0x00);
+        apu.write_register(0x400F, 0b0000_1000);
+        let mut seen = [false; 2];
+        for _ in 0..400 {
+            apu.clock();
+            seen[(apu.noise.output() == 10) as usize] = true;
+        }
+        assert!(seen[0] && seen[1]);
+    }
+
+    #[test]
+    fn test_dmc_fetch_and_irq() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4010, 0b1000_1111);                              //IRQ enabled, fastest rate
+        apu.write_register(0x4012, 0x00);
+        apu.write_register(0x4013, 0x00);                                     //a single byte at $C000
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(apu.read_status() & 0x10, 0x10);
+        assert_eq!(apu.dmc_sample_request(), Some(0xC000));
+
+        apu.provide_dmc_sample(0xFF);
+        assert_eq!(apu.dmc_sample_request(), None);
+        assert!(apu.dmc_irq());
+        assert_eq!(apu.read_status() & 0x90, 0x80);
+
+        apu.write_register(0x4011, 0x40);
+        run_cycles(&mut apu, 54 * 16);
+        assert_eq!(apu.dmc.output(), 0x40 + 2 * 8);                           //every set bit steps the level up by 2
+
+        apu.write_register(0x4015, 0);                                        //writing $4015 clears the DMC IRQ
+        assert!(!apu.dmc_irq());
+    }
+
+    #[test]
+    fn test_dmc_loops() {
+        let mut apu = Apu::default();
+        apu.write_register(0x4010, 0b0100_0000);
+        apu.write_register(0x4012, 0xFF);
+        apu.write_register(0x4013, 0x00);
+        apu.write_register(0x4015, 0b0001_0000);
+        assert_eq!(apu.dmc_sample_request(), Some(0xFFC0));
+        apu.provide_dmc_sample(0);
+        assert_eq!(apu.dmc.bytes_remaining, 1);
+        assert!(!apu.dmc_irq());
+    }
+
+    #[test]
+    fn test_mixer() {
+        let mut apu = Apu::default();
+        let idle = apu.output();                                              //the triangle rests on step 0, which outputs 15
+        assert!((idle - TND_TABLE[45]).abs() < f32::EPSILON);
+        apu.write_register(0x4011, 0x7F);
+        let with_dmc = apu.output();
+        assert!(with_dmc > idle && with_dmc < 1.0);
+        assert!((PULSE_TABLE[30] - 0.2575).abs() < 0.001);
+        assert!((TND_TABLE[202] - 0.7425).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_resampled_output_rate() {
+        let mut apu = Apu::new(48_000);
+        run_cycles(&mut apu, resampler::NTSC_CPU_CLOCK as u32 / 10);
+        let samples = apu.take_samples();
+        assert!((4799..=4801).contains(&samples.len()));
+        assert_eq!(apu.pending_samples(), 0);
+
+        apu.set_sample_rate(22_050);
+        assert_eq!(apu.sample_rate(), 22_050);
+        apu.write_register(0x4015, 0b0000_0001);
+        apu.write_register(0x4000, 0b1011_1111);
+        apu.write_register(0x4002, 0xFD);                                     //~440Hz square
+        apu.write_register(0x4003, 0b1111_1000);
+        run_cycles(&mut apu, resampler::NTSC_CPU_CLOCK as u32 / 20);
+        let samples = apu.take_samples();
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak > 0.05 && peak <= 1.0);
+    }
+
+    #[test]
+    fn test_undrained_output_is_bounded() {
+        let mut apu = Apu::new(8_000);
+        run_cycles(&mut apu, resampler::NTSC_CPU_CLOCK as u32 * 3);          //three seconds and nobody takes the samples
+        assert_eq!(apu.pending_samples(), 8_000);
+        assert_eq!(apu.take_samples().len(), 8_000);
+        assert_eq!(apu.pending_samples(), 0);
+    }
+}
@@ -0,0 +1,92 @@
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//https://www.nesdev.org/wiki/APU_Noise, periods are in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+#[derive(Clone)]
+pub struct Noise {
+    mode: bool,                                                               //short mode taps bit 6 instead of bit 1 for a metallic tone
+    timer_period: u16,
+    timer: u16,
+    shift_register: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            mode: false,
+            timer_period: PERIOD_TABLE[0],
+            timer: 0,
+            shift_register: 1,                                                //loaded with 1 on power up
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    pub fn write_control(&mut self, data: u8) {                               //$400C --LC VVVV
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    pub fn write_period(&mut self, data: u8) {                                //$400E M--- PPPP
+        self.mode = data & 0b1000_0000 != 0;
+        self.timer_period = PERIOD_TABLE[(data & 0b1111) as usize];
+    }
+
+    pub fn write_length(&mut self, data: u8) {                                //$400F LLLL L---
+        self.length.load(data >> 3);
+        self.envelope.start = true;
+    }
+
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.timer_period - 1;
+            let tap = if self.mode { 6 } else { 1 };
+            let feedback = (self.shift_register & 1) ^ ((self.shift_register >> tap) & 1);
+            self.shift_register >>= 1;
+            self.shift_register |= feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.shift_register & 1 == 1 || !self.length.is_active() {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Snapshot for Noise {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.mode);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u16(self.shift_register);
+        self.envelope.save(w);
+        self.length.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.mode = r.bool()?;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.shift_register = r.u16()?;
+        self.envelope.load(r)?;
+        Snapshot::load(&mut self.length, r)?;
+        Ok(())
+    }
+}
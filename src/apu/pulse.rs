@@ -0,0 +1,162 @@
+use super::units::{Envelope, LengthCounter};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//https://www.nesdev.org/wiki/APU_Pulse
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],                                                 //12.5%
+    [0, 1, 1, 0, 0, 0, 0, 0],                                                 //25%
+    [0, 1, 1, 1, 1, 0, 0, 0],                                                 //50%
+    [1, 0, 0, 1, 1, 1, 1, 1],                                                 //25% negated
+];
+
+#[derive(Default, Clone)]
+pub struct Sweep {
+    pub enabled: bool,
+    pub period: u8,
+    pub negate: bool,
+    pub shift: u8,
+    pub reload: bool,
+    divider: u8,
+}
+
+#[derive(Clone)]
+pub struct Pulse {
+    ones_complement: bool,                                                    //pulse 1 negates with ones' complement, so it sweeps down one further
+    pub duty: u8,
+    sequence_step: u8,
+    pub timer_period: u16,
+    timer: u16,
+    pub envelope: Envelope,
+    pub length: LengthCounter,
+    pub sweep: Sweep,
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Pulse {
+            ones_complement,
+            duty: 0,
+            sequence_step: 0,
+            timer_period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    pub fn write_control(&mut self, data: u8) {                               //$4000/$4004 DDLC VVVV
+        self.duty = data >> 6;
+        self.length.halt = data & 0b0010_0000 != 0;
+        self.envelope.write(data);
+    }
+
+    pub fn write_sweep(&mut self, data: u8) {                                 //$4001/$4005 EPPP NSSS
+        self.sweep.enabled = data & 0b1000_0000 != 0;
+        self.sweep.period = (data >> 4) & 0b111;
+        self.sweep.negate = data & 0b1000 != 0;
+        self.sweep.shift = data & 0b111;
+        self.sweep.reload = true;
+    }
+
+    pub fn write_timer_low(&mut self, data: u8) {                             //$4002/$4006 TTTT TTTT
+        self.timer_period = (self.timer_period & 0xFF00) | data as u16;
+    }
+
+    pub fn write_timer_high(&mut self, data: u8) {                            //$4003/$4007 LLLL LTTT
+        self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+        self.length.load(data >> 3);
+        self.sequence_step = 0;
+        self.envelope.start = true;
+    }
+
+    pub fn clock_timer(&mut self) {                                           //clocked every APU cycle (every other CPU cycle)
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            self.sequence_step = (self.sequence_step + 1) & 0b111;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn sweep_target(&self) -> u16 {
+        let change = self.timer_period >> self.sweep.shift;
+        if self.sweep.negate {
+            let extra = if self.ones_complement { 1 } else { 0 };
+            self.timer_period.saturating_sub(change + extra)
+        } else {
+            self.timer_period + change
+        }
+    }
+
+    fn is_muted(&self) -> bool {                                              //the sweep unit mutes the channel even when it is disabled
+        self.timer_period < 8 || self.sweep_target() > 0x7FF
+    }
+
+    pub fn clock_sweep(&mut self) {                                           //clocked every half frame
+        if self.sweep.divider == 0 && self.sweep.enabled && self.sweep.shift > 0 && !self.is_muted() {
+            self.timer_period = self.sweep_target();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.is_active()
+            || self.is_muted()
+            || DUTY_TABLE[self.duty as usize][self.sequence_step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Snapshot for Sweep {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.enabled);
+        w.u8(self.period);
+        w.bool(self.negate);
+        w.u8(self.shift);
+        w.bool(self.reload);
+        w.u8(self.divider);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.enabled = r.bool()?;
+        self.period = r.u8()?;
+        self.negate = r.bool()?;
+        self.shift = r.u8()?;
+        self.reload = r.bool()?;
+        self.divider = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for Pulse {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.duty);
+        w.u8(self.sequence_step);
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        self.envelope.save(w);
+        self.length.save(w);
+        self.sweep.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.duty = r.u8()?;
+        self.sequence_step = r.u8()?;
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.envelope.load(r)?;
+        Snapshot::load(&mut self.length, r)?;
+        self.sweep.load(r)?;
+        Ok(())
+    }
+}
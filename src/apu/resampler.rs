@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+pub const NTSC_CPU_CLOCK: f64 = 1_789_773.0;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44_100;
+
+//the console's own output filters (https://www.nesdev.org/wiki/APU_Mixer)
+const HIGH_PASS_1_HZ: f32 = 90.0;
+const HIGH_PASS_2_HZ: f32 = 440.0;
+const LOW_PASS_HZ: f32 = 14_000.0;
+
+#[derive(Clone)]
+struct Filter {
+    high_pass: bool,
+    alpha: f32,
+    prev_input: f32,
+    prev_output: f32,
+}
+
+impl Filter {
+    fn new(high_pass: bool, cutoff: f32, sample_rate: u32) -> Self {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate as f32;
+        let alpha = if high_pass { rc / (rc + dt) } else { dt / (rc + dt) };
+        Filter { high_pass, alpha, prev_input: 0.0, prev_output: 0.0 }
+    }
+
+    fn process(&mut self, input: f32) -> f32 {
+        let output = if self.high_pass {
+            self.alpha * (self.prev_output + input - self.prev_input)
+        } else {
+            self.prev_output + self.alpha * (input - self.prev_output)
+        };
+        self.prev_input = input;
+        self.prev_output = output;
+        output
+    }
+}
+
+/*
+    RESAMPLER
+    the mixer is sampled on every CPU cycle, each output sample is the average of
+    the CPU cycles it covers (a box filter) followed by the console's output filters.
+    nothing has to drain the output, it keeps the last second and drops anything older
+*/
+#[derive(Clone)]
+pub struct Resampler {
+    sample_rate: u32,
+    cycles_per_sample: f64,
+    counter: f64,
+    sum: f32,
+    count: u32,
+    filters: [Filter; 3],
+    samples: VecDeque<f32>,                                                   //at most sample_rate of them
+}
+
+impl Default for Resampler {
+    fn default() -> Self {
+        Self::new(DEFAULT_SAMPLE_RATE)
+    }
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        assert!(sample_rate > 0, "sample rate must be positive");
+        Resampler {
+            sample_rate,
+            cycles_per_sample: NTSC_CPU_CLOCK / sample_rate as f64,
+            counter: 0.0,
+            sum: 0.0,
+            count: 0,
+            filters: [
+                Filter::new(true, HIGH_PASS_1_HZ, sample_rate),
+                Filter::new(true, HIGH_PASS_2_HZ, sample_rate),
+                Filter::new(false, LOW_PASS_HZ, sample_rate),
+            ],
+            samples: VecDeque::new(),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn push(&mut self, value: f32) {                                      //one mixer value per CPU cycle
+        self.sum += value;
+        self.count += 1;
+        self.counter += 1.0;
+        if self.counter >= self.cycles_per_sample {
+            self.counter -= self.cycles_per_sample;
+            let mut sample = self.sum / self.count as f32;
+            for filter in self.filters.iter_mut() {
+                sample = filter.process(sample);
+            }
+            if self.samples.len() >= self.sample_rate as usize {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(sample);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+
+    pub fn pending(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.samples.drain(..).collect()
+    }
+}
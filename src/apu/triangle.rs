@@ -0,0 +1,90 @@
+use super::units::LengthCounter;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//https://www.nesdev.org/wiki/APU_Triangle
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default, Clone)]
+pub struct Triangle {
+    pub timer_period: u16,
+    timer: u16,
+    sequence_step: u8,
+    pub length: LengthCounter,
+    control: bool,                                                            //also the length counter halt flag
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn write_linear_counter(&mut self, data: u8) {                        //$4008 CRRR RRRR
+        self.control = data & 0b1000_0000 != 0;
+        self.length.halt = self.control;
+        self.linear_reload_value = data & 0b0111_1111;
+    }
+
+    pub fn write_timer_low(&mut self, data: u8) {                             //$400A TTTT TTTT
+        self.timer_period = (self.timer_period & 0xFF00) | data as u16;
+    }
+
+    pub fn write_timer_high(&mut self, data: u8) {                            //$400B LLLL LTTT
+        self.timer_period = (self.timer_period & 0x00FF) | (((data & 0b111) as u16) << 8);
+        self.length.load(data >> 3);
+        self.linear_reload = true;
+    }
+
+    pub fn clock_timer(&mut self) {                                           //the triangle timer runs at the full CPU rate
+        if self.timer == 0 {
+            self.timer = self.timer_period;
+            //the sequencer only moves while both counters are running, periods under 2 are ultrasonic and just pop
+            if self.length.is_active() && self.linear_counter > 0 && self.timer_period >= 2 {
+                self.sequence_step = (self.sequence_step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_linear_counter(&mut self) {                                  //clocked every quarter frame
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn output(&self) -> u8 {                                              //a silenced triangle holds its last step instead of dropping to 0
+        SEQUENCE[self.sequence_step as usize]
+    }
+}
+
+impl Snapshot for Triangle {
+    fn save(&self, w: &mut StateWriter) {
+        w.u16(self.timer_period);
+        w.u16(self.timer);
+        w.u8(self.sequence_step);
+        self.length.save(w);
+        w.bool(self.control);
+        w.u8(self.linear_reload_value);
+        w.u8(self.linear_counter);
+        w.bool(self.linear_reload);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.timer_period = r.u16()?;
+        self.timer = r.u16()?;
+        self.sequence_step = r.u8()?;
+        Snapshot::load(&mut self.length, r)?;
+        self.control = r.bool()?;
+        self.linear_reload_value = r.u8()?;
+        self.linear_counter = r.u8()?;
+        self.linear_reload = r.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    SHARED CHANNEL UNITS
+    https://www.nesdev.org/wiki/APU_Envelope
+    https://www.nesdev.org/wiki/APU_Length_Counter
+
+*/
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Default, Clone)]
+pub struct Envelope {
+    pub start: bool,
+    pub looping: bool,                                                        //shares its bit with the length counter halt flag
+    pub constant_volume: bool,
+    pub volume: u8,                                                           //constant volume, or the divider period when decaying
+    divider: u8,
+    decay_level: u8,
+}
+
+impl Envelope {
+    pub fn write(&mut self, data: u8) {                                       //--LC VVVV
+        self.looping = data & 0b0010_0000 != 0;
+        self.constant_volume = data & 0b0001_0000 != 0;
+        self.volume = data & 0b1111;
+    }
+
+    pub fn clock(&mut self) {                                                 //clocked by the frame counter every quarter frame
+        if self.start {
+            self.start = false;
+            self.decay_level = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay_level > 0 {
+                self.decay_level -= 1;
+            } else if self.looping {
+                self.decay_level = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant_volume {
+            self.volume
+        } else {
+            self.decay_level
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct LengthCounter {
+    pub counter: u8,
+    pub halt: bool,
+    enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn set_enabled(&mut self, enabled: bool) {                            //disabling a channel through $4015 clears its counter straight away
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTH_TABLE[(index & 0x1F) as usize];
+        }
+    }
+
+    pub fn clock(&mut self) {                                                 //clocked by the frame counter every half frame
+        if self.counter > 0 && !self.halt {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl Snapshot for Envelope {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.start);
+        w.bool(self.looping);
+        w.bool(self.constant_volume);
+        w.u8(self.volume);
+        w.u8(self.divider);
+        w.u8(self.decay_level);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.start = r.bool()?;
+        self.looping = r.bool()?;
+        self.constant_volume = r.bool()?;
+        self.volume = r.u8()?;
+        self.divider = r.u8()?;
+        self.decay_level = r.u8()?;
+        Ok(())
+    }
+}
+
+impl Snapshot for LengthCounter {
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(self.counter);
+        w.bool(self.halt);
+        w.bool(self.enabled);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.counter = r.u8()?;
+        self.halt = r.bool()?;
+        self.enabled = r.bool()?;
+        Ok(())
+    }
+}
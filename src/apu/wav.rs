@@ -0,0 +1,143 @@
+use super::resampler::Resampler;
+use super::{PULSE_TABLE, TND_TABLE};
+
+/*
+    AUDIO CAPTURE
+    while a capture is running the APU feeds it the mixer output on every CPU cycle,
+    through resamplers of its own so whatever plays the audio live isn't disturbed.
+    stems are every channel put through the mixer on its own, they don't add up to the
+    mix exactly since the mixer isn't linear
+
+    WAV (http://soundfile.sapp.org/doc/WaveFormat/)
+    16 bit signed PCM, mono, everything little endian
+*/
+pub const CHANNEL_NAMES: [&str; 5] = ["pulse1", "pulse2", "triangle", "noise", "dmc"];
+const BITS_PER_SAMPLE: u16 = 16;
+const PCM_FORMAT: u16 = 1;
+
+pub fn encode_wav(samples: &[f32], sample_rate: u32) -> Vec<u8> {
+    let data_size = (samples.len() * 2) as u32;
+    let block_align = BITS_PER_SAMPLE / 8;
+    let mut out = Vec::with_capacity(44 + data_size as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_size).to_le_bytes());
+    out.extend_from_slice(b"WAVE");
+
+    out.extend_from_slice(b"fmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&PCM_FORMAT.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());                               //channels
+    out.extend_from_slice(&sample_rate.to_le_bytes());
+    out.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes()); //bytes per second
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_size.to_le_bytes());
+    for &sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
+
+#[derive(Clone)]
+pub struct AudioCapture {
+    sample_rate: u32,
+    mix: Resampler,
+    stems: Option<Vec<Resampler>>,                                           //one per channel, in CHANNEL_NAMES order
+}
+
+impl AudioCapture {
+    pub fn new(sample_rate: u32, stems: bool) -> Self {
+        AudioCapture {
+            sample_rate,
+            mix: Resampler::new(sample_rate),
+            stems: stems.then(|| vec![Resampler::new(sample_rate); CHANNEL_NAMES.len()]),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub(super) fn push(&mut self, mix: f32, channels: [u8; 5]) {               //the mixer output and every channel's raw level
+        self.mix.push(mix);
+        if let Some(stems) = self.stems.as_mut() {
+            let [p1, p2, t, n, d] = channels.map(usize::from);
+            let levels = [PULSE_TABLE[p1], PULSE_TABLE[p2], TND_TABLE[3 * t], TND_TABLE[2 * n], TND_TABLE[d]];
+            for (stem, level) in stems.iter_mut().zip(levels) {
+                stem.push(level);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {                                              //samples captured so far
+        self.mix.pending()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn take_samples(&mut self) -> Vec<f32> {                              //for streaming the capture out bit by bit, the stems aren't touched
+        self.mix.take_samples()
+    }
+
+    pub fn into_recording(mut self) -> Recording {
+        Recording {
+            sample_rate: self.sample_rate,
+            mix: self.mix.take_samples(),
+            stems: self.stems.map(|stems| stems.into_iter().map(|mut stem| stem.take_samples()).collect()),
+        }
+    }
+}
+
+pub struct Recording {
+    pub sample_rate: u32,
+    pub mix: Vec<f32>,
+    pub stems: Option<Vec<Vec<f32>>>,                                         //in CHANNEL_NAMES order
+}
+
+impl Recording {
+    pub fn mix_wav(&self) -> Vec<u8> {
+        encode_wav(&self.mix, self.sample_rate)
+    }
+
+    pub fn stem_wavs(&self) -> Vec<(&'static str, Vec<u8>)> {                 //empty unless the capture was started with stems
+        match &self.stems {
+            Some(stems) => CHANNEL_NAMES
+                .iter()
+                .zip(stems)
+                .map(|(name, samples)| (*name, encode_wav(samples, self.sample_rate)))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.mix.len() as f64 / self.sample_rate as f64
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wav_header() {
+        let wav = encode_wav(&[0.0, 1.0, -2.0], 44_100);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 36 + 6);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32::from_le_bytes(wav[24..28].try_into().unwrap()), 44_100);
+        assert_eq!(u32::from_le_bytes(wav[28..32].try_into().unwrap()), 88_200);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[44..], [0, 0, 0xFF, 0x7F, 0x01, 0x80]);                //clipped to the 16 bit range
+    }
+}
@@ -0,0 +1,688 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::opcodes::CPU_OPS_CODES;
+use crate::CPU::AddressMode;
+
+/*
+    6502 ASSEMBLER
+    two passes over the source: the first one works out where every line goes and how big it
+    is, the second one evaluates the operands now that every label is known.
+
+        NAME = expr         constant
+        label:              the current address, can share a line with an instruction
+        .org expr           move the address forward, the gap is filled with zeros
+        .byte expr, "text"  .word expr, ...
+        ; comment
+
+    numbers are decimal, $hex, %binary or 'c', expressions take + - * / & | ^ << >> and
+    parentheses, unary - ~ < (low byte) > (high byte), and * for the current address.
+    an operand that fits in a byte uses zero page unless it is written with more than two hex
+    digits ($0010) or refers to a label that is only defined further down
+*/
+const DEFAULT_ORIGIN: u16 = 0x8000;                                           //where CPU::load puts programs
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,                                                          //1 based
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+//assembles a program, panicking on errors, for tests: cpu.load(asm!("LDA #$05", "TAX", "BRK"))
+#[macro_export]
+macro_rules! asm {
+    ($($line:expr),+ $(,)?) => {
+        $crate::assembler::assemble(&[$($line),+].join("\n")).unwrap_or_else(|err| panic!("{}", err))
+    };
+}
+
+//the operand syntax, AddressMode folds implied, accumulator, relative and indirect into NoneAddress
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl Mode {
+    fn operand_size(self) -> u16 {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::Indirect => 2,
+            _ => 1,
+        }
+    }
+
+    fn widen(self) -> Mode {
+        match self {
+            Mode::ZeroPage => Mode::Absolute,
+            Mode::ZeroPageX => Mode::AbsoluteX,
+            Mode::ZeroPageY => Mode::AbsoluteY,
+            mode => mode,
+        }
+    }
+}
+
+lazy_static! {
+    //OPCODES_MAP the other way round, documented opcodes only
+    static ref ASSEMBLY_MAP: HashMap<(&'static str, Mode), u8> = {
+        let mut map = HashMap::new();
+        for op in CPU_OPS_CODES.iter().filter(|op| !op.is_unofficial()) {
+            let mode = match (&op.mode, op.len, op.code) {
+                (_, _, 0x0a | 0x4a | 0x2a | 0x6a) => Mode::Accumulator,
+                (_, _, 0x6c) => Mode::Indirect,
+                (_, _, 0x20) => Mode::Absolute,
+                (AddressMode::Immeditate, _, _) => Mode::Immediate,
+                (AddressMode::ZeroPage, _, _) => Mode::ZeroPage,
+                (AddressMode::ZeroPageX, _, _) => Mode::ZeroPageX,
+                (AddressMode::ZeroPageY, _, _) => Mode::ZeroPageY,
+                (AddressMode::Absolute, _, _) => Mode::Absolute,
+                (AddressMode::AbsoluteX, _, _) => Mode::AbsoluteX,
+                (AddressMode::AbsoluteY, _, _) => Mode::AbsoluteY,
+                (AddressMode::IndirectX, _, _) => Mode::IndirectX,
+                (AddressMode::IndirectY, _, _) => Mode::IndirectY,
+                (AddressMode::NoneAddress, 2, _) => Mode::Relative,
+                (AddressMode::NoneAddress, _, _) => Mode::Implied,
+            };
+            map.insert((op.mnemonic, mode), op.code);
+        }
+        map
+    };
+}
+
+fn opcode_for(mnemonic: &str, mode: Mode) -> Option<u8> {
+    ASSEMBLY_MAP.get(&(mnemonic, mode)).copied()
+}
+
+/*
+    EXPRESSIONS
+
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Value {
+    value: i32,
+    known: bool,                                                              //false when a label isn't defined yet (first pass only)
+    wide: bool,                                                               //written as a 16 bit literal, or not known yet
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i32, bool),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let digits_from = |start: usize, radix: u32| {
+            let end = (start..chars.len()).find(|&j| !chars[j].is_digit(radix)).unwrap_or(chars.len());
+            let digits: String = chars[start..end].iter().collect();
+            (digits, end)
+        };
+        match c {
+            ' ' | '\t' => i += 1,
+            '$' | '%' => {
+                let radix = if c == '$' { 16 } else { 2 };
+                let (digits, end) = digits_from(i + 1, radix);
+                let value = i32::from_str_radix(&digits, radix).map_err(|_| format!("bad number in '{}'", text))?;
+                tokens.push(Token::Number(value, radix == 16 && digits.len() > 2));
+                i = end;
+            }
+            '0' if chars.get(i + 1) == Some(&'x') => {
+                let (digits, end) = digits_from(i + 2, 16);
+                let value = i32::from_str_radix(&digits, 16).map_err(|_| format!("bad number in '{}'", text))?;
+                tokens.push(Token::Number(value, digits.len() > 2));
+                i = end;
+            }
+            '0'..='9' => {
+                let (digits, end) = digits_from(i, 10);
+                let value = digits.parse().map_err(|_| format!("bad number in '{}'", text))?;
+                tokens.push(Token::Number(value, false));
+                i = end;
+            }
+            '\'' => match (chars.get(i + 1), chars.get(i + 2)) {
+                (Some(&ch), Some('\'')) => {
+                    tokens.push(Token::Number(ch as i32, false));
+                    i += 3;
+                }
+                _ => return Err(format!("bad character literal in '{}'", text)),
+            },
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            _ if c.is_alphabetic() || c == '_' || c == '@' || c == '.' => {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].is_alphanumeric() || chars[j] == '_' || chars[j] == '@' || chars[j] == '.'))
+                    .unwrap_or(chars.len());
+                tokens.push(Token::Name(chars[i..end].iter().collect()));
+                i = end;
+            }
+            _ => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let op = match two.as_str() {
+                    "<<" => "<<",
+                    ">>" => ">>",
+                    _ => match c {
+                        '+' => "+",
+                        '-' => "-",
+                        '*' => "*",
+                        '/' => "/",
+                        '&' => "&",
+                        '|' => "|",
+                        '^' => "^",
+                        '~' => "~",
+                        '<' => "<",
+                        '>' => ">",
+                        _ => return Err(format!("unexpected '{}' in '{}'", c, text)),
+                    },
+                };
+                tokens.push(Token::Op(op));
+                i += op.len();
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    symbols: &'a HashMap<String, i32>,
+    pc: u16,
+    final_pass: bool,
+}
+
+const BINARY_OPERATORS: [&[&str]; 6] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/"]];
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Value, String> {
+        if level == BINARY_OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek().cloned() {
+            if !BINARY_OPERATORS[level].contains(&op) {
+                break;
+            }
+            self.pos += 1;
+            let right = self.binary(level + 1)?;
+            let (a, b) = (left.value, right.value);
+            let value = match op {
+                "|" => a | b,
+                "^" => a ^ b,
+                "&" => a & b,
+                "<<" => a.wrapping_shl(b as u32),
+                ">>" => a.wrapping_shr(b as u32),
+                "+" => a.wrapping_add(b),
+                "-" => a.wrapping_sub(b),
+                "*" => a.wrapping_mul(b),
+                _ if b == 0 && right.known => return Err("division by zero".to_string()),
+                _ => a.checked_div(b).unwrap_or(0),
+            };
+            left = Value { value, known: left.known && right.known, wide: left.wide || right.wide };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        match self.peek().cloned() {
+            Some(Token::Op(op @ ("-" | "~" | "<" | ">"))) => {
+                self.pos += 1;
+                let inner = self.unary()?;
+                let value = match op {
+                    "-" => inner.value.wrapping_neg(),
+                    "~" => !inner.value,
+                    "<" => inner.value & 0xFF,
+                    _ => (inner.value >> 8) & 0xFF,
+                };
+                let wide = inner.wide && !matches!(op, "<" | ">");                 //a byte select always fits in zero page
+                Ok(Value { value, known: inner.known, wide })
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Value, String> {
+        let token = self.tokens.get(self.pos).cloned().ok_or("expression ends too early")?;
+        self.pos += 1;
+        match token {
+            Token::Number(value, wide) => Ok(Value { value, known: true, wide }),
+            Token::Op("*") => Ok(Value { value: self.pc as i32, known: true, wide: true }),
+            Token::Name(name) => match self.symbols.get(&name) {
+                Some(&value) => Ok(Value { value, known: true, wide: value > 0xFF }),
+                None if self.final_pass => Err(format!("unknown label '{}'", name)),
+                None => Ok(Value { value: 0, known: false, wide: true }),
+            },
+            Token::Open => {
+                let inner = self.binary(0)?;
+                match self.tokens.get(self.pos) {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    }
+                    _ => Err("missing ')'".to_string()),
+                }
+            }
+            other => Err(format!("unexpected {:?}", other)),
+        }
+    }
+}
+
+fn evaluate(text: &str, symbols: &HashMap<String, i32>, pc: u16, final_pass: bool) -> Result<Value, String> {
+    let mut evaluator = Evaluator { tokens: tokenize(text)?, pos: 0, symbols, pc, final_pass };
+    if evaluator.tokens.is_empty() {
+        return Err("missing operand".to_string());
+    }
+    let value = evaluator.binary(0)?;
+    if evaluator.pos != evaluator.tokens.len() {
+        return Err(format!("can't make sense of '{}'", text.trim()));
+    }
+    Ok(value)
+}
+
+/*
+    SOURCE LINES
+
+*/
+enum Item {
+    Instruction { opcode: u8, mode: Mode, operand: String },
+    Bytes(Vec<String>),                                                       //expressions and "strings"
+    Words(Vec<String>),
+    Org,
+}
+
+struct Placed {
+    line: usize,
+    pc: u16,
+    item: Item,
+}
+
+fn strip_comment(line: &str) -> &str {                                        //a ; inside quotes isn't a comment
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ';') => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn split_list(text: &str) -> Vec<String> {                                    //splits on commas outside quotes
+    let mut items = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ',') => {
+                items.push(current.trim().to_string());
+                current.clear();
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    items.push(current.trim().to_string());
+    items
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '@' || c == '.')
+}
+
+fn byte_item_size(item: &str) -> u16 {
+    if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+        item.len() as u16 - 2
+    } else {
+        1
+    }
+}
+
+//works out the operand syntax and the expression inside it
+fn parse_operand(mnemonic: &str, operand: &str) -> (Mode, String) {
+    let operand = operand.trim();
+    let upper = operand.to_ascii_uppercase();
+    let is_branch = opcode_for(mnemonic, Mode::Relative).is_some();
+    if operand.is_empty() {
+        let mode = if opcode_for(mnemonic, Mode::Accumulator).is_some() { Mode::Accumulator } else { Mode::Implied };
+        return (mode, String::new());
+    }
+    if upper == "A" && opcode_for(mnemonic, Mode::Accumulator).is_some() {
+        return (Mode::Accumulator, String::new());
+    }
+    if let Some(value) = operand.strip_prefix('#') {
+        return (Mode::Immediate, value.to_string());
+    }
+    if operand.starts_with('(') {
+        let compact: String = upper.chars().filter(|c| !c.is_whitespace()).collect();
+        if compact.ends_with(",X)") {
+            let inner = &operand[1..operand.rfind(',').unwrap()];
+            return (Mode::IndirectX, inner.to_string());
+        }
+        if compact.ends_with("),Y") {
+            let inner = &operand[1..operand.rfind(')').unwrap()];
+            return (Mode::IndirectY, inner.to_string());
+        }
+        if mnemonic == "JMP" && compact.ends_with(')') {
+            return (Mode::Indirect, operand[1..operand.len() - 1].to_string());
+        }
+    }
+    if let Some((base, index)) = operand.rsplit_once(',') {
+        match index.trim().to_ascii_uppercase().as_str() {
+            "X" => return (Mode::ZeroPageX, base.to_string()),
+            "Y" => return (Mode::ZeroPageY, base.to_string()),
+            _ => {}
+        }
+    }
+    if is_branch {
+        return (Mode::Relative, operand.to_string());
+    }
+    (Mode::ZeroPage, operand.to_string())
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut symbols: HashMap<String, i32> = HashMap::new();
+    let mut placed: Vec<Placed> = vec![];
+    let mut origin: Option<u16> = None;
+    let mut pc = DEFAULT_ORIGIN as u32;
+
+    //first pass, where everything goes
+    for (index, raw) in source.lines().enumerate() {
+        let line = index + 1;
+        let err = |message: String| AsmError { line, message };
+        let mut text = strip_comment(raw).trim();
+
+        while let Some((label, rest)) = text.split_once(':') {                //labels, possibly followed by more on the same line
+            let label = label.trim();
+            if !is_identifier(label) {
+                break;
+            }
+            if symbols.insert(label.to_string(), pc as i32).is_some() {
+                return Err(err(format!("'{}' is defined twice", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        if let Some((name, value)) = text.split_once('=') {
+            let name = name.trim();
+            if is_identifier(name) {
+                let value = evaluate(value, &symbols, pc as u16, false).map_err(err)?;
+                if !value.known {
+                    return Err(err(format!("'{}' uses a label that isn't defined yet", name)));
+                }
+                if symbols.insert(name.to_string(), value.value).is_some() {
+                    return Err(err(format!("'{}' is defined twice", name)));
+                }
+                continue;
+            }
+        }
+
+        let (word, rest) = match text.split_once(char::is_whitespace) {
+            Some((word, rest)) => (word, rest.trim()),
+            None => (text, ""),
+        };
+        let keyword = word.to_ascii_uppercase();
+        let item = match keyword.as_str() {
+            ".ORG" => {
+                let value = evaluate(rest, &symbols, pc as u16, false).map_err(err)?;
+                if !value.known || !(0..=0xFFFF).contains(&value.value) {
+                    return Err(err(".org needs an address that is known up front".to_string()));
+                }
+                if origin.is_some() && (value.value as u32) < pc {
+                    return Err(err(format!(".org ${:04X} goes backwards", value.value)));
+                }
+                pc = value.value as u32;
+                origin.get_or_insert(pc as u16);
+                Item::Org
+            }
+            ".BYTE" | ".DB" => Item::Bytes(split_list(rest)),
+            ".WORD" | ".DW" => Item::Words(split_list(rest)),
+            _ if keyword.starts_with('.') => return Err(err(format!("unknown directive '{}'", word))),
+            _ => {
+                let (mut mode, operand) = parse_operand(&keyword, rest);
+                if matches!(mode, Mode::ZeroPage | Mode::ZeroPageX | Mode::ZeroPageY) {
+                    let value = evaluate(&operand, &symbols, pc as u16, false).map_err(err)?;
+                    let fits = !value.wide && (0..=0xFF).contains(&value.value);
+                    if !fits || opcode_for(&keyword, mode).is_none() {
+                        mode = mode.widen();
+                    }
+                }
+                let opcode = opcode_for(&keyword, mode)
+                    .ok_or_else(|| err(format!("{} has no {:?} form", keyword, mode)))?;
+                Item::Instruction { opcode, mode, operand }
+            }
+        };
+
+        let size = match &item {
+            Item::Instruction { mode, .. } => 1 + mode.operand_size() as u32,
+            Item::Bytes(items) => items.iter().map(|i| byte_item_size(i) as u32).sum(),
+            Item::Words(items) => 2 * items.len() as u32,
+            Item::Org => 0,
+        };
+        origin.get_or_insert(pc as u16);
+        placed.push(Placed { line, pc: pc as u16, item });
+        pc += size;
+        if pc > 0x10000 {
+            return Err(err("program runs past $FFFF".to_string()));
+        }
+    }
+
+    //second pass, the bytes
+    let origin = origin.unwrap_or(DEFAULT_ORIGIN);
+    let mut output: Vec<u8> = vec![];
+    for Placed { line, pc, item } in placed {
+        let err = |message: String| AsmError { line, message };
+        let eval = |text: &str| evaluate(text, &symbols, pc, true).map(|v| v.value).map_err(err);
+        output.resize((pc - origin) as usize, 0);
+
+        match item {
+            Item::Org => {}
+            Item::Instruction { opcode, mode, operand } => {
+                output.push(opcode);
+                match mode {
+                    Mode::Implied | Mode::Accumulator => {}
+                    Mode::Relative => {
+                        let target = eval(&operand)?;
+                        let offset = target - (pc as i32 + 2);
+                        if !(-128..=127).contains(&offset) {
+                            return Err(err(format!("branch to ${:04X} is {} bytes away, too far", target, offset)));
+                        }
+                        output.push(offset as i8 as u8);
+                    }
+                    _ if mode.operand_size() == 1 => {
+                        let value = eval(&operand)?;
+                        if !(-128..=0xFF).contains(&value) {
+                            return Err(err(format!("{} doesn't fit in a byte", value)));
+                        }
+                        output.push(value as u8);
+                    }
+                    _ => {
+                        let value = eval(&operand)?;
+                        if !(0..=0xFFFF).contains(&value) {
+                            return Err(err(format!("{} isn't an address", value)));
+                        }
+                        output.extend_from_slice(&(value as u16).to_le_bytes());
+                    }
+                }
+            }
+            Item::Bytes(items) => {
+                for item in items {
+                    if item.len() >= 2 && item.starts_with('"') && item.ends_with('"') {
+                        output.extend_from_slice(&item.as_bytes()[1..item.len() - 1]);
+                        continue;
+                    }
+                    let value = eval(&item)?;
+                    if !(-128..=0xFF).contains(&value) {
+                        return Err(err(format!("{} doesn't fit in a byte", value)));
+                    }
+                    output.push(value as u8);
+                }
+            }
+            Item::Words(items) => {
+                for item in items {
+                    let value = eval(&item)?;
+                    if !(0..=0xFFFF).contains(&value) {
+                        return Err(err(format!("{} isn't an address", value)));
+                    }
+                    output.extend_from_slice(&(value as u16).to_le_bytes());
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::disasm;
+    use crate::opcodes::CPU_OPS_CODES;
+
+    #[test]
+    fn test_macro_and_basic_program() {
+        assert_eq!(asm!("LDA #$c0 \n TAX \n INX \n BRK"), vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
+        assert_eq!(asm!("lda #5", "sta $10", "brk"), vec![0xa9, 0x05, 0x85, 0x10, 0x00]);
+    }
+
+    #[test]
+    fn test_every_documented_opcode_round_trips() {
+        for op in CPU_OPS_CODES.iter().filter(|op| !op.is_unofficial()) {
+            let bytes = [op.code, 0x34, 0x12];
+            let ins = disasm::decode(&bytes, 0x8000).unwrap();
+            let source = format!(".org $8000\n{}", ins.text(&|_| None));
+            let assembled = assemble(&source).unwrap_or_else(|e| panic!("{}: {}", source, e));
+            assert_eq!(assembled, ins.bytes(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_zero_page_or_absolute() {
+        assert_eq!(assemble("LDA $10").unwrap(), vec![0xa5, 0x10]);
+        assert_eq!(assemble("LDA $0010").unwrap(), vec![0xad, 0x10, 0x00]);
+        assert_eq!(assemble("LDA $10,Y").unwrap(), vec![0xb9, 0x10, 0x00]);   //there is no zero page,Y form of LDA
+        assert_eq!(assemble("ptr = $20\nLDA (ptr),Y").unwrap(), vec![0xb1, 0x20]);
+        assert_eq!(assemble("LDA later\nlater = $10").unwrap(), vec![0xad, 0x10, 0x00]);  //not known in time for zero page
+        assert_eq!(assemble("JMP later\nlater: RTS").unwrap(), vec![0x4c, 0x03, 0x80, 0x60]);
+    }
+
+    #[test]
+    fn test_labels_branches_and_directives() {
+        let program = assemble(
+            "
+            .org $C000
+            start:  LDX #3          ; count down
+            loop:   DEX
+                    BNE loop
+                    JSR sub
+                    JMP start
+            sub:    RTS
+            .org $C010
+            table:  .byte 1, $ff, -1, \"AB\", <table, >table
+                    .word start, table + 2
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![
+                0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x20, 0x0b, 0xc0, 0x4c, 0x00, 0xc0, 0x60, 0, 0, 0, 0,
+                0x01, 0xff, 0xff, 0x41, 0x42, 0x10, 0xc0, 0x00, 0xc0, 0x12, 0xc0,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        assert_eq!(assemble(".byte 1 + 2 * 3, (1 + 2) * 3, %1010 | 1, 'A', ~0 & $0f, 1 << 4").unwrap(), vec![
+            7, 9, 11, 65, 15, 16
+        ]);
+        assert_eq!(assemble(".org $9000\n.word *, * + 2").unwrap(), vec![0x00, 0x90, 0x02, 0x90]);
+        assert_eq!(assemble("base = $0300\nLDA base + 4,X").unwrap(), vec![0xbd, 0x04, 0x03]);
+    }
+
+    #[test]
+    fn test_errors_carry_line_numbers() {
+        let err = assemble("NOP\nLDA #1\nFOO $10").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(assemble("BNE far\n.org $8100\nfar: RTS").is_err());
+        assert!(assemble("STX $1234,X").is_err());
+        assert!(assemble("LDA unknown").is_err());
+        assert!(assemble("a: NOP\na: NOP").is_err());
+        assert_eq!(assemble("LDA #$100").unwrap_err().to_string(), "line 1: 256 doesn't fit in a byte");
+        assert_eq!(assemble("NOP\n.word $12345").unwrap_err().to_string(), "line 2: 74565 isn't an address");
+        assert_eq!(assemble(".word $8000, -70000").unwrap_err().to_string(), "line 1: -70000 isn't an address");
+    }
+
+    #[test]
+    fn test_disassembly_reassembles() {
+        let mut prg = assemble(
+            "
+            .org $C000
+            reset:  SEI
+                    LDA #$10
+                    STA $2000
+                    LDA ($20),Y
+                    ASL A
+                    JMP ($0300)
+            nmi:    INC $10,X
+                    BIT $2002
+                    BPL nmi
+                    RTI
+            ",
+        )
+        .unwrap();
+        prg.extend_from_slice(&[0x04, 0x10, 0x02]);                          //an undocumented NOP then junk
+        prg.resize(0x4000 - 6, 0xff);
+        prg.extend_from_slice(&[0x0c, 0xc0, 0x00, 0xc0, 0x0c, 0xc0]);
+        let listing = disasm::disassemble(&prg, 0xC000, &[]);
+        assert_eq!(assemble(&listing).unwrap(), prg);
+    }
+}
@@ -0,0 +1,60 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use nes_emulator_rust::bus::Bus;
+use nes_emulator_rust::cartridge::Rom;
+use nes_emulator_rust::debugger::{Command, Debugger};
+
+/*
+    interactive debugger, `debugger game.nes` then `help` for the commands.
+    an empty line repeats the last command so stepping is just pressing enter
+*/
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("usage: debugger <rom.nes>");
+            process::exit(2);
+        }
+    };
+    let bus = Rom::from_file(&path).and_then(Bus::with_rom).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+
+    let mut dbg = Debugger::new(bus);
+    println!("{}", dbg.execute(Command::Registers));
+
+    let stdin = io::stdin();
+    let mut last: Option<Command> = None;
+    loop {
+        print!("(nesdbg) ");
+        let _ = io::stdout().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) | Err(_) => break,                                          //end of input
+            Ok(_) => {}
+        }
+
+        let command = if line.trim().is_empty() {
+            match last.clone() {
+                Some(command) => command,
+                None => continue,
+            }
+        } else {
+            match Command::parse(&line) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("{}", err);
+                    continue;
+                }
+            }
+        };
+        if command == Command::Quit {
+            break;
+        }
+        println!("{}", dbg.execute(command.clone()));
+        last = Some(command);
+    }
+}
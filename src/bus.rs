@@ -0,0 +1,520 @@
+use crate::apu::Apu;
+use crate::cartridge::{Rom, RomError};
+use crate::cheats::Cheats;
+use crate::joypad::Joypad;
+use crate::mapper::{self, SharedMapper};
+use crate::ppu::NesPPU;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use crate::CPU::Mem;
+
+/*
+    CPU MEMORY MAP (https://www.nesdev.org/wiki/CPU_memory_map)
+    $0000 - $07FF   2KiB internal RAM, mirrored three more times up to $1FFF
+    $2000 - $2007   PPU registers, mirrored every 8 bytes up to $3FFF
+    $4000 - $4017   APU and I/O registers
+    $4018 - $401F   APU and I/O test mode, disabled on a retail NES
+    $4020 - $FFFF   cartridge space (PRG RAM, PRG ROM and mapper registers)
+*/
+const RAM: u16 = 0x0000;
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const PPU_REGISTERS: u16 = 0x2000;
+const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const APU_REGISTERS: u16 = 0x4000;
+const APU_REGISTERS_END: u16 = 0x4013;
+const OAM_DMA: u16 = 0x4014;
+const APU_STATUS: u16 = 0x4015;
+const JOYPAD_1: u16 = 0x4016;
+const JOYPAD_2: u16 = 0x4017;                                                 //writes go to the APU frame counter
+const DMC_DMA_STALL: u16 = 4;
+const JOYPAD_OPEN_BUS_MASK: u8 = 0b1110_0000;                                 //the controller ports only drive the low bits
+const CARTRIDGE_SPACE: u16 = 0x4020;
+const PRG_RAM: u16 = 0x6000;
+
+bitflags! {
+    //every device that can pull the shared IRQ line low gets its own bit
+    pub struct IrqSource: u8 {
+        const EXTERNAL              = 0b00000001;
+        const APU_FRAME             = 0b00000010;
+        const DMC                   = 0b00000100;
+        const MAPPER                = 0b00001000;
+    }
+}
+
+pub struct Bus {
+    cpu_vram: [u8; 2048],
+    ppu: NesPPU,
+    apu: Apu,
+    joypad1: Joypad,
+    joypad2: Joypad,
+    open_bus: u8,                                                             //last value seen on the CPU data bus
+    cartridge: Vec<u8>,                                                       //flat cartridge space so raw programs can be loaded at $8000
+    rom: Option<Rom>,
+    mapper: Option<SharedMapper>,                                             //when a ROM is inserted its mapper answers for $6000-$FFFF
+    nmi_line: bool,
+    nmi_pending: bool,                                                        //latched on the rising edge of nmi_line until the CPU takes it
+    irq_sources: IrqSource,
+    cycles: u64,                                                              //CPU cycles seen through tick, OAM DMA needs to know if it starts on an odd one
+    stall_cycles: u16,                                                        //cycles the CPU is held off the bus by DMA
+    cheats: Cheats,                                                           //left out of save states, they belong to the player
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        Bus {
+            cpu_vram: [0; 2048],
+            ppu: NesPPU::new_empty_rom(),
+            apu: Apu::default(),
+            joypad1: Joypad::new(),
+            joypad2: Joypad::new(),
+            open_bus: 0,
+            cartridge: vec![0; 0x10000 - CARTRIDGE_SPACE as usize],
+            rom: None,
+            mapper: None,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_sources: IrqSource::empty(),
+            cycles: 0,
+            stall_cycles: 0,
+            cheats: Cheats::new(),
+        }
+    }
+
+    pub fn ppu(&self) -> &NesPPU {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut NesPPU {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn joypad1(&self) -> &Joypad {
+        &self.joypad1
+    }
+
+    pub fn joypad1_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    pub fn joypad2(&self) -> &Joypad {
+        &self.joypad2
+    }
+
+    pub fn joypad2_mut(&mut self) -> &mut Joypad {
+        &mut self.joypad2
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    pub fn reset(&mut self) {                                                 //the reset button, RAM and the cartridge keep what they hold
+        self.ppu.write_to_ctrl(0);
+        self.ppu.write_to_mask(0);
+        self.set_nmi_line(self.ppu.nmi_line());
+        self.apu.write_register(APU_STATUS, 0);                               //every channel goes quiet
+        self.update_apu_irqs();
+    }
+
+    pub fn set_nmi_line(&mut self, asserted: bool) {                          //only a transition to asserted latches an NMI
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    pub fn set_irq(&mut self, source: IrqSource, asserted: bool) {
+        self.irq_sources.set(source, asserted);
+    }
+
+    pub fn irq_sources(&self) -> IrqSource {
+        self.irq_sources
+    }
+
+    pub fn with_rom(rom: Rom) -> Result<Self, RomError> {
+        let mapper = mapper::mapper_for(&rom)?;
+        let mut bus = Bus::new();
+        bus.ppu = NesPPU::with_mapper(mapper.clone());
+        bus.mapper = Some(mapper);
+        bus.rom = Some(rom);
+        Ok(bus)
+    }
+
+    pub fn rom(&self) -> Option<&Rom> {
+        self.rom.as_ref()
+    }
+
+    pub fn mapper(&self) -> Option<&SharedMapper> {
+        self.mapper.as_ref()
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {                                     //reads memory without the side effects of reading I/O registers
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_vram[(addr & 0b0000_0111_1111_1111) as usize],
+            CARTRIDGE_SPACE ..= 0xFFFF => match &self.mapper {
+                Some(mapper) if addr >= PRG_RAM => mapper.borrow_mut().cpu_read(addr),
+                _ => self.cartridge[(addr - CARTRIDGE_SPACE) as usize],
+            },
+            _ => 0xFF,                                                        //PPU, APU and I/O registers aren't touched
+        }
+    }
+
+    fn update_mapper_irq(&mut self) {
+        let asserted = self.mapper.as_ref().is_some_and(|mapper| mapper.borrow().irq());
+        self.set_irq(IrqSource::MAPPER, asserted);
+    }
+
+    fn oam_dma(&mut self, page: u8) {                                         //copies $XX00-$XXFF into OAM, the CPU is stalled for 513 or 514 cycles
+        let mut buffer: [u8; 256] = [0; 256];
+        let hi: u16 = (page as u16) << 8;
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.mem_read(hi + i as u16);
+        }
+        self.ppu.write_oam_dma(&buffer);
+        self.stall_cycles += 513 + (self.cycles % 2) as u16;
+    }
+
+    fn update_apu_irqs(&mut self) {
+        self.set_irq(IrqSource::APU_FRAME, self.apu.frame_irq());
+        self.set_irq(IrqSource::DMC, self.apu.dmc_irq());
+    }
+
+    fn service_dmc_dma(&mut self) {                                           //the DMC reads its sample bytes straight off the CPU bus
+        if let Some(addr) = self.apu.dmc_sample_request() {
+            let data = self.mem_read(addr);
+            self.apu.provide_dmc_sample(data);
+            self.stall_cycles += DMC_DMA_STALL;
+        }
+    }
+
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;           //only the low 11 bits select a byte in the 2KiB of RAM
+                self.cpu_vram[mirror_down_addr as usize]
+            }
+            0x2002 => {
+                let data = self.ppu.read_status();
+                self.set_nmi_line(self.ppu.nmi_line());                       //reading the status ends the vblank flag
+                data
+            }
+            0x2004 => self.ppu.read_oam_data(),
+            0x2007 => self.ppu.read_data(),
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.ppu.read_open_bus(),
+            0x2008 ..= PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+                self.mem_read(mirror_down_addr)
+            }
+            APU_STATUS => {
+                let data = self.apu.read_status();
+                self.update_apu_irqs();                                       //reading the status acknowledges the frame IRQ
+                data
+            }
+            JOYPAD_1 => (self.open_bus & JOYPAD_OPEN_BUS_MASK) | self.joypad1.read(),
+            JOYPAD_2 => (self.open_bus & JOYPAD_OPEN_BUS_MASK) | self.joypad2.read(),
+            APU_REGISTERS ..= APU_REGISTERS_END | OAM_DMA => self.open_bus,   //write only
+            CARTRIDGE_SPACE ..= 0xFFFF => match &self.mapper {
+                Some(mapper) if addr >= PRG_RAM => mapper.borrow_mut().cpu_read(addr),
+                _ => self.cartridge[(addr - CARTRIDGE_SPACE) as usize],
+            },
+            _ => {                                                            //test mode registers read back as 0
+                0
+            }
+        };
+        let data = self.cheats.apply(addr, data);
+        self.open_bus = data;
+        data
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.open_bus = data;
+        match addr {
+            RAM ..= RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0000_0111_1111_1111;
+                self.cpu_vram[mirror_down_addr as usize] = data;
+            }
+            PPU_REGISTERS => {
+                self.ppu.write_to_ctrl(data);
+                self.set_nmi_line(self.ppu.nmi_line());                       //enabling NMI during vblank fires one straight away
+            }
+            0x2001 => self.ppu.write_to_mask(data),
+            0x2002 => {}                                                      //PPUSTATUS is read only
+            0x2003 => self.ppu.write_to_oam_addr(data),
+            0x2004 => self.ppu.write_to_oam_data(data),
+            0x2005 => self.ppu.write_to_scroll(data),
+            0x2006 => self.ppu.write_to_ppu_addr(data),
+            0x2007 => self.ppu.write_to_data(data),
+            0x2008 ..= PPU_REGISTERS_MIRRORS_END => {
+                let mirror_down_addr = addr & 0b0010_0000_0000_0111;
+                self.mem_write(mirror_down_addr, data);
+            }
+            OAM_DMA => self.oam_dma(data),
+            APU_REGISTERS ..= APU_REGISTERS_END | APU_STATUS | JOYPAD_2 => {
+                self.apu.write_register(addr, data);
+                self.update_apu_irqs();
+            }
+            JOYPAD_1 => {                                                     //the strobe is wired to both ports
+                self.joypad1.write(data);
+                self.joypad2.write(data);
+            }
+            CARTRIDGE_SPACE ..= 0xFFFF => match &self.mapper {
+                Some(mapper) if addr >= PRG_RAM => {
+                    mapper.borrow_mut().cpu_write(addr, data);
+                    self.update_mapper_irq();                                 //MMC3 acknowledges its IRQ through a register write
+                }
+                _ => self.cartridge[(addr - CARTRIDGE_SPACE) as usize] = data,
+            },
+            _ => {
+                //writes to the test mode registers are ignored
+            }
+        }
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        Bus::peek(self, addr)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.cycles += cycles as u64;
+        self.ppu.tick(cycles as u16 * 3);
+        self.set_nmi_line(self.ppu.nmi_line());
+        self.apu.tick(cycles as u16);
+        self.service_dmc_dma();
+        self.update_apu_irqs();
+        self.update_mapper_irq();
+    }
+
+    fn take_stall_cycles(&mut self) -> u16 {
+        let stall = self.stall_cycles;
+        self.stall_cycles = 0;
+        stall
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        let pending = self.nmi_pending;
+        self.nmi_pending = false;
+        pending
+    }
+
+    fn irq_line(&mut self) -> bool {
+        !self.irq_sources.is_empty()
+    }
+}
+
+impl Snapshot for Bus {                                                       //the PPU, APU, joypads and cartridge get chunks of their own
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.cpu_vram);
+        w.u8(self.open_bus);
+        w.bool(self.nmi_line);
+        w.bool(self.nmi_pending);
+        w.u8(self.irq_sources.bits());
+        w.u64(self.cycles);
+        w.u16(self.stall_cycles);
+        w.bytes(if self.rom.is_none() { &self.cartridge } else { &[] });      //raw programs can write anywhere in cartridge space
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.cpu_vram)?;
+        self.open_bus = r.u8()?;
+        self.nmi_line = r.bool()?;
+        self.nmi_pending = r.bool()?;
+        self.irq_sources = IrqSource::from_bits_truncate(r.u8()?);
+        self.cycles = r.u64()?;
+        self.stall_cycles = r.u16()?;
+        if self.rom.is_none() {
+            r.bytes_into(&mut self.cartridge)?;
+        } else {
+            r.bytes_into(&mut [])?;
+        }
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_ram_mirroring() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0012, 0xab);
+        assert_eq!(bus.mem_read(0x0812), 0xab);
+        assert_eq!(bus.mem_read(0x1012), 0xab);
+        assert_eq!(bus.mem_read(0x1812), 0xab);
+
+        bus.mem_write(0x1fff, 0xcd);
+        assert_eq!(bus.mem_read(0x07ff), 0xcd);
+    }
+
+    #[test]
+    fn test_ppu_register_mirroring() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x3ffe, 0x21);                                          //$3ffe is PPUADDR
+        bus.mem_write(0x200e, 0x05);
+        bus.mem_write(0x2fff, 0x12);                                          //$2fff is PPUDATA
+        assert_eq!(bus.ppu().vram[bus.ppu().mirror_vram_addr(0x2105) as usize], 0x12);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut bus = Bus::new();
+        for i in 0..256u16 {
+            bus.mem_write(0x0200 + i, i as u8);
+        }
+        bus.mem_write(0x4014, 0x02);
+        assert_eq!(bus.ppu().oam_data[0x10], 0x10);
+        assert_eq!(bus.ppu().oam_data[0xff], 0xff);
+        assert_eq!(bus.take_stall_cycles(), 513);
+        assert_eq!(bus.take_stall_cycles(), 0);
+    }
+
+    #[test]
+    fn test_vblank_raises_nmi() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x2000, 0b1000_0000);
+        for _ in 0..(241 * 341 / 3 / 100 + 1) {
+            bus.tick(100);
+        }
+        assert!(bus.poll_nmi());
+        assert_eq!(bus.mem_read(0x2002) >> 7, 1);
+        assert_eq!(bus.mem_read(0x2002) >> 7, 0);
+    }
+
+    #[test]
+    fn test_cartridge_space_covers_top_of_memory() {
+        let mut bus = Bus::new();
+        bus.mem_write_u16(0xfffe, 0x1234);
+        assert_eq!(bus.mem_read_u16(0xfffe), 0x1234);
+        assert_eq!(bus.mem_read(0x4018), 0);
+    }
+
+    #[test]
+    fn test_rom_is_mapped_at_8000() {
+        let mut bus = Bus::with_rom(test_rom(vec![0xa9, 0x05, 0x00])).unwrap();
+        assert_eq!(bus.mem_read(0x8000), 0xa9);
+        assert_eq!(bus.mem_read(0xc000), 0xa9);                               //16KiB of PRG ROM is mirrored
+        assert_eq!(bus.mem_read_u16(0xfffc), 0x8000);
+
+        bus.mem_write(0x8000, 0xff);
+        assert_eq!(bus.mem_read(0x8000), 0xa9);
+
+        bus.mem_write(0x6000, 0x12);                                          //PRG RAM is still writable
+        assert_eq!(bus.mem_read(0x6000), 0x12);
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let mut bus = Bus::new();
+        bus.set_nmi_line(true);
+        assert!(bus.poll_nmi());
+        assert!(!bus.poll_nmi());
+
+        bus.set_nmi_line(true);                                               //holding the line doesn't fire again
+        assert!(!bus.poll_nmi());
+        bus.set_nmi_line(false);
+        bus.set_nmi_line(true);
+        assert!(bus.poll_nmi());
+    }
+
+    #[test]
+    fn test_irq_is_level_triggered() {
+        let mut bus = Bus::new();
+        bus.set_irq(IrqSource::APU_FRAME, true);
+        bus.set_irq(IrqSource::MAPPER, true);
+        assert!(bus.irq_line());
+        assert!(bus.irq_line());
+
+        bus.set_irq(IrqSource::APU_FRAME, false);
+        assert!(bus.irq_line());
+        bus.set_irq(IrqSource::MAPPER, false);
+        assert!(!bus.irq_line());
+    }
+
+    #[test]
+    fn test_apu_drives_irq_and_dmc_dma() {
+        let mut bus = Bus::new();
+        for _ in 0..(29830 / 100 + 1) {
+            bus.tick(100);
+        }
+        assert!(bus.irq_sources().contains(IrqSource::APU_FRAME));
+        assert_eq!(bus.mem_read(0x4015) & 0x40, 0x40);
+        assert!(!bus.irq_line());
+
+        bus.mem_write(0xc000, 0xaa);
+        bus.mem_write(0x4010, 0b1000_0000);
+        bus.mem_write(0x4013, 0x00);
+        bus.mem_write(0x4015, 0b0001_0000);
+        bus.tick(1);
+        assert_eq!(bus.take_stall_cycles(), 4);
+        assert!(bus.irq_sources().contains(IrqSource::DMC));
+    }
+
+    #[test]
+    fn test_joypad_reads_with_open_bus() {
+        use crate::joypad::JoypadButton;
+
+        let mut bus = Bus::new();
+        bus.joypad1_mut().set_buttons(JoypadButton::BUTTON_A | JoypadButton::UP);
+        bus.joypad2_mut().set_button_pressed_status(JoypadButton::BUTTON_B, true);
+        bus.mem_write(0x4016, 1);
+        bus.mem_write(0x4016, 0);
+
+        bus.mem_write(0x0000, 0x40);                                          //the high byte of $4016 is what an LDA leaves on the bus
+        bus.mem_read(0x0000);
+        let reads: Vec<u8> = (0..8).map(|_| bus.mem_read(0x4016)).collect();
+        assert_eq!(reads, vec![0x41, 0x40, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40]);
+        assert_eq!(bus.mem_read(0x4016), 0x41);
+
+        bus.mem_read(0x0000);
+        assert_eq!(bus.mem_read(0x4017), 0x40);
+        assert_eq!(bus.mem_read(0x4017), 0x41);
+    }
+
+    #[test]
+    fn test_mmc3_counts_scanlines_through_ppu_a12() {
+        let mut bus = Bus::with_rom(crate::mapper::test::banked_rom(4, 2, 1)).unwrap();
+        bus.mem_write(0xc000, 10);                                            //IRQ latch
+        bus.mem_write(0xc001, 0);
+        bus.mem_write(0xe001, 0);
+        bus.mem_write(0x2000, 0b0000_1000);                                   //sprites from $1000, background from $0000
+        bus.mem_write(0x2001, 0b0001_1000);
+
+        for _ in 0..(10 * 341 / 3) {                                          //the 11th rise reloads then counts down to 0 on scanline 10
+            bus.tick(1);
+        }
+        assert!(!bus.irq_line());
+        for _ in 0..(341 / 3 + 1) {
+            bus.tick(1);
+        }
+        assert!(bus.irq_sources().contains(IrqSource::MAPPER));
+
+        bus.mem_write(0xe000, 0);
+        assert!(!bus.irq_line());
+    }
+}
@@ -0,0 +1,475 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    iNES AND NES 2.0 ROM FILES
+    Both formats start with the same 16 byte header, NES 2.0 reuses bytes 8-15 for the extended fields
+    https://www.nesdev.org/wiki/INES
+    https://www.nesdev.org/wiki/NES_2.0
+
+*/
+const NES_TAG: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];                             //"NES" followed by MS-DOS end of file
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+pub const PRG_ROM_PAGE_SIZE: usize = 16384;
+pub const CHR_ROM_PAGE_SIZE: usize = 8192;
+const DEFAULT_PRG_RAM_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Vertical,
+    Horizontal,
+    FourScreen,
+    SingleScreenLower,                                                        //mapper controlled, every nametable shows the same 1KiB
+    SingleScreenUpper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TvRegion {
+    Ntsc,
+    Pal,
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFormat {
+    INes,
+    Nes2,
+}
+
+#[derive(Debug)]
+pub enum RomError {
+    InvalidMagic,                                                             //file does not start with "NES\x1A"
+    Truncated { expected: usize, actual: usize },                            //header promised more PRG/CHR data than the file holds
+    NoPrgRom,                                                                 //header says there is no PRG ROM, so no reset vector either
+    PrgRomTooSmall { size: usize, minimum: usize },                          //less than one PRG bank of the mapper
+    UnsupportedMapper(u16),
+    Io(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::InvalidMagic => write!(f, "file is not in iNES file format"),
+            RomError::Truncated { expected, actual } => {
+                write!(f, "ROM file is truncated, expected {} bytes but found {}", expected, actual)
+            }
+            RomError::NoPrgRom => write!(f, "ROM has no PRG ROM"),
+            RomError::PrgRomTooSmall { size, minimum } => {
+                write!(f, "PRG ROM is {} bytes but the mapper needs at least {}", size, minimum)
+            }
+            RomError::UnsupportedMapper(id) => write!(f, "mapper {} is not supported", id),
+            RomError::Io(err) => write!(f, "could not read ROM file: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Rom {
+    pub format: RomFormat,
+    pub prg_rom: Vec<u8>,
+    pub chr_rom: Vec<u8>,
+    pub trainer: Option<Vec<u8>>,                                             //512 bytes that get copied to $7000 before the game starts
+    pub mapper: u16,
+    pub submapper: u8,
+    pub screen_mirroring: Mirroring,
+    pub battery: bool,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub tv_region: TvRegion,
+}
+
+impl Rom {
+    pub fn new(raw: &[u8]) -> Result<Rom, RomError> {
+        if raw.len() < HEADER_SIZE {
+            return Err(RomError::Truncated { expected: HEADER_SIZE, actual: raw.len() });
+        }
+        if raw[0..4] != NES_TAG {
+            return Err(RomError::InvalidMagic);
+        }
+
+        let format = if raw[7] & 0b0000_1100 == 0b0000_1000 {
+            RomFormat::Nes2
+        } else {
+            RomFormat::INes
+        };
+
+        let four_screen = raw[6] & 0b1000 != 0;
+        let vertical_mirroring = raw[6] & 0b1 != 0;
+        let screen_mirroring = match (four_screen, vertical_mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+        let battery = raw[6] & 0b10 != 0;
+        let has_trainer = raw[6] & 0b100 != 0;
+
+        let (rom, prg_rom_size, chr_rom_size) = match format {
+            RomFormat::Nes2 => Rom::parse_nes2_header(raw, screen_mirroring, battery),
+            RomFormat::INes => Rom::parse_ines_header(raw, screen_mirroring, battery),
+        };
+        rom.with_data(raw, has_trainer, prg_rom_size, chr_rom_size)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Rom, RomError> {
+        let raw = fs::read(path)?;
+        Rom::new(&raw)
+    }
+
+    fn parse_ines_header(raw: &[u8], screen_mirroring: Mirroring, battery: bool) -> (Rom, usize, usize) {
+        //old dumping tools wrote junk like "DiskDude!" into bytes 7-15, in that case only trust byte 6
+        let dirty_header = raw[12..16].iter().any(|&b| b != 0);
+        let mapper_high = if dirty_header { 0 } else { raw[7] & 0b1111_0000 };
+        let mapper = (mapper_high | (raw[6] >> 4)) as u16;
+
+        let prg_ram_size = if raw[8] == 0 { DEFAULT_PRG_RAM_SIZE } else { raw[8] as usize * DEFAULT_PRG_RAM_SIZE };
+        let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
+
+        let rom = Rom {
+            format: RomFormat::INes,
+            prg_rom: vec![],
+            chr_rom: vec![],
+            trainer: None,
+            mapper,
+            submapper: 0,
+            screen_mirroring,
+            battery,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { CHR_ROM_PAGE_SIZE } else { 0 },   //boards without CHR ROM have 8KiB of CHR RAM
+            chr_nvram_size: 0,
+            tv_region: if !dirty_header && raw[9] & 1 != 0 { TvRegion::Pal } else { TvRegion::Ntsc },
+        };
+        (rom, raw[4] as usize * PRG_ROM_PAGE_SIZE, chr_rom_size)
+    }
+
+    fn parse_nes2_header(raw: &[u8], screen_mirroring: Mirroring, battery: bool) -> (Rom, usize, usize) {
+        let mapper = ((raw[8] & 0x0F) as u16) << 8
+            | (raw[7] & 0xF0) as u16
+            | (raw[6] >> 4) as u16;
+
+        let prg_rom_size = nes2_rom_size(raw[4], raw[9] & 0x0F, PRG_ROM_PAGE_SIZE);
+        let chr_rom_size = nes2_rom_size(raw[5], raw[9] >> 4, CHR_ROM_PAGE_SIZE);
+
+        let tv_region = match raw[12] & 0b11 {
+            0 => TvRegion::Ntsc,
+            1 => TvRegion::Pal,
+            2 => TvRegion::MultiRegion,
+            _ => TvRegion::Dendy,
+        };
+
+        let rom = Rom {
+            format: RomFormat::Nes2,
+            prg_rom: vec![],
+            chr_rom: vec![],
+            trainer: None,
+            mapper,
+            submapper: raw[8] >> 4,
+            screen_mirroring,
+            battery,
+            prg_ram_size: nes2_ram_size(raw[10] & 0x0F),
+            prg_nvram_size: nes2_ram_size(raw[10] >> 4),
+            chr_ram_size: nes2_ram_size(raw[11] & 0x0F),
+            chr_nvram_size: nes2_ram_size(raw[11] >> 4),
+            tv_region,
+        };
+        (rom, prg_rom_size, chr_rom_size)
+    }
+
+    //fills in the trainer, PRG and CHR data that follow the header. the sizes come from the header
+    //and can be absurd, so they are checked against the file before anything is allocated
+    fn with_data(mut self, raw: &[u8], has_trainer: bool, prg_rom_size: usize, chr_rom_size: usize) -> Result<Rom, RomError> {
+        if prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+        let trainer_size = if has_trainer { TRAINER_SIZE } else { 0 };
+        let prg_rom_start = HEADER_SIZE + trainer_size;
+        let chr_rom_start = prg_rom_start.saturating_add(prg_rom_size);
+        let end = chr_rom_start.saturating_add(chr_rom_size);                 //usize::MAX when the sizes don't fit, which no file reaches
+
+        if raw.len() < end {
+            return Err(RomError::Truncated { expected: end, actual: raw.len() });
+        }
+
+        if has_trainer {
+            self.trainer = Some(raw[HEADER_SIZE..prg_rom_start].to_vec());
+        }
+        self.prg_rom = raw[prg_rom_start..chr_rom_start].to_vec();
+        self.chr_rom = raw[chr_rom_start..end].to_vec();
+        Ok(self)
+    }
+}
+
+fn nes2_rom_size(lsb: u8, msb: u8, page_size: usize) -> usize {
+    if msb == 0x0F {
+        //exponent-multiplier notation, size = 2^E * (MM * 2 + 1) bytes, up to 2^63 * 7 which doesn't fit
+        let exponent = (lsb >> 2) as u32;
+        let multiplier = (lsb & 0b11) as usize * 2 + 1;
+        2usize.checked_pow(exponent).and_then(|size| size.checked_mul(multiplier)).unwrap_or(usize::MAX)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * page_size
+    }
+}
+
+fn nes2_ram_size(shift: u8) -> usize {                                        //RAM sizes are stored as a shift count, 64 << shift bytes
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+impl Snapshot for Mirroring {                                                 //mappers that switch mirroring save it with their registers
+    fn save(&self, w: &mut StateWriter) {
+        w.u8(*self as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        *self = match r.u8()? {
+            0 => Mirroring::Vertical,
+            1 => Mirroring::Horizontal,
+            2 => Mirroring::FourScreen,
+            3 => Mirroring::SingleScreenLower,
+            4 => Mirroring::SingleScreenUpper,
+            other => return Err(StateError::Corrupt(format!("unknown mirroring {}", other))),
+        };
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    pub struct TestRom {
+        pub header: Vec<u8>,
+        pub trainer: Option<Vec<u8>>,
+        pub prg_rom: Vec<u8>,
+        pub chr_rom: Vec<u8>,
+    }
+
+    pub fn create_rom(rom: TestRom) -> Vec<u8> {
+        let mut result = Vec::with_capacity(
+            rom.header.len()
+                + rom.trainer.as_ref().map_or(0, |t| t.len())
+                + rom.prg_rom.len()
+                + rom.chr_rom.len(),
+        );
+
+        result.extend(&rom.header);
+        if let Some(t) = rom.trainer {
+            result.extend(t);
+        }
+        result.extend(&rom.prg_rom);
+        result.extend(&rom.chr_rom);
+
+        result
+    }
+
+    pub fn test_rom(program: Vec<u8>) -> Rom {                              //NROM with the program at the start of a single 16KiB PRG bank
+        let mut prg_rom = program;
+        prg_rom.resize(PRG_ROM_PAGE_SIZE, 0);
+        prg_rom[0x3FFC] = 0x00;                                              //reset vector points at $8000
+        prg_rom[0x3FFD] = 0x80;
+
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom,
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        Rom::new(&raw).unwrap()
+    }
+
+    //reads controller 1 in every NMI and leaves the buttons in $10 (JoypadButton bits), a running
+    //sum of them in $11, the NMI count in $12 and a history from $0200, so RAM depends on every input so far
+    pub fn joypad_echo_rom() -> Rom {
+        test_rom(crate::asm!(
+            "        LDA #$80",
+            "        STA $2000",
+            "loop:   JMP loop",
+            "nmi:    LDA #$01",
+            "        STA $4016",
+            "        LDA #$00",
+            "        STA $4016",
+            "        LDX #$08",
+            "read:   LDA $4016",
+            "        LSR A",
+            "        ROR $10",
+            "        DEX",
+            "        BNE read",
+            "        LDA $10",
+            "        CLC",
+            "        ADC $11",
+            "        STA $11",
+            "        INC $12",
+            "        LDY $12",
+            "        LDA $10",
+            "        STA $0200,Y",
+            "        RTI",
+            "        .org $BFFA",
+            "        .word nmi, $8000, $8000",
+        ))
+    }
+
+    #[test]
+    fn test_ines() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::INes);
+        assert_eq!(rom.chr_rom, vec![2; CHR_ROM_PAGE_SIZE]);
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 3);
+        assert_eq!(rom.screen_mirroring, Mirroring::Vertical);
+        assert_eq!(rom.prg_ram_size, DEFAULT_PRG_RAM_SIZE);
+        assert_eq!(rom.chr_ram_size, 0);
+        assert_eq!(rom.tv_region, TvRegion::Ntsc);
+    }
+
+    #[test]
+    fn test_with_trainer_and_battery() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x4E, 0x00, 00, 0x01, 00, 00, 00, 00, 00, 00],
+            trainer: Some(vec![7; TRAINER_SIZE]),
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.trainer, Some(vec![7; TRAINER_SIZE]));
+        assert_eq!(rom.prg_rom, vec![1; 2 * PRG_ROM_PAGE_SIZE]);
+        assert_eq!(rom.mapper, 4);
+        assert_eq!(rom.screen_mirroring, Mirroring::FourScreen);
+        assert!(rom.battery);
+        assert_eq!(rom.prg_nvram_size, DEFAULT_PRG_RAM_SIZE);
+        assert_eq!(rom.chr_ram_size, CHR_ROM_PAGE_SIZE);
+        assert_eq!(rom.tv_region, TvRegion::Pal);
+    }
+
+    #[test]
+    fn test_dirty_ines_header_ignores_byte_7() {
+        let mut header = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x10, 0x44];
+        header.extend(b"iskDude!");
+        let raw = create_rom(TestRom {
+            header,
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+        assert_eq!(rom.mapper, 1);
+    }
+
+    #[test]
+    fn test_nes2() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x11, 0x08, 0x21, 0x00, 0x70, 0x07, 0x03, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        let rom = Rom::new(&raw).unwrap();
+
+        assert_eq!(rom.format, RomFormat::Nes2);
+        assert_eq!(rom.mapper, 0x101);
+        assert_eq!(rom.submapper, 2);
+        assert_eq!(rom.prg_ram_size, 0);
+        assert_eq!(rom.prg_nvram_size, 8192);
+        assert_eq!(rom.chr_ram_size, 8192);
+        assert_eq!(rom.tv_region, TvRegion::Dendy);
+    }
+
+    #[test]
+    fn test_nes2_exponent_size() {
+        assert_eq!(nes2_rom_size(0b0000_1101, 0x0F, PRG_ROM_PAGE_SIZE), 8 * 3);
+        assert_eq!(nes2_rom_size(0x02, 0x01, PRG_ROM_PAGE_SIZE), 0x102 * PRG_ROM_PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x00, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+
+        assert!(matches!(Rom::new(&raw), Err(RomError::InvalidMagic)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![],
+        });
+
+        assert!(matches!(
+            Rom::new(&raw),
+            Err(RomError::Truncated { expected, actual }) if expected == HEADER_SIZE + 2 * PRG_ROM_PAGE_SIZE + CHR_ROM_PAGE_SIZE && actual == raw.len()
+        ));
+        assert!(matches!(Rom::new(&raw[..4]), Err(RomError::Truncated { .. })));
+    }
+
+    #[test]
+    fn test_huge_nes2_size_is_truncated() {
+        //PRG size in exponent form, 2^63 * 7 bytes, which must not be allocated
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0xFF, 0x01, 0x00, 0x08, 00, 0x0F, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![1; PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert_eq!(nes2_rom_size(0xFF, 0x0F, PRG_ROM_PAGE_SIZE), usize::MAX);
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated { expected: usize::MAX, .. })));
+
+        //2^62 bytes fits in a usize but still not in the file
+        let mut raw = raw;
+        raw[4] = 62 << 2;
+        assert!(matches!(Rom::new(&raw), Err(RomError::Truncated { expected, .. }) if expected > 1 << 62));
+    }
+
+    #[test]
+    fn test_no_prg_rom() {
+        let raw = create_rom(TestRom {
+            header: vec![0x4E, 0x45, 0x53, 0x1A, 0x00, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00],
+            trainer: None,
+            prg_rom: vec![],
+            chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+        });
+        assert!(matches!(Rom::new(&raw), Err(RomError::NoPrgRom)));
+    }
+}
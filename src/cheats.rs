@@ -0,0 +1,269 @@
+use std::fmt;
+
+/*
+    CHEATS
+    both kinds work on what the CPU reads through Mem, memory itself is never changed so
+    turning a cheat off puts the game straight back the way it was. the PPU and Bus::peek
+    still see the real contents
+
+    GAME GENIE (https://www.nesdev.org/wiki/Game_Genie)
+    a 6 letter code replaces the byte read from a PRG ROM address, an 8 letter code only
+    does so while the ROM holds the compare value there, which keeps it from firing when
+    a different bank is switched in. every letter is 4 bits, scrambled into 15 bits of
+    address, 8 of value and 8 of compare
+
+    PRO ACTION REPLAY
+    "AAAA:VV" or "AAAAVV" in hex freezes RAM or PRG RAM at AAAA to VV
+
+    CHEAT FILES
+    one code per line, anything after it is its name and # starts a comment. a code with
+    a - in front is loaded switched off
+
+        SXIOPO          infinite lives
+        -075A:09        start with 9 lives
+*/
+const GAME_GENIE_LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+const RAM_MIRRORS_END: u16 = 0x1FFF;
+const RAM_MASK: u16 = 0x07FF;
+const PRG_RAM: u16 = 0x6000;
+const ROM_START: u16 = 0x8000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CheatError {
+    BadCode(String),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheatError::BadCode(code) => write!(f, "'{}' is not a Game Genie or Pro Action Replay code", code),
+            CheatError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for CheatError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    Patch { addr: u16, value: u8, compare: Option<u8> },                      //Game Genie, on PRG ROM
+    Freeze { addr: u16, value: u8 },                                          //Pro Action Replay, on RAM or PRG RAM
+}
+
+impl CheatKind {
+    fn apply(&self, addr: u16, data: u8) -> Option<u8> {
+        match *self {
+            CheatKind::Patch { addr: at, value, compare } if at == addr && compare.is_none_or(|c| c == data) => Some(value),
+            CheatKind::Freeze { addr: at, value } if at == addr => Some(value),
+            _ => None,
+        }
+    }
+}
+
+pub fn decode_game_genie(code: &str) -> Option<CheatKind> {
+    let letters = code
+        .bytes()
+        .map(|letter| GAME_GENIE_LETTERS.iter().position(|&known| known == letter.to_ascii_uppercase()).map(|n| n as u16))
+        .collect::<Option<Vec<u16>>>()?;
+    if letters.len() != 6 && letters.len() != 8 {
+        return None;
+    }
+    let n = |i: usize| letters[i];
+    let addr = ROM_START
+        | ((n(3) & 7) << 12)
+        | ((n(4) & 8) << 8)
+        | ((n(5) & 7) << 8)
+        | ((n(1) & 8) << 4)
+        | ((n(2) & 7) << 4)                                                   //bit 3 of the third letter only tells the Game Genie the code is 8 long
+        | (n(3) & 8)
+        | (n(4) & 7);
+    let mut value = ((n(1) & 7) << 4) | ((n(0) & 8) << 4) | (n(0) & 7);
+    let compare = if letters.len() == 6 {
+        value |= n(5) & 8;
+        None
+    } else {
+        value |= n(7) & 8;
+        Some((((n(7) & 7) << 4) | ((n(6) & 8) << 4) | (n(6) & 7) | (n(5) & 8)) as u8)
+    };
+    Some(CheatKind::Patch { addr, value: value as u8, compare })
+}
+
+pub fn decode_action_replay(code: &str) -> Option<CheatKind> {
+    let digits = code.replace(':', "");
+    if digits.len() != 6 || !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    let addr = u16::from_str_radix(&digits[..4], 16).ok()?;
+    let value = u8::from_str_radix(&digits[4..], 16).ok()?;
+    (addr <= RAM_MASK || (PRG_RAM..ROM_START).contains(&addr)).then_some(CheatKind::Freeze { addr, value })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub name: String,
+    pub kind: CheatKind,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    pub fn parse(code: &str) -> Result<Cheat, CheatError> {
+        let kind = decode_game_genie(code)
+            .or_else(|| decode_action_replay(code))
+            .ok_or_else(|| CheatError::BadCode(code.to_string()))?;
+        Ok(Cheat { code: code.to_ascii_uppercase(), name: String::new(), kind, enabled: true })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    active: Vec<CheatKind>,                                                   //the enabled ones, so the read path doesn't check every cheat's flag
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats::default()
+    }
+
+    pub fn parse_file(text: &str) -> Result<Vec<Cheat>, CheatError> {
+        let mut cheats = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let cheat = Cheat::parse(code).map_err(|err| CheatError::Parse { line: index + 1, message: err.to_string() })?;
+            cheats.push(Cheat { name: name.trim().to_string(), enabled, ..cheat });
+        }
+        Ok(cheats)
+    }
+
+    pub fn load(&mut self, text: &str) -> Result<usize, CheatError> {         //adds to the cheats already there, all or nothing
+        let cheats = Cheats::parse_file(text)?;
+        let count = cheats.len();
+        self.cheats.extend(cheats);
+        self.update();
+        Ok(count)
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        self.update();
+        self.cheats.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        let cheat = (index < self.cheats.len()).then(|| self.cheats.remove(index));
+        self.update();
+        cheat
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.update();
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {      //false when there is no such cheat
+        match self.cheats.get_mut(index) {
+            Some(cheat) => cheat.enabled = enabled,
+            None => return false,
+        }
+        self.update();
+        true
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    pub fn apply(&self, addr: u16, data: u8) -> u8 {                          //what the CPU reads instead of data
+        let addr = if addr <= RAM_MIRRORS_END { addr & RAM_MASK } else { addr };
+        self.active.iter().rev().find_map(|cheat| cheat.apply(addr, data)).unwrap_or(data)
+    }
+
+    fn update(&mut self) {
+        self.active = self.cheats.iter().filter(|cheat| cheat.enabled).map(|cheat| cheat.kind).collect();
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::test_rom;
+    use crate::CPU::Mem;
+
+    #[test]
+    fn test_decode_game_genie() {
+        //Super Mario Bros., DEC lives becomes LDA
+        assert_eq!(decode_game_genie("SXIOPO"), Some(CheatKind::Patch { addr: 0x91D9, value: 0xAD, compare: None }));
+        assert_eq!(decode_game_genie("aaaaaa"), Some(CheatKind::Patch { addr: 0x8000, value: 0x00, compare: None }));
+        assert_eq!(
+            decode_game_genie("NNNNNNNN"),
+            Some(CheatKind::Patch { addr: 0xFFFF, value: 0xFF, compare: Some(0xFF) })
+        );
+        assert_eq!(decode_game_genie("SXIOP"), None);
+        assert_eq!(decode_game_genie("SXIOPB"), None);
+        assert_eq!(decode_action_replay("075A:09"), Some(CheatKind::Freeze { addr: 0x075A, value: 0x09 }));
+        assert_eq!(decode_action_replay("60ff10"), Some(CheatKind::Freeze { addr: 0x60FF, value: 0x10 }));
+        assert_eq!(decode_action_replay("8000:01"), None);
+        assert_eq!(decode_action_replay("2000:01"), None);
+    }
+
+    #[test]
+    fn test_cheat_file() {
+        let cheats = Cheats::parse_file("# smb\nSXIOPO   infinite lives\n\n-075A:09 start with 9 lives\n").unwrap();
+        assert_eq!(cheats.len(), 2);
+        assert_eq!((cheats[0].name.as_str(), cheats[0].enabled), ("infinite lives", true));
+        assert_eq!((cheats[1].code.as_str(), cheats[1].enabled), ("075A:09", false));
+        let error = Cheats::parse_file("SXIOPO\nhello").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: 'hello' is not a Game Genie or Pro Action Replay code");
+    }
+
+    #[test]
+    fn test_cheats_on_the_read_path() {
+        let program = crate::asm!("        LDA $0300", "        LDA #$42", "        NOP");
+        let mut bus = Bus::with_rom(test_rom(program)).unwrap();
+        bus.mem_write(0x0300, 0x11);
+        let patch = |value: u8, compare: Option<u8>| Cheat {
+            code: String::new(),
+            name: String::new(),
+            kind: CheatKind::Patch { addr: 0x8004, value, compare },
+            enabled: true,
+        };
+
+        bus.cheats_mut().add(patch(0x99, None));
+        assert_eq!(bus.mem_read(0x8004), 0x99);                               //the operand of LDA #$42
+        assert_eq!(bus.peek(0x8004), 0x42);
+        bus.cheats_mut().clear();
+        bus.cheats_mut().add(patch(0x99, Some(0x41)));
+        assert_eq!(bus.mem_read(0x8004), 0x42);                               //compare doesn't match
+        bus.cheats_mut().add(patch(0x77, Some(0x42)));
+        assert_eq!(bus.mem_read(0x8004), 0x77);
+
+        let freeze = bus.cheats_mut().add(Cheat::parse("0300:05").unwrap());
+        assert_eq!(bus.mem_read(0x0B00), 0x05);                               //mirrors are frozen too
+        bus.mem_write(0x0300, 0x22);
+        assert_eq!(bus.mem_read(0x0300), 0x05);
+        assert!(bus.cheats_mut().set_enabled(freeze, false));
+        assert_eq!(bus.mem_read(0x0300), 0x22);
+        assert!(!bus.cheats_mut().set_enabled(9, false));
+        assert_eq!(bus.cheats().list().len(), 3);
+    }
+}
@@ -0,0 +1,538 @@
+pub mod watch;
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use crate::bus::Bus;
+use crate::disasm;
+use crate::CPU::{CpuFlags, Mem, StopReason, CPU};
+use watch::{Access, WatchBus, WatchHit};
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+const HISTORY: usize = 4;                                                     //instructions shown before the PC, disassembling backwards isn't reliable
+const DEFAULT_DUMP_LEN: u16 = 64;
+const DEFAULT_DISASSEMBLY_LINES: usize = 10;
+
+const FLAG_NAMES: [(CpuFlags, char); 8] = [
+    (CpuFlags::NEGTAIVE, 'N'),
+    (CpuFlags::OVERFLOW, 'V'),
+    (CpuFlags::BREAK2, 'U'),
+    (CpuFlags::BREAK, 'B'),
+    (CpuFlags::DECIMAL_MODE, 'D'),
+    (CpuFlags::INTERRUPT_DISABLE, 'I'),
+    (CpuFlags::ZERO, 'Z'),
+    (CpuFlags::CARRY, 'C'),
+];
+
+pub const HELP: &str = "\
+addresses and values are hex ($ and 0x are optional), counts are decimal
+  s, step [n]            run n instructions
+  n, next                step over a JSR
+  o, out                 run until the current subroutine returns
+  c, continue [frames]   run until a breakpoint, a watchpoint or the CPU halts
+  b, break <addr>        break when the PC reaches addr
+  bd, delete <addr>      remove a breakpoint
+  bl                     list breakpoints
+  w, watch <addr> [r|w|rw]  stop after the CPU reads and/or writes addr
+  wd, unwatch <addr>     remove a watchpoint
+  wl                     list watchpoints
+  r, regs                show registers and flags
+  x <addr> [len]         hex dump memory
+  d, dis [addr] [n]      disassemble, around the PC by default
+  set <a|x|y|sp|pc|p> <value>  change a register
+  m, poke <addr> <byte>..  change memory
+  h, help                this text
+  q, quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    P,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Step(u32),
+    Next,
+    Out,
+    Continue(Option<u64>),                                                    //optional frame limit
+    Break(u16),
+    Delete(u16),
+    Breakpoints,
+    Watch(u16, Access),
+    Unwatch(u16),
+    Watchpoints,
+    Registers,
+    Dump(u16, u16),
+    Disassemble(Option<u16>, usize),
+    Set(Register, u16),
+    Poke(u16, Vec<u8>),
+    Help,
+    Quit,
+}
+
+//why a run command gave control back to the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Break {
+    Done,                                                                     //the command finished normally
+    Breakpoint(u16),
+    Watchpoint(WatchHit),
+    Halted(StopReason),
+    FrameLimit,
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_hex(text)?;
+    u8::try_from(value).map_err(|_| format!("'{}' doesn't fit in a byte", text))
+}
+
+fn parse_count<T: std::str::FromStr>(text: &str) -> Result<T, String> {
+    text.parse().map_err(|_| format!("'{}' is not a count", text))
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((name, args)) => (name.to_ascii_lowercase(), args),
+            None => return Err("empty command".to_string()),
+        };
+        let addr = |index: usize| -> Result<u16, String> {
+            args.get(index).ok_or_else(|| format!("{} needs an address", name)).and_then(|arg| parse_hex(arg))
+        };
+
+        let command = match name.as_str() {
+            "s" | "step" => Command::Step(args.first().map(|n| parse_count(n)).transpose()?.unwrap_or(1)),
+            "n" | "next" => Command::Next,
+            "o" | "out" => Command::Out,
+            "c" | "continue" => Command::Continue(args.first().map(|n| parse_count(n)).transpose()?),
+            "b" | "break" => Command::Break(addr(0)?),
+            "bd" | "delete" => Command::Delete(addr(0)?),
+            "bl" => Command::Breakpoints,
+            "w" | "watch" => {
+                let access = match args.get(1).map(|a| a.to_ascii_lowercase()).as_deref() {
+                    None | Some("rw") => Access::READ | Access::WRITE,
+                    Some("r") => Access::READ,
+                    Some("w") => Access::WRITE,
+                    Some(other) => return Err(format!("watch kind '{}' should be r, w or rw", other)),
+                };
+                Command::Watch(addr(0)?, access)
+            }
+            "wd" | "unwatch" => Command::Unwatch(addr(0)?),
+            "wl" => Command::Watchpoints,
+            "r" | "regs" => Command::Registers,
+            "x" => Command::Dump(addr(0)?, args.get(1).map(|n| parse_count(n)).transpose()?.unwrap_or(DEFAULT_DUMP_LEN)),
+            "d" | "dis" => Command::Disassemble(
+                args.first().map(|a| parse_hex(a)).transpose()?,
+                args.get(1).map(|n| parse_count(n)).transpose()?.unwrap_or(DEFAULT_DISASSEMBLY_LINES),
+            ),
+            "set" => {
+                let register = match args.first().map(|r| r.to_ascii_lowercase()).as_deref() {
+                    Some("a") => Register::A,
+                    Some("x") => Register::X,
+                    Some("y") => Register::Y,
+                    Some("sp") => Register::Sp,
+                    Some("pc") => Register::Pc,
+                    Some("p") => Register::P,
+                    _ => return Err("set needs one of a, x, y, sp, pc or p".to_string()),
+                };
+                let value = addr(1)?;
+                if register != Register::Pc && value > 0xFF {
+                    return Err(format!("{:?} is only 8 bits wide", register));
+                }
+                Command::Set(register, value)
+            }
+            "m" | "poke" => {
+                let bytes = args.iter().skip(1).map(|b| parse_byte(b)).collect::<Result<Vec<u8>, String>>()?;
+                if bytes.is_empty() {
+                    return Err("poke needs at least one byte".to_string());
+                }
+                Command::Poke(addr(0)?, bytes)
+            }
+            "h" | "help" | "?" => Command::Help,
+            "q" | "quit" | "exit" => Command::Quit,
+            _ => return Err(format!("unknown command '{}', try help", name)),
+        };
+        Ok(command)
+    }
+}
+
+/*
+    DEBUGGER
+    drives a CPU whose bus is wrapped in a WatchBus, every run command goes one instruction
+    at a time through CPU::step so breakpoints and watchpoints are checked in between.
+    BRK is taken as a normal interrupt since real programs use it
+*/
+pub struct Debugger {
+    pub cpu: CPU<WatchBus<Bus>>,
+    breakpoints: BTreeSet<u16>,
+    history: VecDeque<u16>,                                                   //addresses of the last few instructions that ran
+}
+
+impl Debugger {
+    pub fn new(bus: Bus) -> Self {
+        let mut cpu = CPU::with_bus(WatchBus::new(bus));
+        cpu.reset();
+        Debugger { cpu, breakpoints: BTreeSet::new(), history: VecDeque::new() }
+    }
+
+    pub fn peek(&self, addr: u16) -> u8 {                                     //never trips a watchpoint or a read side effect
+        self.cpu.bus.inner.peek(addr)
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    /*
+        RUNNING
+
+    */
+    fn step_instruction(&mut self) -> Result<u8, Break> {                     //hands back the opcode that ran
+        let pc = self.cpu.program_counter;
+        let code = self.peek(pc);
+        if let Err(reason) = self.cpu.step() {
+            return Err(Break::Halted(reason));
+        }
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(pc);
+        match self.cpu.bus.take_hits().first() {
+            Some(hit) => Err(Break::Watchpoint(*hit)),
+            None => Ok(code),
+        }
+    }
+
+    //steps until done says so, the closure sees the opcode that just ran and the stack pointer before it
+    fn run_to<F>(&mut self, mut done: F) -> Break
+    where
+        F: FnMut(&CPU<WatchBus<Bus>>, u8, u8) -> bool,
+    {
+        loop {
+            let stack_pointer = self.cpu.stack_pointer;
+            let code = match self.step_instruction() {
+                Ok(code) => code,
+                Err(stop) => return stop,
+            };
+            if done(&self.cpu, code, stack_pointer) {
+                return Break::Done;
+            }
+            if self.breakpoints.contains(&self.cpu.program_counter) {
+                return Break::Breakpoint(self.cpu.program_counter);
+            }
+        }
+    }
+
+    pub fn step(&mut self, count: u32) -> Break {
+        let mut remaining = count;
+        self.run_to(|_, _, _| {
+            remaining = remaining.saturating_sub(1);
+            remaining == 0
+        })
+    }
+
+    pub fn step_over(&mut self) -> Break {                                    //a JSR runs until its RTS, anything else is a single step
+        if self.peek(self.cpu.program_counter) != JSR {
+            return self.step(1);
+        }
+        let return_addr = self.cpu.program_counter.wrapping_add(3);
+        let stack_pointer = self.cpu.stack_pointer;
+        self.run_to(|cpu, _, _| cpu.program_counter == return_addr && cpu.stack_pointer == stack_pointer)
+    }
+
+    pub fn step_out(&mut self) -> Break {                                     //runs until an RTS pops the frame we are in
+        let frame = self.cpu.stack_pointer;
+        self.run_to(|_, code, stack_pointer| code == RTS && stack_pointer >= frame)
+    }
+
+    pub fn resume(&mut self, frames: Option<u64>) -> Break {
+        let limit = frames.map(|frames| self.cpu.bus.inner.ppu().frame_count + frames);
+        let stop = self.run_to(|cpu, _, _| limit.is_some_and(|limit| cpu.bus.inner.ppu().frame_count >= limit));
+        match (stop, limit) {
+            (Break::Done, Some(_)) => Break::FrameLimit,
+            (stop, _) => stop,
+        }
+    }
+
+    /*
+        DISPLAY
+
+    */
+    pub fn registers(&self) -> String {
+        let cpu = &self.cpu;
+        let flags: String = FLAG_NAMES
+            .iter()
+            .map(|(flag, name)| if cpu.status.contains(*flag) { *name } else { name.to_ascii_lowercase() })
+            .collect();
+        let ppu = cpu.bus.inner.ppu();
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} {} CYC:{} PPU:{:>3},{:>3} FRAME:{}",
+            cpu.program_counter,
+            cpu.register_a,
+            cpu.register_x,
+            cpu.register_y,
+            cpu.stack_pointer,
+            cpu.status.bits(),
+            flags,
+            cpu.cycles,
+            ppu.scanline,
+            ppu.dot,
+            ppu.frame_count,
+        )
+    }
+
+    pub fn hex_dump(&self, addr: u16, len: u16) -> String {
+        let mut out = String::new();
+        let end = addr as u32 + len as u32;
+        let mut row = addr as u32;
+        while row < end {
+            let bytes: Vec<u8> = (row..end.min(row + 16)).map(|a| self.peek(a as u16)).collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            let _ = writeln!(out, "{:04X}  {:<47}  {}", row, hex.join(" "), text);
+            row += 16;
+        }
+        out.trim_end().to_string()
+    }
+
+    pub fn disassemble_one(&self, addr: u16) -> (String, u16) {               //the text and the length of the instruction at addr
+        let bytes: Vec<u8> = (0..3).map(|i| self.peek(addr.wrapping_add(i))).collect();
+        let ins = match disasm::decode(&bytes, addr) {
+            Some(ins) => ins,
+            None => return (format!("{:04X}  {:02X}        .byte ${:02X}", addr, bytes[0], bytes[0]), 1),
+        };
+        let symbol = |addr: u16| disasm::register_name(addr).map(str::to_string);
+        let hex: Vec<String> = ins.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+        (format!("{:04X}  {:8}  {}", addr, hex.join(" "), ins.text(&symbol)), ins.size())
+    }
+
+    pub fn disassemble(&self, start: Option<u16>, lines: usize) -> String {
+        let pc = self.cpu.program_counter;
+        let mut addrs: Vec<u16> = match start {
+            Some(_) => vec![],
+            None => self.history.iter().copied().filter(|&addr| addr != pc).collect(),
+        };
+        let mut addr = start.unwrap_or(pc);
+        while addrs.len() < lines {
+            addrs.push(addr);
+            addr = addr.wrapping_add(self.disassemble_one(addr).1);
+        }
+
+        let mut out = String::new();
+        for addr in addrs {
+            let marker = if addr == pc { '>' } else if self.breakpoints.contains(&addr) { '*' } else { ' ' };
+            let _ = writeln!(out, "{} {}", marker, self.disassemble_one(addr).0);
+        }
+        out.trim_end().to_string()
+    }
+
+    fn describe(&self, stop: Break) -> String {
+        let reason = match stop {
+            Break::Done => String::new(),
+            Break::Breakpoint(addr) => format!("breakpoint at ${:04X}\n", addr),
+            Break::Watchpoint(hit) => {
+                let kind = if hit.access.contains(Access::READ) { "read" } else { "write" };
+                format!("watchpoint: {} ${:04X} = {:02X}\n", kind, hit.addr, hit.value)
+            }
+            Break::Halted(reason) => format!("CPU halted: {:?}\n", reason),
+            Break::FrameLimit => "frame limit reached\n".to_string(),
+        };
+        format!("{}{}\n> {}", reason, self.registers(), self.disassemble_one(self.cpu.program_counter).0)
+    }
+
+    /*
+        COMMANDS
+
+    */
+    pub fn execute(&mut self, command: Command) -> String {
+        match command {
+            Command::Step(count) => {
+                let stop = self.step(count);
+                self.describe(stop)
+            }
+            Command::Next => {
+                let stop = self.step_over();
+                self.describe(stop)
+            }
+            Command::Out => {
+                let stop = self.step_out();
+                self.describe(stop)
+            }
+            Command::Continue(frames) => {
+                let stop = self.resume(frames);
+                self.describe(stop)
+            }
+            Command::Break(addr) => {
+                self.add_breakpoint(addr);
+                format!("breakpoint set at ${:04X}", addr)
+            }
+            Command::Delete(addr) => match self.remove_breakpoint(addr) {
+                true => format!("breakpoint at ${:04X} removed", addr),
+                false => format!("no breakpoint at ${:04X}", addr),
+            },
+            Command::Breakpoints => {
+                let list: Vec<String> = self.breakpoints.iter().map(|addr| format!("${:04X}", addr)).collect();
+                if list.is_empty() { "no breakpoints".to_string() } else { list.join("\n") }
+            }
+            Command::Watch(addr, access) => {
+                self.cpu.bus.watch(addr, access);
+                format!("watching ${:04X} ({:?})", addr, access)
+            }
+            Command::Unwatch(addr) => match self.cpu.bus.unwatch(addr) {
+                true => format!("watchpoint at ${:04X} removed", addr),
+                false => format!("no watchpoint at ${:04X}", addr),
+            },
+            Command::Watchpoints => {
+                let list: Vec<String> =
+                    self.cpu.bus.watches().map(|(addr, access)| format!("${:04X} {:?}", addr, access)).collect();
+                if list.is_empty() { "no watchpoints".to_string() } else { list.join("\n") }
+            }
+            Command::Registers => self.registers(),
+            Command::Dump(addr, len) => self.hex_dump(addr, len),
+            Command::Disassemble(addr, lines) => self.disassemble(addr, lines),
+            Command::Set(register, value) => {
+                match register {
+                    Register::A => self.cpu.register_a = value as u8,
+                    Register::X => self.cpu.register_x = value as u8,
+                    Register::Y => self.cpu.register_y = value as u8,
+                    Register::Sp => self.cpu.stack_pointer = value as u8,
+                    Register::Pc => self.cpu.program_counter = value,
+                    Register::P => self.cpu.status = CpuFlags::from_bits_truncate(value as u8),
+                }
+                self.registers()
+            }
+            Command::Poke(addr, bytes) => {                                   //goes to the real bus, so a write to ROM hits the mapper registers
+                for (i, byte) in bytes.iter().enumerate() {
+                    self.cpu.bus.inner.mem_write(addr.wrapping_add(i as u16), *byte);
+                }
+                self.hex_dump(addr, bytes.len() as u16)
+            }
+            Command::Help => HELP.to_string(),
+            Command::Quit => String::new(),
+        }
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn debugger(program: &[u8]) -> Debugger {
+        let mut bus = Bus::new();
+        for (i, byte) in program.iter().enumerate() {
+            bus.mem_write(0x8000 + i as u16, *byte);
+        }
+        bus.mem_write_u16(0xFFFC, 0x8000);
+        Debugger::new(bus)
+    }
+
+    //$8000 JSR $8010, INX, JMP $8003 / $8010 LDA #$07, STA $10, LDY $10, RTS
+    const PROGRAM: [u8; 23] = [
+        0x20, 0x10, 0x80, 0xe8, 0x4c, 0x03, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        0xa9, 0x07, 0x85, 0x10, 0xa4, 0x10, 0x60,
+    ];
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("step 20"), Ok(Command::Step(20)));
+        assert_eq!(Command::parse("b $C000"), Ok(Command::Break(0xC000)));
+        assert_eq!(Command::parse("w 0x10 w"), Ok(Command::Watch(0x10, Access::WRITE)));
+        assert_eq!(Command::parse("set pc 8000"), Ok(Command::Set(Register::Pc, 0x8000)));
+        assert_eq!(Command::parse("m 200 1 ff"), Ok(Command::Poke(0x200, vec![1, 0xff])));
+        assert_eq!(Command::parse("d"), Ok(Command::Disassemble(None, DEFAULT_DISASSEMBLY_LINES)));
+        assert!(Command::parse("set a 100").is_err());
+        assert!(Command::parse("b zz").is_err());
+        assert!(Command::parse("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_breakpoint_and_step_over() {
+        let mut dbg = debugger(&PROGRAM);
+        assert_eq!(dbg.step_over(), Break::Done);                             //the whole subroutine runs
+        assert_eq!(dbg.cpu.program_counter, 0x8003);
+        assert_eq!(dbg.cpu.register_y, 7);
+
+        dbg.add_breakpoint(0x8004);
+        assert_eq!(dbg.resume(None), Break::Breakpoint(0x8004));
+        assert_eq!(dbg.cpu.register_x, 1);
+        assert_eq!(dbg.resume(None), Break::Breakpoint(0x8004));              //around the loop once more
+        assert_eq!(dbg.cpu.register_x, 2);
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut dbg = debugger(&PROGRAM);
+        assert_eq!(dbg.step(2), Break::Done);                                 //into the subroutine and past LDA
+        assert_eq!(dbg.cpu.program_counter, 0x8012);
+        assert_eq!(dbg.step_out(), Break::Done);
+        assert_eq!(dbg.cpu.program_counter, 0x8003);
+        assert_eq!(dbg.cpu.stack_pointer, 0xFD);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut dbg = debugger(&PROGRAM);
+        dbg.execute(Command::Watch(0x10, Access::WRITE));
+        assert_eq!(
+            dbg.resume(None),
+            Break::Watchpoint(WatchHit { addr: 0x10, value: 7, access: Access::WRITE })
+        );
+        assert_eq!(dbg.cpu.program_counter, 0x8014);                          //stops after the STA
+
+        dbg.execute(Command::Watch(0x10, Access::READ));
+        assert_eq!(dbg.peek(0x10), 7);                                        //the debugger looking doesn't count
+        assert_eq!(dbg.step(5), Break::Watchpoint(WatchHit { addr: 0x10, value: 7, access: Access::READ }));
+        assert_eq!(dbg.cpu.program_counter, 0x8016);
+    }
+
+    #[test]
+    fn test_halt_is_reported() {
+        let mut dbg = debugger(&[0xe8, 0x02]);
+        assert_eq!(dbg.resume(None), Break::Halted(StopReason::Jammed { code: 0x02, address: 0x8001 }));
+    }
+
+    #[test]
+    fn test_edit_registers_and_memory() {
+        let mut dbg = debugger(&PROGRAM);
+        dbg.execute(Command::parse("set a 42").unwrap());
+        dbg.execute(Command::parse("set p 81").unwrap());
+        dbg.execute(Command::parse("m 300 de ad").unwrap());
+        assert_eq!(dbg.cpu.register_a, 0x42);
+        assert_eq!(dbg.peek(0x300), 0xde);
+        assert_eq!(dbg.peek(0x301), 0xad);
+        assert!(dbg.registers().starts_with("PC:8000 A:42 X:00 Y:00 SP:FD P:81 NvubdizC"));
+        assert_eq!(dbg.hex_dump(0x300, 2), "0300  DE AD                                            ..");
+    }
+
+    #[test]
+    fn test_disassembly_around_pc() {
+        let mut dbg = debugger(&PROGRAM);
+        dbg.step(2);
+        assert_eq!(
+            dbg.disassemble(None, 4),
+            "  8000  20 10 80  JSR $8010\n  8010  A9 07     LDA #$07\n> 8012  85 10     STA $10\n  8014  A4 10     LDY $10"
+        );
+        assert_eq!(dbg.disassemble_one(0x8004).0, "8004  4C 03 80  JMP $8003");
+    }
+}
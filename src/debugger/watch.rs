@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use crate::CPU::Mem;
+
+bitflags! {
+    pub struct Access: u8 {
+        const READ                  = 0b00000001;
+        const WRITE                 = 0b00000010;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub addr: u16,
+    pub value: u8,
+    pub access: Access,                                                       //exactly one of READ or WRITE
+}
+
+/*
+    WATCHPOINTS
+    sits between the CPU and the real bus so every access the CPU makes goes past it,
+    accesses to a watched address are queued up for the debugger to collect after the instruction.
+    the debugger itself goes around it through `inner` so looking at memory never trips a watch
+*/
+pub struct WatchBus<M: Mem> {
+    pub inner: M,
+    watches: BTreeMap<u16, Access>,
+    hits: Vec<WatchHit>,
+}
+
+impl<M: Mem> WatchBus<M> {
+    pub fn new(inner: M) -> Self {
+        WatchBus { inner, watches: BTreeMap::new(), hits: vec![] }
+    }
+
+    pub fn watch(&mut self, addr: u16, access: Access) {
+        self.watches.insert(addr, access);
+    }
+
+    pub fn unwatch(&mut self, addr: u16) -> bool {
+        self.watches.remove(&addr).is_some()
+    }
+
+    pub fn watches(&self) -> impl Iterator<Item = (u16, Access)> + '_ {
+        self.watches.iter().map(|(addr, access)| (*addr, *access))
+    }
+
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+
+    fn check(&mut self, addr: u16, value: u8, access: Access) {
+        if let Some(watched) = self.watches.get(&addr) {
+            if watched.contains(access) {
+                self.hits.push(WatchHit { addr, value, access });
+            }
+        }
+    }
+}
+
+impl<M: Mem> Mem for WatchBus<M> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let value = self.inner.mem_read(addr);
+        self.check(addr, value, Access::READ);
+        value
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.check(addr, data, Access::WRITE);
+        self.inner.mem_write(addr, data);
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.inner.peek(addr)
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.inner.tick(cycles);
+    }
+
+    fn take_stall_cycles(&mut self) -> u16 {
+        self.inner.take_stall_cycles()
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        self.inner.poll_nmi()
+    }
+
+    fn irq_line(&mut self) -> bool {
+        self.inner.irq_line()
+    }
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::opcodes::{self, OpCode};
+use crate::CPU::AddressMode;
+
+/*
+    6502 DISASSEMBLER
+    decode turns the bytes of one instruction into an Instruction, disassemble turns a whole
+    PRG range into a listing the assembler can read back:
+
+        RESET:
+            SEI                     ; C000  78
+            STA PPUCTRL             ; C001  8D 00 20
+
+    code is found by following the control flow from the entry points (and the vectors when
+    the range holds $FFFA-$FFFF), anything it never reaches is written out as .byte data.
+    without any entry point it falls back to a linear sweep where undocumented opcodes are data
+*/
+const VECTORS: u16 = 0xFFFA;
+const VECTOR_NAMES: [(u16, &str); 3] = [(0xFFFA, "NMI"), (0xFFFC, "RESET"), (0xFFFE, "IRQ")];
+const DATA_BYTES_PER_LINE: usize = 8;
+const COMMENT_COLUMN: usize = 28;
+
+const REGISTER_NAMES: [(u16, &str); 30] = [
+    (0x2000, "PPUCTRL"),
+    (0x2001, "PPUMASK"),
+    (0x2002, "PPUSTATUS"),
+    (0x2003, "OAMADDR"),
+    (0x2004, "OAMDATA"),
+    (0x2005, "PPUSCROLL"),
+    (0x2006, "PPUADDR"),
+    (0x2007, "PPUDATA"),
+    (0x4000, "SQ1_VOL"),
+    (0x4001, "SQ1_SWEEP"),
+    (0x4002, "SQ1_LO"),
+    (0x4003, "SQ1_HI"),
+    (0x4004, "SQ2_VOL"),
+    (0x4005, "SQ2_SWEEP"),
+    (0x4006, "SQ2_LO"),
+    (0x4007, "SQ2_HI"),
+    (0x4008, "TRI_LINEAR"),
+    (0x400A, "TRI_LO"),
+    (0x400B, "TRI_HI"),
+    (0x400C, "NOISE_VOL"),
+    (0x400E, "NOISE_LO"),
+    (0x400F, "NOISE_HI"),
+    (0x4010, "DMC_FREQ"),
+    (0x4011, "DMC_RAW"),
+    (0x4012, "DMC_START"),
+    (0x4013, "DMC_LEN"),
+    (0x4014, "OAMDMA"),
+    (0x4015, "SND_CHN"),
+    (0x4016, "JOY1"),
+    (0x4017, "JOY2"),
+];
+
+pub fn register_name(addr: u16) -> Option<&'static str> {                     //the usual names for the PPU, APU and I/O registers
+    REGISTER_NAMES.iter().find(|(reg, _)| *reg == addr).map(|(_, name)| *name)
+}
+
+//where execution can go after an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Branch(u16),                                                              //either the target or the next instruction
+    Jump(u16),
+    Call(u16),                                                                //the target, then back to the next instruction
+    Return,
+    Stop,                                                                     //JMP (ind), BRK and JAM, nowhere we can follow
+}
+
+#[derive(Clone, Copy)]
+pub struct Instruction {
+    pub addr: u16,
+    pub opcode: &'static OpCode,
+    pub operand: u16,                                                         //the operand byte or little endian word, 0 without one
+}
+
+impl Instruction {
+    pub fn size(&self) -> u16 {                                               //in bytes, opcode included
+        self.opcode.len as u16
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        let [lo, hi] = self.operand.to_le_bytes();
+        [self.opcode.code, lo, hi][..self.opcode.len as usize].to_vec()
+    }
+
+    pub fn is_branch(&self) -> bool {                                         //relative addressing shows up as a two byte NoneAddress
+        matches!(self.opcode.mode, AddressMode::NoneAddress) && self.opcode.len == 2
+    }
+
+    pub fn flow(&self) -> Flow {
+        let next = self.addr.wrapping_add(self.size());
+        match self.opcode.code {
+            0x4c => Flow::Jump(self.operand),
+            0x20 => Flow::Call(self.operand),
+            0x60 | 0x40 => Flow::Return,
+            0x6c | 0x00 => Flow::Stop,
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => Flow::Stop,
+            _ if self.is_branch() => Flow::Branch(next.wrapping_add(self.operand as u8 as i8 as u16)),
+            _ => Flow::Next,
+        }
+    }
+
+    //the operand as assembly text, symbol gets the first say on absolute addresses and jump targets
+    pub fn operand_text(&self, symbol: &dyn Fn(u16) -> Option<String>) -> String {
+        let byte = self.operand as u8;
+        let word = |addr: u16| symbol(addr).unwrap_or_else(|| format!("${:04X}", addr));
+        match (&self.opcode.mode, self.flow()) {
+            (_, Flow::Branch(target)) | (_, Flow::Jump(target)) | (_, Flow::Call(target)) => word(target),
+            (AddressMode::Immeditate, _) => format!("#${:02X}", byte),
+            (AddressMode::ZeroPage, _) => format!("${:02X}", byte),
+            (AddressMode::ZeroPageX, _) => format!("${:02X},X", byte),
+            (AddressMode::ZeroPageY, _) => format!("${:02X},Y", byte),
+            (AddressMode::IndirectX, _) => format!("(${:02X},X)", byte),
+            (AddressMode::IndirectY, _) => format!("(${:02X}),Y", byte),
+            (AddressMode::Absolute, _) => word(self.operand),
+            (AddressMode::AbsoluteX, _) => format!("{},X", word(self.operand)),
+            (AddressMode::AbsoluteY, _) => format!("{},Y", word(self.operand)),
+            (AddressMode::NoneAddress, _) if self.opcode.code == 0x6c => format!("(${:04X})", self.operand),
+            (AddressMode::NoneAddress, _) => match self.opcode.code {
+                0x0a | 0x4a | 0x2a | 0x6a => "A".to_string(),
+                _ => String::new(),
+            },
+        }
+    }
+
+    pub fn text(&self, symbol: &dyn Fn(u16) -> Option<String>) -> String {
+        format!("{} {}", self.opcode.mnemonic, self.operand_text(symbol)).trim_end().to_string()
+    }
+}
+
+pub fn decode(bytes: &[u8], addr: u16) -> Option<Instruction> {               //None for an unknown opcode or one cut off by the end of bytes
+    let opcode = *opcodes::OPCODES_MAP.get(bytes.first()?)?;
+    let operand = match opcode.len {
+        2 => *bytes.get(1)? as u16,
+        3 => u16::from_le_bytes([*bytes.get(1)?, *bytes.get(2)?]),
+        _ => 0,
+    };
+    Some(Instruction { addr, opcode, operand })
+}
+
+fn register_symbol(addr: u16) -> Option<String> {
+    register_name(addr).map(str::to_string)
+}
+
+/*
+    LISTINGS
+
+*/
+struct Analysis {
+    code: BTreeMap<u16, Instruction>,
+    labels: BTreeMap<u16, String>,
+}
+
+fn analyse(bytes: &[u8], origin: u16, entry_points: &[u16]) -> Analysis {
+    let end = origin as u32 + bytes.len() as u32;
+    let in_range = |addr: u16| (origin as u32..end).contains(&(addr as u32));
+    let decode_at = |addr: u16| decode(&bytes[(addr - origin) as usize..], addr);
+
+    let mut labels = BTreeMap::new();
+    let mut entries: Vec<u16> = entry_points.iter().copied().filter(|&addr| in_range(addr)).collect();
+    if in_range(VECTORS) && end > 0xFFFF {
+        for (vector, name) in VECTOR_NAMES {
+            let offset = (vector - origin) as usize;
+            let target = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+            if in_range(target) {
+                entries.push(target);
+                labels.entry(target).or_insert_with(|| name.to_string());
+            }
+        }
+    }
+
+    let mut code = BTreeMap::new();
+    let mut claimed = vec![false; bytes.len()];                               //bytes that belong to an instruction already
+    if entries.is_empty() {
+        let mut addr = origin as u32;
+        while addr < end {
+            match decode_at(addr as u16) {
+                Some(ins) if !ins.opcode.is_unofficial() => {
+                    code.insert(addr as u16, ins);
+                    addr += ins.size() as u32;
+                }
+                _ => addr += 1,
+            }
+        }
+    } else {
+        let mut pending = entries;
+        while let Some(addr) = pending.pop() {
+            if !in_range(addr) || code.contains_key(&addr) {
+                continue;
+            }
+            let ins = match decode_at(addr) {
+                Some(ins) if ins.flow() != Flow::Stop || ins.opcode.code == 0x6c => ins,
+                _ => continue,                                               //BRK, JAM and unknown bytes end the path
+            };
+            let span = (addr - origin) as usize..(addr - origin) as usize + ins.size() as usize;
+            if claimed[span.clone()].iter().any(|&c| c) {
+                continue;                                                    //overlaps an instruction we already decoded
+            }
+            claimed[span].iter_mut().for_each(|c| *c = true);
+            code.insert(addr, ins);
+
+            let next = addr.wrapping_add(ins.size());
+            match ins.flow() {
+                Flow::Next => pending.push(next),
+                Flow::Branch(target) | Flow::Call(target) => {
+                    pending.push(next);
+                    pending.push(target);
+                }
+                Flow::Jump(target) => pending.push(target),
+                Flow::Return | Flow::Stop => {}
+            }
+        }
+    }
+
+    for ins in code.values() {
+        let (target, prefix) = match ins.flow() {
+            Flow::Call(target) => (target, "SUB"),
+            Flow::Branch(target) | Flow::Jump(target) => (target, "L"),
+            _ => continue,
+        };
+        if code.contains_key(&target) {
+            labels.entry(target).or_insert_with(|| format!("{}_{:04X}", prefix, target));
+        }
+    }
+    Analysis { code, labels }
+}
+
+fn push_line(out: &mut String, text: &str, comment: &str) {
+    let line = format!("    {}", text);
+    let width = COMMENT_COLUMN.max(line.len() + 1);
+    let _ = writeln!(out, "{:<width$}; {}", line, comment, width = width);
+}
+
+pub fn disassemble(bytes: &[u8], origin: u16, entry_points: &[u16]) -> String {
+    let analysis = analyse(bytes, origin, entry_points);
+    let labels = &analysis.labels;
+    let symbol = |addr: u16| labels.get(&addr).cloned().or_else(|| register_symbol(addr));
+
+    let mut body = String::new();
+    let mut registers_used = BTreeSet::new();
+    let mut data: Vec<u8> = vec![];
+    let mut data_start = origin;
+    let end = origin as u32 + bytes.len() as u32;
+
+    let flush = |out: &mut String, data: &mut Vec<u8>, start: u16| {
+        for (i, chunk) in data.chunks(DATA_BYTES_PER_LINE).enumerate() {
+            let list: Vec<String> = chunk.iter().map(|b| format!("${:02X}", b)).collect();
+            let addr = start.wrapping_add((i * DATA_BYTES_PER_LINE) as u16);
+            push_line(out, &format!(".byte {}", list.join(", ")), &format!("{:04X}", addr));
+        }
+        data.clear();
+    };
+
+    let mut addr = origin as u32;
+    while addr < end {
+        let here = addr as u16;
+        let label = labels.get(&here);
+        let ins = analysis.code.get(&here);
+        let vectors = here == VECTORS && ins.is_none() && end > 0xFFFF;
+        if (label.is_some() || ins.is_some() || vectors) && !data.is_empty() {
+            flush(&mut body, &mut data, data_start);
+        }
+        if let Some(label) = label {
+            let _ = writeln!(body, "{}:", label);
+        }
+
+        if vectors {                                                          //the vectors read better as words
+            let words: Vec<String> = (0..3)
+                .map(|i| {
+                    let offset = (VECTORS - origin) as usize + i * 2;
+                    let target = u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+                    symbol(target).unwrap_or_else(|| format!("${:04X}", target))
+                })
+                .collect();
+            push_line(&mut body, &format!(".word {}", words.join(", ")), &format!("{:04X}", VECTORS));
+            break;
+        }
+
+        match ins {
+            Some(ins) => {
+                let hex: Vec<String> = ins.bytes().iter().map(|b| format!("{:02X}", b)).collect();
+                let comment = format!("{:04X}  {}", here, hex.join(" "));
+                if ins.opcode.is_unofficial() {                               //the assembler only knows the documented set
+                    let list: Vec<String> = ins.bytes().iter().map(|b| format!("${:02X}", b)).collect();
+                    push_line(&mut body, &format!(".byte {}", list.join(", ")), &format!("{}  {}", comment, ins.text(&symbol)));
+                } else {
+                    if matches!(ins.opcode.mode, AddressMode::Absolute | AddressMode::AbsoluteX | AddressMode::AbsoluteY) {
+                        if let Some(name) = register_name(ins.operand).filter(|_| !labels.contains_key(&ins.operand)) {
+                            registers_used.insert((ins.operand, name));
+                        }
+                    }
+                    push_line(&mut body, &ins.text(&symbol), &comment);
+                }
+                addr += ins.size() as u32;
+            }
+            None => {
+                if data.is_empty() {
+                    data_start = here;
+                }
+                data.push(bytes[(here - origin) as usize]);
+                addr += 1;
+            }
+        }
+    }
+    if !data.is_empty() {
+        flush(&mut body, &mut data, data_start);
+    }
+
+    let mut out = String::new();
+    for (addr, name) in &registers_used {
+        let _ = writeln!(out, "{} = ${:04X}", name, addr);
+    }
+    if !registers_used.is_empty() {
+        out.push('\n');
+    }
+    let _ = writeln!(out, ".org ${:04X}", origin);
+    out.push_str(&body);
+    out
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(bytes: &[u8], addr: u16) -> String {
+        decode(bytes, addr).unwrap().text(&register_symbol)
+    }
+
+    #[test]
+    fn test_every_address_mode() {
+        assert_eq!(text(&[0xa9, 0x05], 0), "LDA #$05");
+        assert_eq!(text(&[0xa5, 0x10], 0), "LDA $10");
+        assert_eq!(text(&[0xb5, 0x10], 0), "LDA $10,X");
+        assert_eq!(text(&[0xb6, 0x10], 0), "LDX $10,Y");
+        assert_eq!(text(&[0xad, 0x34, 0x12], 0), "LDA $1234");
+        assert_eq!(text(&[0xad, 0x10, 0x00], 0), "LDA $0010");             //absolute keeps four digits
+        assert_eq!(text(&[0xbd, 0x34, 0x12], 0), "LDA $1234,X");
+        assert_eq!(text(&[0xb9, 0x34, 0x12], 0), "LDA $1234,Y");
+        assert_eq!(text(&[0xa1, 0x10], 0), "LDA ($10,X)");
+        assert_eq!(text(&[0xb1, 0x10], 0), "LDA ($10),Y");
+        assert_eq!(text(&[0x6c, 0xff, 0x02], 0), "JMP ($02FF)");
+        assert_eq!(text(&[0x0a], 0), "ASL A");
+        assert_eq!(text(&[0xe8], 0), "INX");
+        assert_eq!(text(&[0xd0, 0xfe], 0x8000), "BNE $8000");
+        assert_eq!(text(&[0x8d, 0x00, 0x20], 0), "STA PPUCTRL");
+        assert_eq!(text(&[0x9d, 0x14, 0x40], 0), "STA OAMDMA,X");
+        assert!(decode(&[0xad, 0x00], 0).is_none());
+    }
+
+    #[test]
+    fn test_flow() {
+        assert_eq!(decode(&[0x20, 0x00, 0x90], 0x8000).unwrap().flow(), Flow::Call(0x9000));
+        assert_eq!(decode(&[0x10, 0x02], 0x8000).unwrap().flow(), Flow::Branch(0x8004));
+        assert_eq!(decode(&[0x60], 0x8000).unwrap().flow(), Flow::Return);
+        assert_eq!(decode(&[0x02], 0x8000).unwrap().flow(), Flow::Stop);
+    }
+
+    #[test]
+    fn test_listing_with_labels_data_and_vectors() {
+        let mut prg = vec![0u8; 0x4000];
+        let program = [
+            0x78,                                                             //C000 SEI
+            0x20, 0x08, 0xc0,                                                 //C001 JSR $C008
+            0x4c, 0x04, 0xc0,                                                 //C004 JMP $C004
+            0xff,                                                             //C007 never reached
+            0x8d, 0x00, 0x20,                                                 //C008 STA $2000
+            0x60,                                                             //C00B RTS
+        ];
+        prg[..program.len()].copy_from_slice(&program);
+        prg[0x3ffa..].copy_from_slice(&[0x0b, 0xc0, 0x00, 0xc0, 0x0b, 0xc0]);
+        let listing = disassemble(&prg, 0xC000, &[]);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "PPUCTRL = $2000");
+        assert_eq!(lines[2], ".org $C000");
+        assert_eq!(lines[3], "RESET:");
+        assert_eq!(lines[4], "    SEI                     ; C000  78");
+        assert_eq!(lines[5], "    JSR SUB_C008            ; C001  20 08 C0");
+        assert_eq!(lines[6], "L_C004:");
+        assert_eq!(lines[7], "    JMP L_C004              ; C004  4C 04 C0");
+        assert_eq!(lines[8], "    .byte $FF               ; C007");
+        assert_eq!(lines[9], "SUB_C008:");
+        assert_eq!(lines[10], "    STA PPUCTRL             ; C008  8D 00 20");
+        assert_eq!(lines[11], "NMI:");
+        assert_eq!(lines[12], "    RTS                     ; C00B  60");
+        assert_eq!(lines.last().unwrap(), &"    .word NMI, RESET, NMI   ; FFFA");
+    }
+
+    #[test]
+    fn test_linear_sweep_without_entry_points() {
+        let listing = disassemble(&[0xa9, 0x01, 0x02, 0xea], 0x8000, &[]);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[1], "    LDA #$01                ; 8000  A9 01");
+        assert_eq!(lines[2], "    .byte $02               ; 8002");           //undocumented opcodes are taken as data
+        assert_eq!(lines[3], "    NOP                     ; 8003  EA");
+    }
+}
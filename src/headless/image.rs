@@ -0,0 +1,350 @@
+use crate::ppu::frame::Frame;
+use crate::savestate::crc32;
+
+/*
+    SCREENSHOT FORMATS
+    rgb     the framebuffer as it is, 256x240 and 3 bytes per pixel
+    ppm     binary PPM (P6), a short text header in front of the same bytes
+    png     truecolour PNG, deflated with the fixed Huffman codes which is plenty for NES
+            graphics, a frame usually ends up a few KiB
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+    Rgb,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Option<ImageFormat> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Some(ImageFormat::Png),
+            "ppm" => Some(ImageFormat::Ppm),
+            "rgb" | "raw" => Some(ImageFormat::Rgb),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Rgb => "rgb",
+        }
+    }
+
+    pub fn encode(&self, frame: &Frame) -> Vec<u8> {
+        match self {
+            ImageFormat::Png => encode_png(&frame.data, Frame::WIDTH, Frame::HEIGHT),
+            ImageFormat::Ppm => encode_ppm(&frame.data, Frame::WIDTH, Frame::HEIGHT),
+            ImageFormat::Rgb => frame.data.clone(),
+        }
+    }
+}
+
+pub fn encode_ppm(rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    out.extend_from_slice(rgb);
+    out
+}
+
+
+/*
+    PNG (https://www.w3.org/TR/png/)
+    signature, IHDR, one IDAT with the zlib stream, IEND. every row starts with filter 0
+*/
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const COLOR_TYPE_RGB: u8 = 2;
+
+fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);                                           //covers the type and the data, not the length
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub fn encode_png(rgb: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, COLOR_TYPE_RGB, 0, 0, 0]);                 //bit depth, colour type, compression, filter, interlace
+
+    let mut rows = Vec::with_capacity(rgb.len() + height);
+    for row in rgb.chunks(width * 3).take(height) {
+        rows.push(0);
+        rows.extend_from_slice(row);
+    }
+
+    let mut out = PNG_SIGNATURE.to_vec();
+    png_chunk(&mut out, b"IHDR", &header);
+    png_chunk(&mut out, b"IDAT", &zlib_compress(&rows));
+    png_chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+
+/*
+    ZLIB AND DEFLATE (RFC 1950, RFC 1951)
+    a single final block with the fixed Huffman codes, matches are found greedily through
+    hash chains over the last 32KiB
+*/
+const WINDOW_SIZE: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const MAX_CHAIN: usize = 64;                                                  //candidates tried per position
+const HASH_BITS: u32 = 15;
+const END_OF_BLOCK: u16 = 256;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new(out: Vec<u8>) -> Self {
+        BitWriter { out, bits: 0, count: 0 }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {                             //plain values go in least significant bit first
+        self.bits |= value << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_code(&mut self, code: u32, count: u32) {                         //Huffman codes go in most significant bit first
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+
+    fn literal_length(&mut self, symbol: u16) {                               //the fixed literal/length code
+        let symbol = symbol as u32;
+        match symbol {
+            0 ..= 143 => self.write_code(0b0011_0000 + symbol, 8),
+            144 ..= 255 => self.write_code(0b1_1001_0000 + symbol - 144, 9),
+            256 ..= 279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0b1100_0000 + symbol - 280, 8),
+        }
+    }
+
+    fn matched(&mut self, length: usize, distance: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap();
+        self.literal_length(257 + index as u16);
+        self.write((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+
+        let index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap();
+        self.write_code(index as u32, 5);
+        self.write((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+}
+
+fn hash(data: &[u8], pos: usize) -> usize {
+    let value = (data[pos] as u32) << 16 | (data[pos + 1] as u32) << 8 | data[pos + 2] as u32;
+    (value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert(data: &[u8], pos: usize, head: &mut [usize], prev: &mut [usize]) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash(data, pos);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+fn longest_match(data: &[u8], pos: usize, head: &[usize], prev: &[usize]) -> (usize, usize) {
+    let limit = (data.len() - pos).min(MAX_MATCH);
+    let mut best = (0, 0);
+    let mut candidate = head[hash(data, pos)];
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW_SIZE {
+            break;
+        }
+        let length = (0..limit).take_while(|&i| data[candidate + i] == data[pos + i]).count();
+        if length > best.0 {
+            best = (length, pos - candidate);
+            if length == limit {
+                break;
+            }
+        }
+        candidate = prev[candidate];
+    }
+    best
+}
+
+pub fn deflate(data: &[u8], out: Vec<u8>) -> Vec<u8> {
+    let mut w = BitWriter::new(out);
+    w.write(1, 1);                                                            //BFINAL
+    w.write(0b01, 2);                                                         //BTYPE fixed Huffman
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];                        //newest position for every hash
+    let mut prev = vec![usize::MAX; data.len()];                              //the position before it with the same hash
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let (length, distance) = if pos + MIN_MATCH <= data.len() {
+            longest_match(data, pos, &head, &prev)
+        } else {
+            (0, 0)
+        };
+        if length >= MIN_MATCH {
+            w.matched(length, distance);
+            for skipped in pos..pos + length {
+                insert(data, skipped, &mut head, &mut prev);
+            }
+            pos += length;
+        } else {
+            w.literal_length(data[pos] as u16);
+            insert(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+    w.literal_length(END_OF_BLOCK);
+    w.finish()
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {                                          //the most bytes before b can overflow
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = deflate(data, vec![0x78, 0x01]);                            //32KiB window, fastest compression level
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    //just enough of an inflater to read back fixed Huffman blocks
+    fn inflate_fixed(data: &[u8]) -> Vec<u8> {
+        let mut pos = 0;
+        let mut bit = |count: u32| -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                value |= ((data[pos / 8] >> (pos % 8)) as u32 & 1) << i;
+                pos += 1;
+            }
+            value
+        };
+        let mut out: Vec<u8> = vec![];
+        assert_eq!(bit(1), 1);
+        assert_eq!(bit(2), 0b01);
+        loop {
+            let mut code = 0;
+            let mut len = 0;
+            let symbol = loop {
+                code = (code << 1) | bit(1);
+                len += 1;
+                match (len, code) {
+                    (7, 0 ..= 0b001_0111) => break code + 256,
+                    (8, 0b0011_0000 ..= 0b1011_1111) => break code - 0b0011_0000,
+                    (8, 0b1100_0000 ..= 0b1100_0111) => break code - 0b1100_0000 + 280,
+                    (9, 0b1_1001_0000 ..= 0b1_1111_1111) => break code - 0b1_1001_0000 + 144,
+                    _ => {}
+                }
+            };
+            match symbol {
+                0 ..= 255 => out.push(symbol as u8),
+                256 => return out,
+                _ => {
+                    let index = symbol as usize - 257;
+                    let length = LENGTH_BASE[index] as usize + bit(LENGTH_EXTRA[index] as u32) as usize;
+                    let index = (bit(5).reverse_bits() >> 27) as usize;
+                    let distance = DISTANCE_BASE[index] as usize + bit(DISTANCE_EXTRA[index] as u32) as usize;
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_deflate_round_trip() {
+        let mut data: Vec<u8> = b"hello hello hello, NES world! ".repeat(20);
+        data.extend((0..3000u32).map(|i| (i * i % 251) as u8));
+        data.extend(vec![0x0F; 1000]);
+        let packed = deflate(&data, vec![]);
+        assert!(packed.len() < data.len());
+        assert_eq!(inflate_fixed(&packed), data);
+        assert_eq!(inflate_fixed(&deflate(&[], vec![])), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(&vec![0xFF; 100_000]), 0x149A_302C);
+    }
+
+    #[test]
+    fn test_png_layout() {
+        let mut frame = Frame::new();
+        frame.set_pixel(10, 20, (0xFF, 0x80, 0x00));
+        let png = ImageFormat::Png.encode(&frame);
+        assert_eq!(png[..8], PNG_SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..20], 256u32.to_be_bytes());
+        assert_eq!(png[20..24], 240u32.to_be_bytes());
+        assert_eq!(crc32(&png[12..29]).to_be_bytes(), png[29..33]);
+        assert_eq!(&png[png.len() - 12..], &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+
+        let idat_len = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let zlib = &png[41..41 + idat_len];
+        let rows = inflate_fixed(&zlib[2..zlib.len() - 4]);
+        assert_eq!(rows.len(), 240 * (1 + 256 * 3));
+        assert_eq!(&rows[20 * 769 + 1 + 30..20 * 769 + 1 + 33], &[0xFF, 0x80, 0x00]);
+        assert!(png.len() < 3000, "{} bytes", png.len());                         //a blank frame is mostly long matches
+    }
+
+    #[test]
+    fn test_ppm() {
+        let ppm = ImageFormat::Ppm.encode(&Frame::new());
+        assert!(ppm.starts_with(b"P6\n256 240\n255\n"));
+        assert_eq!(ppm.len(), 15 + 256 * 240 * 3);
+    }
+}
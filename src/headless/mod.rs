@@ -0,0 +1,380 @@
+pub mod image;
+pub mod movie;
+pub mod video;
+
+use std::fmt;
+
+use crate::apu::wav::{AudioCapture, Recording};
+use crate::bus::Bus;
+use crate::cartridge::{Rom, RomError};
+use crate::joypad::JoypadButton;
+use crate::ppu::frame::Frame;
+use crate::CPU::{StopReason, CPU};
+
+/*
+    HEADLESS RUNNER
+    runs a cartridge a frame at a time with nothing attached, the controllers are driven
+    by an input script. frames are counted from 1, the input for frame n is set before it
+    runs and the framebuffer holds its picture once run_frame returns. a frame can also
+    start by pressing reset or by switching the console off and on again
+*/
+const BUTTON_NAMES: [(&str, JoypadButton); 8] = [
+    ("a", JoypadButton::BUTTON_A),
+    ("b", JoypadButton::BUTTON_B),
+    ("select", JoypadButton::SELECT),
+    ("start", JoypadButton::START),
+    ("up", JoypadButton::UP),
+    ("down", JoypadButton::DOWN),
+    ("left", JoypadButton::LEFT),
+    ("right", JoypadButton::RIGHT),
+];
+
+bitflags! {
+    //same bits as the command field of an FM2 movie
+    #[derive(Default)]
+    pub struct Commands: u8 {
+        const RESET             = 0b00000001;
+        const POWER             = 0b00000010;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameInput {
+    pub pads: [JoypadButton; 2],
+    pub commands: Commands,                                                   //carried out before the frame runs
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+pub fn parse_buttons(text: &str) -> Option<JoypadButton> {                    //"a+right", "none" or "-" for nothing held
+    if text == "none" || text == "-" {
+        return Some(JoypadButton::empty());
+    }
+    text.split('+').try_fold(JoypadButton::empty(), |held, name| {
+        let name = name.trim().to_ascii_lowercase();
+        BUTTON_NAMES.iter().find(|(known, _)| *known == name).map(|(_, button)| held | *button)
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InputEntry {
+    start: u64,
+    end: Option<u64>,                                                         //released after this frame, otherwise held until the next entry
+    buttons: [JoypadButton; 2],
+}
+
+/*
+    INPUT SCRIPTS
+    one entry per line, # starts a comment
+
+        FRAME BUTTONS [BUTTONS]         hold from FRAME until the next entry
+        FIRST-LAST BUTTONS [BUTTONS]    hold for those frames, then let go
+        FRAME reset|power               press reset or power cycle before FRAME
+
+    the second set of buttons is for controller 2, e.g. "120-125 start" or "300 a+right"
+*/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    entries: Vec<InputEntry>,                                                 //sorted by start frame
+    commands: Vec<(u64, Commands)>,
+}
+
+impl InputScript {
+    pub fn new() -> Self {
+        InputScript::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut entries = vec![];
+        let mut commands = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| ScriptError { line: index + 1, message };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() > 3 {
+                return Err(error("expected a frame and up to two sets of buttons".to_string()));
+            }
+            let frame = |text: &str| text.parse::<u64>().map_err(|_| error(format!("'{}' is not a frame number", text)));
+            let command = match fields.get(1) {
+                Some(&"reset") => Commands::RESET,
+                Some(&"power") => Commands::POWER,
+                _ => Commands::empty(),
+            };
+            if !command.is_empty() {
+                if fields.len() > 2 {
+                    return Err(error(format!("{} goes on a line of its own", fields[1])));
+                }
+                commands.push((frame(fields[0])?, command));
+                continue;
+            }
+            let (start, end) = match fields[0].split_once('-') {
+                Some((first, last)) => (frame(first)?, Some(frame(last)?)),
+                None => (frame(fields[0])?, None),
+            };
+            if end.is_some_and(|end| end < start) {
+                return Err(error(format!("frame range {} ends before it starts", fields[0])));
+            }
+            let mut buttons = [JoypadButton::empty(); 2];
+            for (pad, text) in fields[1..].iter().enumerate() {
+                buttons[pad] = parse_buttons(text).ok_or_else(|| error(format!("unknown buttons '{}'", text)))?;
+            }
+            entries.push(InputEntry { start, end, buttons });
+        }
+        entries.sort_by_key(|entry| entry.start);                             //stable, so the later of two lines for a frame wins
+        Ok(InputScript { entries, commands })
+    }
+
+    pub fn buttons_at(&self, frame: u64) -> [JoypadButton; 2] {
+        let at = self.entries.partition_point(|entry| entry.start <= frame);
+        match at.checked_sub(1).map(|index| &self.entries[index]) {
+            Some(entry) if entry.end.is_none_or(|end| frame <= end) => entry.buttons,
+            _ => [JoypadButton::empty(); 2],
+        }
+    }
+
+    pub fn input_at(&self, frame: u64) -> FrameInput {
+        let commands = self.commands.iter().filter(|(at, _)| *at == frame).fold(Commands::empty(), |all, (_, c)| all | *c);
+        FrameInput { pads: self.buttons_at(frame), commands }
+    }
+
+    pub fn last_frame(&self) -> u64 {                                         //the last frame the script changes anything on
+        let held = self.entries.iter().map(|entry| entry.end.map_or(entry.start, |end| end + 1));
+        held.chain(self.commands.iter().map(|(frame, _)| *frame)).max().unwrap_or(0)
+    }
+}
+
+pub struct Headless {
+    pub cpu: CPU,
+    pub script: InputScript,
+    frame: u64,                                                               //frames completed so far
+}
+
+impl Headless {
+    pub fn new(rom: Rom) -> Result<Self, RomError> {
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom)?);
+        cpu.reset();
+        Ok(Headless { cpu, script: InputScript::new(), frame: 0 })
+    }
+
+    pub fn with_script(mut self, script: InputScript) -> Self {
+        self.script = script;
+        self
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn frame_buffer(&self) -> &Frame {
+        &self.cpu.bus.ppu().frame
+    }
+
+    pub fn reset(&mut self) {
+        self.cpu.bus.reset();
+        self.cpu.reset();
+    }
+
+    pub fn power_cycle(&mut self) {                                           //a fresh console with the same cartridge, cheats and a running audio capture carry on
+        let rom = self.cpu.bus.rom().cloned().expect("the runner always has a cartridge");
+        let capture = self.cpu.bus.apu_mut().stop_capture();
+        let cheats = std::mem::take(self.cpu.bus.cheats_mut());
+        let config = self.cpu.config;
+        self.cpu = CPU::with_bus(Bus::with_rom(rom).expect("the mapper was supported a moment ago"));
+        self.cpu.config = config;
+        *self.cpu.bus.cheats_mut() = cheats;
+        if let Some(capture) = capture {
+            self.cpu.bus.apu_mut().start_capture(capture);
+        }
+        self.cpu.reset();
+    }
+
+    pub fn run_frame(&mut self) -> Result<(), StopReason> {                   //fails when the CPU halts before the frame is done
+        let input = self.script.input_at(self.frame + 1);
+        self.run_frame_with(input)
+    }
+
+    pub fn run_frame_with(&mut self, input: FrameInput) -> Result<(), StopReason> {
+        if input.commands.contains(Commands::POWER) {
+            self.power_cycle();
+        } else if input.commands.contains(Commands::RESET) {
+            self.reset();
+        }
+        let [pad1, pad2] = input.pads;
+        self.cpu.bus.joypad1_mut().set_buttons(pad1);
+        self.cpu.bus.joypad2_mut().set_buttons(pad2);
+        match self.cpu.run_frame() {
+            StopReason::FrameComplete => {
+                self.frame += 1;
+                Ok(())
+            }
+            reason => Err(reason),
+        }
+    }
+
+    pub fn run_frames<F>(&mut self, frames: u64, mut after_frame: F) -> Result<(), StopReason>
+    where
+        F: FnMut(&mut Headless),
+    {
+        for _ in 0..frames {
+            self.run_frame()?;
+            after_frame(self);
+        }
+        Ok(())
+    }
+
+    pub fn start_audio_capture(&mut self, sample_rate: u32, stems: bool) {  //replaces a capture that is already running
+        self.cpu.bus.apu_mut().start_capture(AudioCapture::new(sample_rate, stems));
+    }
+
+    pub fn stop_audio_capture(&mut self) -> Option<Recording> {
+        self.cpu.bus.apu_mut().stop_capture().map(AudioCapture::into_recording)
+    }
+
+    pub fn record_audio(&mut self, frames: u64, sample_rate: u32, stems: bool) -> Result<Recording, StopReason> {
+        self.start_audio_capture(sample_rate, stems);
+        let result = self.run_frames(frames, |_| {});
+        let recording = self.stop_audio_capture().expect("the capture was started above");
+        result.map(|_| recording)
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::{joypad_echo_rom, test_rom};
+    use crate::CPU::Mem;
+
+    #[test]
+    fn test_parse_buttons() {
+        assert_eq!(parse_buttons("a+RIGHT"), Some(JoypadButton::BUTTON_A | JoypadButton::RIGHT));
+        assert_eq!(parse_buttons("-"), Some(JoypadButton::empty()));
+        assert_eq!(parse_buttons("start+jump"), None);
+    }
+
+    #[test]
+    fn test_script() {
+        let script = InputScript::parse(
+            "# title screen\n\
+             10-12 start\n\
+             20 right b    # controller 2 fires\n\
+             30 none\n\
+             25-26 a+right",
+        )
+        .unwrap();
+        assert_eq!(script.buttons_at(9), [JoypadButton::empty(); 2]);
+        assert_eq!(script.buttons_at(12), [JoypadButton::START, JoypadButton::empty()]);
+        assert_eq!(script.buttons_at(13), [JoypadButton::empty(); 2]);
+        assert_eq!(script.buttons_at(24), [JoypadButton::RIGHT, JoypadButton::BUTTON_B]);
+        assert_eq!(script.buttons_at(25)[0], JoypadButton::BUTTON_A | JoypadButton::RIGHT);
+        assert_eq!(script.buttons_at(27), [JoypadButton::empty(); 2]);     //a range lets go rather than going back to the entry before
+        assert_eq!(script.buttons_at(1000), [JoypadButton::empty(); 2]);
+        assert_eq!(script.last_frame(), 30);
+
+        let script = InputScript::parse("40 reset\n40 a\n50 power").unwrap();
+        let pads = [JoypadButton::BUTTON_A, JoypadButton::empty()];
+        assert_eq!(script.input_at(40), FrameInput { pads, commands: Commands::RESET });
+        assert_eq!(script.input_at(41).commands, Commands::empty());
+        assert_eq!(script.last_frame(), 50);
+        assert!(InputScript::parse("40 reset a").is_err());
+
+        let error = InputScript::parse("5 a\n\nx start").unwrap_err();
+        assert_eq!(error.to_string(), "line 3: 'x' is not a frame number");
+        assert_eq!(InputScript::parse("5 jump").unwrap_err().message, "unknown buttons 'jump'");
+        assert!(InputScript::parse("9-3 a").is_err());
+    }
+
+    #[test]
+    fn test_runner_applies_the_script() {
+        let mut runner = Headless::new(joypad_echo_rom()).unwrap().with_script(InputScript::parse("3 start").unwrap());
+        let mut seen = vec![];
+        runner.run_frames(4, |runner| seen.push(runner.cpu.bus.peek(0x10))).unwrap();
+        assert_eq!(runner.frame(), 4);
+        assert_eq!(seen[1], 0);
+        assert_eq!(seen[2], JoypadButton::START.bits());                      //frames start at vblank, so the NMI handler runs inside frame 3
+
+        runner.cpu.bus.mem_write(0x0300, 0x42);
+        runner.run_frame_with(FrameInput { commands: Commands::RESET, ..FrameInput::default() }).unwrap();
+        assert_eq!(runner.cpu.bus.peek(0x0300), 0x42);                        //RAM survives reset
+        runner.run_frame_with(FrameInput { commands: Commands::POWER, ..FrameInput::default() }).unwrap();
+        assert_eq!(runner.cpu.bus.peek(0x0300), 0);
+        assert_eq!(runner.frame(), 6);
+    }
+
+    #[test]
+    fn test_runner_takes_brk_as_an_interrupt() {
+        let program = crate::asm!(
+            "loop:   BRK",
+            "        NOP",                                                    //BRK skips the byte after it
+            "        INC $20",
+            "        JMP loop",
+            "irq:    INC $21",
+            "        RTI",
+            "        .org $BFFA",
+            "        .word $8000, $8000, irq",
+        );
+        let mut runner = Headless::new(test_rom(program)).unwrap();
+        runner.run_frames(5, |_| ()).unwrap();
+        runner.power_cycle();
+        runner.run_frame().unwrap();
+        assert_eq!(runner.frame(), 6);
+        let (returns, interrupts) = (runner.cpu.bus.peek(0x20), runner.cpu.bus.peek(0x21));
+        assert!(interrupts > 0);
+        assert!(interrupts - returns <= 1, "{} BRKs but {} returns", interrupts, returns);   //the frame can end inside the handler
+    }
+
+    #[test]
+    fn test_record_pulse_with_stems() {
+        //pulse 1 at 50% duty and constant volume 15, its length counter halted so it keeps going
+        let program = crate::asm!(
+            "        LDA #$01",
+            "        STA $4015",
+            "        LDA #$BF",
+            "        STA $4000",
+            "        LDA #$FD",
+            "        STA $4002",
+            "        LDA #$00",
+            "        STA $4003",
+            "loop:   JMP loop",
+        );
+        let mut runner = Headless::new(test_rom(program)).unwrap();
+        runner.run_frame().unwrap();
+
+        let recording = runner.record_audio(30, 22_050, true).unwrap();
+        assert!((recording.seconds() - 0.5).abs() < 0.01, "{} seconds", recording.seconds());
+        let stems = recording.stems.as_ref().unwrap();
+        let loudest = |samples: &[f32]| samples.iter().fold(0.0f32, |max, s| max.max(s.abs()));
+        assert!(loudest(&recording.mix) > 0.05);
+        assert!(loudest(&stems[0]) > 0.05);
+        assert_eq!(loudest(&stems[1]), 0.0);
+        assert_eq!(stems[2].len(), recording.mix.len());
+        assert_eq!(recording.stem_wavs()[3].0, "noise");
+        assert!(runner.stop_audio_capture().is_none());
+
+        runner.start_audio_capture(44_100, false);
+        runner.run_frame().unwrap();
+        let recording = runner.stop_audio_capture().unwrap();
+        assert!(recording.stem_wavs().is_empty());
+        assert!(recording.mix.len() > 700 && recording.mix.len() < 760);
+    }
+}
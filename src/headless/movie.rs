@@ -0,0 +1,400 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Commands, FrameInput, Headless};
+use crate::cartridge::Rom;
+use crate::joypad::JoypadButton;
+use crate::savestate::crc32;
+use crate::CPU::{StopReason, CPU};
+
+/*
+    FM2 MOVIES (https://fceux.com/web/help/fm2.html)
+    a text header of "key value" lines followed by one line per frame
+
+        |commands|RLDUTSBA|RLDUTSBA||
+
+    commands is a decimal bitfield (1 reset, 2 power), then one field per port where any
+    character other than '.' or ' ' means the button in that column is held. the first
+    input line is the first frame after power on, which is frame 1 of the headless runner
+
+    romChecksum is the MD5 of the PRG and CHR ROM in base64. on top of what FCEUX writes,
+    "ramChecksum FRAME CRC32" lines record the CRC-32 of the 2KiB of RAM after a frame so
+    playback can tell when it has gone off the rails. FCEUX skips keys it doesn't know
+*/
+const PAD_COLUMNS: &[u8; 8] = b"RLDUTSBA";                                    //bit 7 of JoypadButton down to bit 0
+const PORT_GAMEPAD: &str = "1";
+const RAM_SIZE: u16 = 0x800;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MovieError {
+    Parse { line: usize, message: String },
+    WrongRom { movie: String, rom: String },
+    Desync { frame: u64, expected: u32, found: u32 },
+    Halted { frame: u64, reason: StopReason },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            MovieError::WrongRom { movie, rom } => {
+                write!(f, "movie was recorded with ROM {} but this ROM is {}", movie, rom)
+            }
+            MovieError::Desync { frame, expected, found } => write!(
+                f,
+                "movie desynced at frame {}, RAM checksum is {:08x} instead of {:08x}",
+                frame, found, expected
+            ),
+            MovieError::Halted { frame, reason } => write!(f, "CPU stopped in frame {}: {:?}", frame, reason),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+pub fn rom_checksum(rom: &Rom) -> String {
+    format!("base64:{}", base64(&md5(&[rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat())))
+}
+
+pub fn ram_checksum(cpu: &CPU) -> u32 {
+    let ram: Vec<u8> = (0..RAM_SIZE).map(|addr| cpu.bus.peek(addr)).collect();
+    crc32(&ram)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Movie {
+    header: Vec<(String, String)>,                                            //kept in file order, unknown keys included
+    pub frames: Vec<FrameInput>,
+}
+
+impl Movie {
+    pub fn new(rom: &Rom, rom_name: &str) -> Movie {
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+        let guid = md5(format!("{}{}", rom_checksum(rom), seed).as_bytes());
+        let hex: String = guid.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let header = [
+            ("version", "3".to_string()),
+            ("emuVersion", "22020".to_string()),
+            ("rerecordCount", "0".to_string()),
+            ("palFlag", "0".to_string()),
+            ("romFilename", rom_name.to_string()),
+            ("romChecksum", rom_checksum(rom)),
+            ("guid", format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])),
+            ("fourscore", "0".to_string()),
+            ("microphone", "0".to_string()),
+            ("port0", PORT_GAMEPAD.to_string()),
+            ("port1", PORT_GAMEPAD.to_string()),
+            ("port2", "0".to_string()),
+            ("FDS", "0".to_string()),
+            ("NewPPU", "0".to_string()),
+        ];
+        Movie {
+            header: header.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+            frames: vec![],
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut movie = Movie::default();
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| MovieError::Parse { line: index + 1, message };
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                let input = movie.parse_record(line).map_err(error)?;
+                movie.frames.push(input);
+            } else if !line.trim().is_empty() {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                if key == "binary" && value.trim() == "1" {
+                    return Err(error("binary FM2 movies aren't supported".to_string()));
+                }
+                movie.header.push((key.to_string(), value.to_string()));
+            }
+        }
+        Ok(movie)
+    }
+
+    fn parse_record(&self, line: &str) -> Result<FrameInput, String> {
+        let fields: Vec<&str> = line[1..].split('|').collect();
+        let commands = fields[0].trim();
+        let commands = match commands {
+            "" => 0,
+            _ => commands.parse::<u8>().map_err(|_| format!("'{}' is not a command field", commands))?,
+        };
+        let mut input = FrameInput { commands: Commands::from_bits_truncate(commands), ..FrameInput::default() };
+        for (port, pad) in input.pads.iter_mut().enumerate() {
+            if self.header(&format!("port{}", port)).unwrap_or(PORT_GAMEPAD) != PORT_GAMEPAD {
+                continue;
+            }
+            let field = fields.get(port + 1).ok_or_else(|| format!("no input for port {}", port))?;
+            if field.len() != PAD_COLUMNS.len() {
+                return Err(format!("gamepad input '{}' should be {} characters", field, PAD_COLUMNS.len()));
+            }
+            for (column, held) in field.bytes().enumerate() {
+                if held != b'.' && held != b' ' {
+                    *pad |= JoypadButton::from_bits_truncate(0x80 >> column);
+                }
+            }
+        }
+        Ok(input)
+    }
+
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.header.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn check_rom(&self, rom: &Rom) -> Result<(), MovieError> {           //movies without a checksum are taken on trust
+        let found = rom_checksum(rom);
+        match self.header("romChecksum") {
+            Some(expected) if expected != found => {
+                Err(MovieError::WrongRom { movie: expected.to_string(), rom: found })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.frames.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn input(&self, frame: u64) -> FrameInput {                           //nothing held once the movie runs out
+        match frame.checked_sub(1) {
+            Some(index) => self.frames.get(index as usize).copied().unwrap_or_default(),
+            None => FrameInput::default(),
+        }
+    }
+
+    pub fn checkpoints(&self) -> Vec<(u64, u32)> {
+        self.header
+            .iter()
+            .filter(|(key, _)| key == "ramChecksum")
+            .filter_map(|(_, value)| {
+                let (frame, crc) = value.split_once(' ')?;
+                Some((frame.parse().ok()?, u32::from_str_radix(crc.trim(), 16).ok()?))
+            })
+            .collect()
+    }
+
+    pub fn add_checkpoint(&mut self, frame: u64, cpu: &CPU) {
+        self.header.push(("ramChecksum".to_string(), format!("{} {:08x}", frame, ram_checksum(cpu))));
+    }
+
+    pub fn verify(&self, frame: u64, cpu: &CPU) -> Result<(), MovieError> {   //call after the frame ran
+        let found = ram_checksum(cpu);
+        for (_, expected) in self.checkpoints().into_iter().filter(|(at, _)| *at == frame) {
+            if expected != found {
+                return Err(MovieError::Desync { frame, expected, found });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in &self.header {
+            out.push_str(&format!("{} {}\n", key, value));
+        }
+        for input in &self.frames {
+            let pads: Vec<String> = input
+                .pads
+                .iter()
+                .map(|pad| {
+                    PAD_COLUMNS
+                        .iter()
+                        .enumerate()
+                        .map(|(column, &name)| if pad.bits() & (0x80 >> column) != 0 { name as char } else { '.' })
+                        .collect()
+                })
+                .collect();
+            out.push_str(&format!("|{}|{}|{}||\n", input.commands.bits(), pads[0], pads[1]));
+        }
+        out
+    }
+}
+
+//plays the whole movie from where the runner is, which should be just after power on.
+//hands back the RAM checksum after the last frame
+pub fn play(runner: &mut Headless, movie: &Movie, verify: bool) -> Result<u32, MovieError> {
+    movie.check_rom(runner.cpu.bus.rom().expect("the runner always has a cartridge"))?;
+    for frame in 1..=movie.len() {
+        runner.run_frame_with(movie.input(frame)).map_err(|reason| MovieError::Halted { frame, reason })?;
+        if verify {
+            movie.verify(frame, &runner.cpu)?;
+        }
+    }
+    Ok(ram_checksum(&runner.cpu))
+}
+
+
+/*
+    MD5 (RFC 1321) AND BASE64 (RFC 4648)
+
+*/
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+lazy_static! {
+    static ref MD5_CONSTANTS: [u32; 64] = {
+        let mut table = [0u32; 64];
+        for (i, k) in table.iter_mut().enumerate() {
+            *k = ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32;
+        }
+        table
+    };
+}
+
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    let mut state: [u32; 4] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476];
+    for block in message.chunks(64) {
+        let words: Vec<u32> = block.chunks(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect();
+        let [mut a, mut b, mut c, mut d] = state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(MD5_CONSTANTS[i])
+                .wrapping_add(words[g])
+                .rotate_left(MD5_SHIFTS[(i / 16) * 4 + i % 4]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(rotated);
+        }
+        for (word, add) in state.iter_mut().zip([a, b, c, d]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 16];
+    for (bytes, word) in digest.chunks_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::{joypad_echo_rom, test_rom};
+
+    #[test]
+    fn test_md5_and_base64() {
+        let hex = |digest: [u8; 16]| digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(hex(md5(b"The quick brown fox jumps over the lazy dog")), "9e107d9d372bb6826bd81d3542a419d6");
+        assert_eq!(hex(md5(&[b'a'; 200])), "887f30b43b2867f4a9accceee7d16e6c");
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(&md5(b"")), "1B2M2Y8AsgTpgAmY7PhCfg==");
+    }
+
+    #[test]
+    fn test_parse_fm2() {
+        let movie = Movie::parse(
+            "version 3\n\
+             romFilename game\n\
+             port0 1\n\
+             port1 1\n\
+             port2 0\n\
+             comment author someone\n\
+             |0|........|........||\n\
+             |1|R..U..BA|.L.....A||\n\
+             |2|....T...|        ||\n",
+        )
+        .unwrap();
+        assert_eq!(movie.len(), 3);
+        assert_eq!(movie.header("comment"), Some("author someone"));
+        assert_eq!(movie.input(2).commands, Commands::RESET);
+        assert_eq!(
+            movie.input(2).pads,
+            [
+                JoypadButton::RIGHT | JoypadButton::UP | JoypadButton::BUTTON_B | JoypadButton::BUTTON_A,
+                JoypadButton::LEFT | JoypadButton::BUTTON_A
+            ]
+        );
+        assert_eq!(movie.input(3), FrameInput { pads: [JoypadButton::START, JoypadButton::empty()], commands: Commands::POWER });
+        assert_eq!(movie.input(4), FrameInput::default());
+
+        let written = movie.to_fm2();
+        assert!(written.contains("|1|R..U..BA|.L.....A||\n"));
+        assert_eq!(Movie::parse(&written).unwrap(), movie);
+
+        assert!(Movie::parse("binary 1\n").is_err());
+        let error = Movie::parse("port0 1\n|0|..|\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: gamepad input '..' should be 8 characters");
+    }
+
+    #[test]
+    fn test_record_play_and_verify() {
+        let rom = joypad_echo_rom();
+        let mut movie = Movie::new(&rom, "input");
+        let mut runner = Headless::new(rom.clone()).unwrap();
+        for frame in 1..=20u64 {
+            let input = FrameInput {
+                pads: [JoypadButton::from_bits_truncate(frame as u8 * 7), JoypadButton::empty()],
+                commands: if frame == 12 { Commands::RESET } else { Commands::empty() },
+            };
+            movie.frames.push(input);
+            runner.run_frame_with(input).unwrap();
+            if frame % 5 == 0 {
+                movie.add_checkpoint(frame, &runner.cpu);
+            }
+        }
+        let recorded = ram_checksum(&runner.cpu);
+        let movie = Movie::parse(&movie.to_fm2()).unwrap();
+        assert_eq!(movie.checkpoints().len(), 4);
+
+        let mut replay = Headless::new(rom.clone()).unwrap();
+        assert_eq!(play(&mut replay, &movie, true), Ok(recorded));
+
+        let mut tampered = movie.clone();
+        tampered.frames[7].pads[0] = JoypadButton::SELECT;
+        match play(&mut Headless::new(rom).unwrap(), &tampered, true) {
+            Err(MovieError::Desync { frame: 10, .. }) => {}
+            other => panic!("expected a desync at the first checkpoint after the change, got {:?}", other),
+        }
+
+        let other_rom = test_rom(vec![0x4c, 0x00, 0x80]);
+        assert!(matches!(
+            play(&mut Headless::new(other_rom).unwrap(), &movie, true),
+            Err(MovieError::WrongRom { .. })
+        ));
+    }
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+
+use crate::ppu::frame::Frame;
+
+/*
+    VIDEO STREAMS
+    y4m     YUV4MPEG2 (https://wiki.multimedia.cx/index.php/YUV4MPEG2), a text header then
+            "FRAME" and the Y, Cb and Cr planes for every frame. 4:2:0 with the chroma of every
+            2x2 block averaged, full range BT.601, which ffmpeg and mpv read as they are
+    rgb     the framebuffers one after another, 256x240 RGB24 with no header
+
+    the frame rate is the NTSC one, CPU clock / 29780.5 cycles, so a WAV captured over the
+    same frames plays back in sync with the video
+*/
+const FRAME_RATE: (u32, u32) = (3_579_546, 59_561);                           //2 * 1789773 / (2 * 29780.5), about 60.0988
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoFormat {
+    Y4m,
+    Rgb,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &str) -> VideoFormat {                             //anything that isn't .y4m is written as raw RGB
+        if path.to_ascii_lowercase().ends_with(".y4m") {
+            VideoFormat::Y4m
+        } else {
+            VideoFormat::Rgb
+        }
+    }
+}
+
+pub struct VideoWriter<W: Write> {
+    out: W,
+    format: VideoFormat,
+    frames: u64,
+}
+
+impl<W: Write> VideoWriter<W> {
+    pub fn new(mut out: W, format: VideoFormat) -> io::Result<Self> {
+        if format == VideoFormat::Y4m {
+            writeln!(
+                out,
+                "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+                Frame::WIDTH,
+                Frame::HEIGHT,
+                FRAME_RATE.0,
+                FRAME_RATE.1
+            )?;
+        }
+        Ok(VideoWriter { out, format, frames: 0 })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn write_frame(&mut self, frame: &Frame) -> io::Result<()> {
+        match self.format {
+            VideoFormat::Y4m => {
+                self.out.write_all(b"FRAME\n")?;
+                self.out.write_all(&yuv420(frame))?;
+            }
+            VideoFormat::Rgb => self.out.write_all(&frame.data)?,
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+fn to_ycbcr((r, g, b): (u8, u8, u8)) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    (
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    )
+}
+
+fn yuv420(frame: &Frame) -> Vec<u8> {
+    let (width, height) = (Frame::WIDTH, Frame::HEIGHT);
+    let mut luma = Vec::with_capacity(width * height);
+    let mut cb = Vec::with_capacity(width * height / 4);
+    let mut cr = Vec::with_capacity(width * height / 4);
+    for y in 0..height {
+        for x in 0..width {
+            luma.push(to_ycbcr(frame.pixel(x, y)).0.round() as u8);
+        }
+    }
+    for y in (0..height).step_by(2) {
+        for x in (0..width).step_by(2) {
+            let (mut sum_cb, mut sum_cr) = (0.0, 0.0);
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (_, pixel_cb, pixel_cr) = to_ycbcr(frame.pixel(x + dx, y + dy));
+                sum_cb += pixel_cb;
+                sum_cr += pixel_cr;
+            }
+            cb.push((sum_cb / 4.0).round().clamp(0.0, 255.0) as u8);
+            cr.push((sum_cr / 4.0).round().clamp(0.0, 255.0) as u8);
+        }
+    }
+    luma.extend(cb);
+    luma.extend(cr);
+    luma
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_y4m_stream() {
+        let mut frame = Frame::new();
+        frame.set_pixel(0, 0, (255, 255, 255));
+        frame.set_pixel(2, 0, (255, 0, 0));
+        frame.set_pixel(3, 0, (255, 0, 0));
+        frame.set_pixel(2, 1, (255, 0, 0));
+        frame.set_pixel(3, 1, (255, 0, 0));
+
+        let mut video = VideoWriter::new(vec![], VideoFormat::Y4m).unwrap();
+        video.write_frame(&frame).unwrap();
+        video.write_frame(&Frame::new()).unwrap();
+        assert_eq!(video.frames(), 2);
+        let stream = video.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W256 H240 F3579546:59561 Ip A1:1 C420jpeg XCOLORRANGE=FULL\n";
+        assert_eq!(&stream[..header.len()], header);
+        let frame_size = 6 + 256 * 240 * 3 / 2;
+        assert_eq!(stream.len(), header.len() + 2 * frame_size);
+
+        let planes = &stream[header.len() + 6..header.len() + frame_size];
+        assert_eq!(planes[0..3], [255, 0, 76]);                               //white, black, red
+        let (cb, cr) = (&planes[256 * 240..], &planes[256 * 240 + 128 * 120..]);
+        assert_eq!((cb[0], cr[0]), (128, 128));                               //one white pixel among black is still grey
+        assert_eq!((cb[1], cr[1]), (85, 255));
+        assert_eq!(&stream[header.len() + frame_size..][..6], b"FRAME\n");
+    }
+
+    #[test]
+    fn test_raw_stream() {
+        assert_eq!(VideoFormat::from_path("run.Y4M"), VideoFormat::Y4m);
+        assert_eq!(VideoFormat::from_path("run.rgb"), VideoFormat::Rgb);
+        let mut video = VideoWriter::new(vec![], VideoFormat::Rgb).unwrap();
+        video.write_frame(&Frame::new()).unwrap();
+        assert_eq!(video.finish().unwrap().len(), 256 * 240 * 3);
+    }
+}
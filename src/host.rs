@@ -0,0 +1,127 @@
+use crate::bus::Bus;
+use crate::CPU::{StopReason, CPU};
+
+/*
+    HOST INTEGRATION
+    CPU::run_with_host calls the host before every instruction and once whenever the PPU
+    finishes a frame (the start of vblank, when the framebuffer is complete).
+    both calls get the whole CPU, so a host reads memory through cpu.bus.peek, feeds input
+    through cpu.bus.joypad1_mut() and ends the run with cpu.request_stop()
+*/
+pub trait Host {
+    fn on_instruction(&mut self, _cpu: &mut CPU<Bus>) {}
+
+    fn on_frame(&mut self, _cpu: &mut CPU<Bus>) {}
+}
+
+//a host made of two closures, for when a struct would be overkill
+pub struct Callbacks<I, F> {
+    pub on_instruction: I,
+    pub on_frame: F,
+}
+
+impl<I, F> Host for Callbacks<I, F>
+where
+    I: FnMut(&mut CPU<Bus>),
+    F: FnMut(&mut CPU<Bus>),
+{
+    fn on_instruction(&mut self, cpu: &mut CPU<Bus>) {
+        (self.on_instruction)(cpu);
+    }
+
+    fn on_frame(&mut self, cpu: &mut CPU<Bus>) {
+        (self.on_frame)(cpu);
+    }
+}
+
+impl CPU {
+    pub fn run_with_host<H: Host>(&mut self, host: &mut H) -> StopReason {   //only returns on a halt or a stop request
+        let mut frame = self.bus.ppu().frame_count;
+        self.run_until(|cpu| {
+            let current = cpu.bus.ppu().frame_count;
+            if current != frame {
+                frame = current;
+                host.on_frame(cpu);
+            }
+            host.on_instruction(cpu);
+            false
+        })
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::joypad::JoypadButton;
+    use crate::CPU::Mem;
+
+    struct Recorder {
+        instructions: u64,
+        frames: Vec<u64>,
+        stop_after_frames: usize,
+    }
+
+    impl Host for Recorder {
+        fn on_instruction(&mut self, _cpu: &mut CPU<Bus>) {
+            self.instructions += 1;
+        }
+
+        fn on_frame(&mut self, cpu: &mut CPU<Bus>) {
+            self.frames.push(cpu.bus.ppu().frame_count);
+            if self.frames.len() == self.stop_after_frames {
+                cpu.request_stop();
+            }
+        }
+    }
+
+    #[test]
+    fn test_host_sees_instructions_and_frames() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x4c, 0x00, 0x80]);                                    //JMP $8000 forever
+        cpu.reset();
+        let mut host = Recorder { instructions: 0, frames: vec![], stop_after_frames: 3 };
+        assert_eq!(cpu.run_with_host(&mut host), StopReason::HostRequest);
+        assert_eq!(host.frames, vec![1, 2, 3]);
+        assert!(host.instructions > 2 * 29780 / 3);                           //two whole frames and most of the first, 3 cycles per JMP
+        assert_eq!(cpu.bus.ppu().frame_count, 3);
+    }
+
+    #[test]
+    fn test_host_takes_brk_as_an_interrupt() {
+        let mut cpu = CPU::new();
+        cpu.load(vec![0x00, 0xea, 0x4c, 0x00, 0x80]);                        //BRK, its padding byte, JMP $8000
+        cpu.mem_write_u16(0xfffe, 0x9000);
+        for (i, byte) in [0xa9, 0x01, 0x85, 0x10, 0x40].iter().enumerate() {   //LDA #1, STA $10, RTI
+            cpu.mem_write(0x9000 + i as u16, *byte);
+        }
+        cpu.reset();
+        let mut host = Recorder { instructions: 0, frames: vec![], stop_after_frames: 1 };
+        assert_eq!(cpu.run_with_host(&mut host), StopReason::HostRequest);
+        assert_eq!(cpu.mem_read(0x10), 1);
+    }
+
+    #[test]
+    fn test_host_injects_input_and_reads_memory() {
+        let mut cpu = CPU::new();
+        cpu.config.halt_on_brk = true;
+        //LDA #1, STA $4016, LDA #0, STA $4016, LDA $4016, STA $10, BRK
+        cpu.load(vec![0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, 0xad, 0x16, 0x40, 0x85, 0x10, 0x00]);
+        cpu.reset();
+        let mut seen = vec![];
+        let mut host = Callbacks {
+            on_instruction: |cpu: &mut CPU<Bus>| {
+                cpu.bus.joypad1_mut().set_buttons(JoypadButton::BUTTON_A);
+                seen.push(cpu.program_counter);
+            },
+            on_frame: |_: &mut CPU<Bus>| {},
+        };
+        assert_eq!(cpu.run_with_host(&mut host), StopReason::Brk { address: 0x800f });
+        assert_eq!(seen.len(), 7);
+        assert_eq!(cpu.mem_read(0x10) & 1, 1);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    STANDARD CONTROLLER (https://www.nesdev.org/wiki/Standard_controller)
+    writing 1 to $4016 keeps reloading the shift register, writing 0 latches it
+    each read of $4016/$4017 then returns the next button in bit 0:
+    A, B, Select, Start, Up, Down, Left, Right, and 1 for every read after that
+*/
+bitflags! {
+    #[derive(Default)]
+    pub struct JoypadButton: u8 {
+        const RIGHT             = 0b10000000;
+        const LEFT              = 0b01000000;
+        const DOWN              = 0b00100000;
+        const UP                = 0b00010000;
+        const START             = 0b00001000;
+        const SELECT            = 0b00000100;
+        const BUTTON_B          = 0b00000010;
+        const BUTTON_A          = 0b00000001;
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: JoypadButton,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad::default()
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    pub fn read(&mut self) -> u8 {                                            //only bit 0 is driven, the bus fills in the rest
+        if self.button_index > 7 {
+            return 1;
+        }
+        let response = (self.button_status.bits() >> self.button_index) & 1;
+        if !self.strobe {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        self.button_status.set(button, pressed);
+    }
+
+    pub fn set_buttons(&mut self, buttons: JoypadButton) {                    //replaces the whole state, handy for scripted input
+        self.button_status = buttons;
+    }
+
+    pub fn buttons(&self) -> JoypadButton {
+        self.button_status
+    }
+}
+
+impl Snapshot for Joypad {
+    fn save(&self, w: &mut StateWriter) {
+        w.bool(self.strobe);
+        w.u8(self.button_index);
+        w.u8(self.button_status.bits());
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.strobe = r.bool()?;
+        self.button_index = r.u8()?;
+        self.button_status = JoypadButton::from_bits_truncate(r.u8()?);
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn read_all(joypad: &mut Joypad) -> Vec<u8> {
+        (0..10).map(|_| joypad.read()).collect()
+    }
+
+    #[test]
+    fn test_serial_read_order() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::BUTTON_A, true);
+        joypad.set_button_pressed_status(JoypadButton::START, true);
+        joypad.set_button_pressed_status(JoypadButton::RIGHT, true);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(read_all(&mut joypad), vec![1, 0, 0, 1, 0, 0, 0, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_keeps_returning_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::BUTTON_A);
+        joypad.write(1);
+        assert_eq!(read_all(&mut joypad), vec![1; 10]);
+
+        joypad.set_buttons(JoypadButton::BUTTON_B);
+        assert_eq!(joypad.read(), 0);
+        joypad.write(0);
+        assert_eq!(read_all(&mut joypad), vec![0, 1, 0, 0, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_restarts_sequence() {
+        let mut joypad = Joypad::new();
+        joypad.set_buttons(JoypadButton::SELECT);
+        joypad.write(1);
+        joypad.write(0);
+        joypad.read();
+        joypad.read();
+        assert_eq!(joypad.read(), 1);
+        joypad.write(1);
+        joypad.write(0);
+        assert_eq!(joypad.read(), 0);
+
+        joypad.set_button_pressed_status(JoypadButton::SELECT, false);
+        assert_eq!(joypad.buttons(), JoypadButton::empty());
+    }
+}
@@ -0,0 +1,25 @@
+#[allow(non_snake_case)]
+pub mod CPU;
+pub mod apu;
+pub mod assembler;
+pub mod bus;
+pub mod cartridge;
+pub mod cheats;
+pub mod debugger;
+pub mod disasm;
+pub mod headless;
+pub mod host;
+pub mod joypad;
+pub mod mapper;
+pub mod opcodes;
+pub mod ppu;
+pub mod ramsearch;
+pub mod rewind;
+pub mod savestate;
+pub mod trace;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate bitflags;
@@ -1,12 +1,296 @@
-pub mod CPU;
-pub mod opcodes;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::process;
 
-#[macro_use]
-extern crate lazy_static;
+use nes_emulator_rust::apu::resampler::DEFAULT_SAMPLE_RATE;
+use nes_emulator_rust::cartridge::{Rom, PRG_ROM_PAGE_SIZE};
+use nes_emulator_rust::disasm;
+use nes_emulator_rust::headless::image::ImageFormat;
+use nes_emulator_rust::headless::movie::{ram_checksum, Movie, MovieError};
+use nes_emulator_rust::headless::video::{VideoFormat, VideoWriter};
+use nes_emulator_rust::headless::{Headless, InputScript};
+use nes_emulator_rust::ramsearch::{ValueType, Watch, WatchList};
 
-#[macro_use]
-extern crate bitflags;
+const USAGE: &str = "\
+usage:
+  NES_Emulator_rust disasm <rom.nes> [bank]   disassemble the 16KiB PRG banks
+  NES_Emulator_rust run <rom.nes> [options]   run without a display and save screenshots
+    --frames <n>            frames to run (default 60, or the length of the movie)
+    --input <script>        controller input, lines of \"FRAME[-LAST] BUTTONS [BUTTONS]\"
+                            or \"FRAME reset|power\"
+    --movie <file.fm2>      play the input of an FCEUX movie instead of a script
+    --verify                stop with an error when the movie's RAM checksums don't match
+    --expect-ram <crc32>    fail unless the RAM checksum after the last frame is this
+    --record <file.fm2>     save the input of the run as a movie, with RAM checksums
+    --cheats <file>         Game Genie and Pro Action Replay codes, one per line
+    --watch <addr[:type],..>  print these addresses after every frame, type is u8 (default),
+                            s8, u16 or s16
+    --screenshot <n,n,..>   frames to save (default the last one)
+    --format <png|ppm|rgb>  screenshot format (default png)
+    --out <dir>             where screenshots go (default the current directory)
+    --wav <file>            record the sound of the whole run
+    --stems                 also record every channel on its own, next to the WAV file
+    --video <file>          record every frame, as Y4M for .y4m files and raw RGB24 otherwise,
+                            together with --wav the two line up";
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
+}
+
+fn load_rom(path: &str) -> Rom {
+    Rom::from_file(path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    })
+}
+
+/*
+    DISASM
+    every 16KiB bank is listed on its own. the last bank is placed at $C000 where the vectors
+    live, the others at $8000, which is where UxROM, MMC1 and NROM-256 put them
+*/
+fn disasm_command(args: &[String]) {
+    let rom = load_rom(args.first().unwrap_or_else(|| fail(USAGE)));
+    let banks: Vec<&[u8]> = rom.prg_rom.chunks(PRG_ROM_PAGE_SIZE).collect();
+    let selected: Vec<usize> = match args.get(1) {
+        Some(bank) => match bank.parse::<usize>() {
+            Ok(bank) if bank < banks.len() => vec![bank],
+            _ => fail(&format!("bank should be a number below {}", banks.len())),
+        },
+        None => (0..banks.len()).collect(),
+    };
+
+    for bank in selected {
+        let origin: u16 = if bank + 1 == banks.len() { 0xC000 } else { 0x8000 };
+        println!("; PRG bank {} at ${:04X}-${:04X}", bank, origin, origin as usize + banks[bank].len() - 1);
+        println!("{}", disasm::disassemble(banks[bank], origin, &[]));
+    }
+}
+
+/*
+    RUN
+    screenshots are named after the ROM and the frame, e.g. smb_00060.png
+*/
+const CHECKPOINT_INTERVAL: u64 = 60;                                         //frames between RAM checksums in recorded movies
+
+struct RunOptions {
+    frames: Option<u64>,
+    script: InputScript,
+    movie: Option<Movie>,
+    verify: bool,
+    expect_ram: Option<u32>,
+    record: Option<PathBuf>,
+    watches: WatchList,
+    cheats: Option<(String, String)>,                                         //path and contents
+    screenshots: Vec<u64>,
+    format: ImageFormat,
+    out: PathBuf,
+    wav: Option<PathBuf>,
+    stems: bool,
+    video: Option<String>,
+}
+
+fn write_file(path: &Path, data: &[u8]) {
+    if let Err(err) = fs::write(path, data) {
+        eprintln!("{}: {}", path.display(), err);
+        process::exit(1);
+    }
+    println!("{}", path.display());
+}
+
+fn parse_number(text: &str) -> u64 {
+    text.parse().unwrap_or_else(|_| fail(&format!("'{}' is not a number", text)))
+}
+
+fn parse_watch(text: &str) -> Option<Watch> {                                 //"075A" or "$0086:s16"
+    let (addr, value_type) = text.split_once(':').unwrap_or((text, "u8"));
+    let addr = u16::from_str_radix(addr.trim_start_matches('$'), 16).ok()?;
+    Some(Watch { addr, value_type: ValueType::parse(value_type)?, name: format!("${:04X}", addr) })
+}
+
+fn parse_run_options(args: &[String]) -> RunOptions {
+    let mut options = RunOptions {
+        frames: None,
+        script: InputScript::new(),
+        movie: None,
+        verify: false,
+        expect_ram: None,
+        record: None,
+        watches: WatchList::new(),
+        cheats: None,
+        screenshots: vec![],
+        format: ImageFormat::Png,
+        out: PathBuf::from("."),
+        wav: None,
+        stems: false,
+        video: None,
+    };
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        match flag.as_str() {
+            "--stems" => {
+                options.stems = true;
+                continue;
+            }
+            "--verify" => {
+                options.verify = true;
+                continue;
+            }
+            _ => (),
+        }
+        let value = args.next().unwrap_or_else(|| fail(USAGE));
+        match flag.as_str() {
+            "--frames" => options.frames = Some(parse_number(value)),
+            "--input" => {
+                let text = fs::read_to_string(value).unwrap_or_else(|err| fail(&format!("{}: {}", value, err)));
+                options.script = InputScript::parse(&text).unwrap_or_else(|err| fail(&format!("{}: {}", value, err)));
+            }
+            "--movie" => {
+                let text = fs::read_to_string(value).unwrap_or_else(|err| fail(&format!("{}: {}", value, err)));
+                options.movie = Some(Movie::parse(&text).unwrap_or_else(|err| fail(&format!("{}: {}", value, err))));
+            }
+            "--expect-ram" => {
+                let crc = u32::from_str_radix(value.trim_start_matches("0x"), 16).ok();
+                options.expect_ram = Some(crc.unwrap_or_else(|| fail(&format!("'{}' is not a CRC-32 in hex", value))));
+            }
+            "--watch" => {
+                for item in value.split(',') {
+                    options.watches.add(parse_watch(item).unwrap_or_else(|| fail(&format!("bad watch '{}'", item))));
+                }
+            }
+            "--record" => options.record = Some(PathBuf::from(value)),
+            "--cheats" => {
+                let text = fs::read_to_string(value).unwrap_or_else(|err| fail(&format!("{}: {}", value, err)));
+                options.cheats = Some((value.clone(), text));
+            }
+            "--screenshot" => options.screenshots = value.split(',').map(parse_number).collect(),
+            "--format" => {
+                options.format = ImageFormat::parse(value).unwrap_or_else(|| fail(&format!("unknown format '{}'", value)))
+            }
+            "--out" => options.out = PathBuf::from(value),
+            "--wav" => options.wav = Some(PathBuf::from(value)),
+            "--video" => options.video = Some(value.clone()),
+            _ => fail(USAGE),
+        }
+    }
+    if options.stems && options.wav.is_none() {
+        fail("--stems needs --wav");
+    }
+    if options.verify && options.movie.is_none() {
+        fail("--verify needs --movie");
+    }
+    options
+}
+
+fn run_command(args: &[String]) {
+    let path = args.first().unwrap_or_else(|| fail(USAGE));
+    let mut options = parse_run_options(&args[1..]);
+    let name = Path::new(path).file_stem().map_or("frame".into(), |stem| stem.to_string_lossy());
+    let rom = load_rom(path);
+    if let Some(Err(err)) = options.movie.as_ref().map(|movie| movie.check_rom(&rom)) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    let mut recording = options.record.as_ref().map(|_| Movie::new(&rom, &name));
+    let mut runner = Headless::new(rom).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    runner.script = options.script;
+    if let Some((path, text)) = &options.cheats {
+        if let Err(err) = runner.cpu.bus.cheats_mut().load(text) {
+            fail(&format!("{}: {}", path, err));
+        }
+    }
+    if options.wav.is_some() {
+        runner.start_audio_capture(DEFAULT_SAMPLE_RATE, options.stems);
+    }
+    let frames = options.frames.unwrap_or(options.movie.as_ref().map_or(60, Movie::len));
+    let screenshots = if options.screenshots.is_empty() { vec![frames] } else { options.screenshots };
+
+    let mut video = options.video.as_ref().map(|path| {
+        let file = File::create(path).unwrap_or_else(|err| fail(&format!("{}: {}", path, err)));
+        VideoWriter::new(BufWriter::new(file), VideoFormat::from_path(path))
+            .unwrap_or_else(|err| fail(&format!("{}: {}", path, err)))
+    });
+
+    let mut result = Ok(());
+    for frame in 1..=frames {
+        let input = match &options.movie {
+            Some(movie) => movie.input(frame),
+            None => runner.script.input_at(frame),
+        };
+        if let Err(reason) = runner.run_frame_with(input) {
+            result = Err(MovieError::Halted { frame, reason });
+            break;
+        }
+        if !options.watches.is_empty() {
+            let values = options.watches.update(&runner.cpu);
+            let report: Vec<String> =
+                options.watches.list().iter().zip(values).map(|(watch, v)| format!("{}={}", watch.name, v.value)).collect();
+            println!("frame {}: {}", frame, report.join(" "));
+        }
+        if let Some(movie) = recording.as_mut() {
+            movie.frames.push(input);
+            if frame % CHECKPOINT_INTERVAL == 0 || frame == frames {
+                movie.add_checkpoint(frame, &runner.cpu);
+            }
+        }
+        if let Some(video) = video.as_mut() {
+            if let Err(err) = video.write_frame(runner.frame_buffer()) {
+                fail(&format!("{}: {}", options.video.as_ref().unwrap(), err));
+            }
+        }
+        if screenshots.contains(&frame) {
+            let file = options.out.join(format!("{}_{:05}.{}", name, frame, options.format.extension()));
+            write_file(&file, &options.format.encode(runner.frame_buffer()));
+        }
+        if let (true, Some(movie)) = (options.verify, &options.movie) {
+            if let Err(err) = movie.verify(frame, &runner.cpu) {
+                result = Err(err);
+                break;
+            }
+        }
+    }
+
+    if let (Some(path), Some(video)) = (&options.video, video) {
+        match video.finish() {
+            Ok(_) => println!("{}", path),
+            Err(err) => fail(&format!("{}: {}", path, err)),
+        }
+    }
+    if let (Some(path), Some(recording)) = (&options.wav, runner.stop_audio_capture()) {
+        write_file(path, &recording.mix_wav());                              //written even when the run stops early
+        let stem = path.file_stem().map_or("audio".into(), |stem| stem.to_string_lossy());
+        for (channel, wav) in recording.stem_wavs() {
+            write_file(&path.with_file_name(format!("{}_{}.wav", stem, channel)), &wav);
+        }
+    }
+    if let (Some(path), Some(movie)) = (&options.record, recording) {
+        write_file(path, movie.to_fm2().as_bytes());                         //the frames up to where the run stopped
+    }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+    let crc = ram_checksum(&runner.cpu);
+    if options.movie.is_some() || options.expect_ram.is_some() {
+        println!("frame {}: RAM checksum {:08x}", runner.frame(), crc);
+    }
+    if options.expect_ram.is_some_and(|expected| expected != crc) {
+        eprintln!("RAM checksum should be {:08x}", options.expect_ram.unwrap());
+        process::exit(1);
+    }
+}
 
 fn main() {
-    println!("Hello, world");
-}
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("disasm") => disasm_command(&args[1..]),
+        Some("run") => run_command(&args[1..]),
+        _ => fail(USAGE),
+    }
+}
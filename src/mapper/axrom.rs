@@ -0,0 +1,93 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//mapper 7 (https://www.nesdev.org/wiki/AxROM), writes to $8000-$FFFF pick a 32KiB PRG bank (bits 0-2) and a single screen nametable (bit 4)
+pub struct AxRom {
+    memory: CartridgeMemory,
+    prg_bank: usize,
+    mirroring: Mirroring,
+}
+
+impl AxRom {
+    pub fn new(rom: &Rom) -> Self {
+        AxRom {
+            memory: CartridgeMemory::from_rom(rom),
+            prg_bank: 0,
+            mirroring: Mirroring::SingleScreenLower,
+        }
+    }
+}
+
+impl Mapper for AxRom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xFFFF => self.memory.read_prg(self.prg_bank, 0x8000, addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.write_prg_ram(addr, data),
+            0x8000 ..= 0xFFFF => {
+                self.prg_bank = (data & 0b111) as usize;
+                self.mirroring = if data & 0b1_0000 != 0 {
+                    Mirroring::SingleScreenUpper
+                } else {
+                    Mirroring::SingleScreenLower
+                };
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for AxRom {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        w.u32(self.prg_bank as u32);
+        self.mirroring.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.prg_bank = r.u32()? as usize;
+        self.mirroring.load(r)?;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test::banked_rom;
+
+    #[test]
+    fn test_prg_bank_and_single_screen() {
+        let mut mapper = AxRom::new(&banked_rom(7, 8, 0));
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.cpu_write(0x8000, 0b1_0010);
+        assert_eq!(mapper.cpu_read(0x8000), 2 * 32);
+        assert_eq!(mapper.cpu_read(0xFFFF), 2 * 32 + 31);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+}
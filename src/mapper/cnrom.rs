@@ -0,0 +1,88 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//mapper 3 (https://www.nesdev.org/wiki/CNROM), fixed PRG and an 8KiB CHR bank selected by writes to $8000-$FFFF
+pub struct CnRom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    chr_bank: usize,
+}
+
+impl CnRom {
+    pub fn new(rom: &Rom) -> Self {
+        CnRom {
+            memory: CartridgeMemory::from_rom(rom),
+            mirroring: rom.screen_mirroring,
+            chr_bank: 0,
+        }
+    }
+}
+
+impl Mapper for CnRom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xFFFF => self.memory.read_prg(0, 0x8000, addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.write_prg_ram(addr, data),
+            0x8000 ..= 0xFFFF => self.chr_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(self.chr_bank, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(self.chr_bank, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for CnRom {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        self.mirroring.save(w);
+        w.u32(self.chr_bank as u32);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.mirroring.load(r)?;
+        self.chr_bank = r.u32()? as usize;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test::banked_rom;
+
+    #[test]
+    fn test_chr_bank_select() {
+        let mut mapper = CnRom::new(&banked_rom(3, 2, 4));
+        assert_eq!(mapper.ppu_read(0x0000), 0);
+        mapper.cpu_write(0x8000, 2);
+        assert_eq!(mapper.ppu_read(0x0000), 16);
+        assert_eq!(mapper.ppu_read(0x1FFF), 23);
+        mapper.cpu_write(0xFFFF, 5);                                          //only two bank bits are wired on 32KiB of CHR
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.cpu_read(0xC000), 16);
+    }
+}
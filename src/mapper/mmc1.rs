@@ -0,0 +1,221 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    mapper 1 (https://www.nesdev.org/wiki/MMC1)
+    registers are loaded one bit at a time through a 5 bit shift register, the fifth write
+    to $8000-$FFFF copies it into the register picked by bits 13-14 of that last address
+    $8000 - $9FFF   control      CPPMM  CHR mode, PRG mode, mirroring
+    $A000 - $BFFF   CHR bank 0
+    $C000 - $DFFF   CHR bank 1
+    $E000 - $FFFF   PRG bank     RPPPP  PRG RAM disable (ignored), PRG bank
+*/
+const SHIFT_RESET: u8 = 0b1_0000;                                              //the marker bit reaches bit 0 after five writes
+const OUTER_PRG_BANK_SIZE: usize = 0x40000;                                   //512KiB SUROM boards use CHR bank bit 4 as PRG A18
+
+pub struct Mmc1 {
+    memory: CartridgeMemory,
+    shift_register: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(rom: &Rom) -> Self {
+        Mmc1 {
+            memory: CartridgeMemory::from_rom(rom),
+            shift_register: SHIFT_RESET,
+            control: 0b0_1100,                                                //powers up with the last bank fixed at $C000
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x8000 ..= 0x9FFF => self.control = data,
+            0xA000 ..= 0xBFFF => self.chr_bank_0 = data,
+            0xC000 ..= 0xDFFF => self.chr_bank_1 = data,
+            _ => self.prg_bank = data,
+        }
+    }
+
+    fn outer_prg_bank(&self) -> usize {
+        if self.memory.prg_rom.len() > OUTER_PRG_BANK_SIZE {
+            ((self.chr_bank_0 & 0b1_0000) >> 4) as usize * (OUTER_PRG_BANK_SIZE / 0x4000)
+        } else {
+            0
+        }
+    }
+
+    fn prg_bank_16k(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0b1111) as usize;
+        let inner_banks = self.memory.prg_bank_count(0x4000).min(OUTER_PRG_BANK_SIZE / 0x4000);
+        let lower = addr < 0xC000;
+        let inner = match (self.control >> 2) & 0b11 {
+            0 | 1 => (bank & !1) + if lower { 0 } else { 1 },                 //32KiB mode ignores the low bit
+            2 => if lower { 0 } else { bank },                                 //first bank fixed at $8000
+            _ => if lower { bank } else { inner_banks - 1 },                   //last bank fixed at $C000
+        };
+        self.outer_prg_bank() + inner
+    }
+
+    fn chr_bank_4k(&self, addr: u16) -> usize {
+        if self.control & 0b1_0000 == 0 {                                     //8KiB mode ignores the low bit
+            (self.chr_bank_0 & !1) as usize + (addr >= 0x1000) as usize
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as usize
+        } else {
+            self.chr_bank_1 as usize
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xFFFF => self.memory.read_prg(self.prg_bank_16k(addr), 0x4000, addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.write_prg_ram(addr, data),
+            0x8000 ..= 0xFFFF => {
+                if data & 0b1000_0000 != 0 {                                  //reset the shift register and lock PRG mode 3
+                    self.shift_register = SHIFT_RESET;
+                    self.control |= 0b0_1100;
+                    return;
+                }
+                let complete = self.shift_register & 1 == 1;
+                self.shift_register = (self.shift_register >> 1) | ((data & 1) << 4);
+                if complete {
+                    let value = self.shift_register;
+                    self.write_register(addr, value);
+                    self.shift_register = SHIFT_RESET;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(self.chr_bank_4k(addr), 0x1000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(self.chr_bank_4k(addr), 0x1000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+}
+
+impl Snapshot for Mmc1 {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        w.u8(self.shift_register);
+        w.u8(self.control);
+        w.u8(self.chr_bank_0);
+        w.u8(self.chr_bank_1);
+        w.u8(self.prg_bank);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.shift_register = r.u8()?;
+        self.control = r.u8()?;
+        self.chr_bank_0 = r.u8()?;
+        self.chr_bank_1 = r.u8()?;
+        self.prg_bank = r.u8()?;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test::banked_rom;
+
+    fn write_serial(mapper: &mut Mmc1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.cpu_write(addr, (value >> i) & 1);
+        }
+    }
+
+    #[test]
+    fn test_power_on_fixes_last_bank() {
+        let mut mapper = Mmc1::new(&banked_rom(1, 8, 2));
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7 * 16);
+    }
+
+    #[test]
+    fn test_serial_writes_and_prg_modes() {
+        let mut mapper = Mmc1::new(&banked_rom(1, 8, 2));
+        write_serial(&mut mapper, 0xE000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3 * 16);
+        assert_eq!(mapper.cpu_read(0xC000), 7 * 16);
+
+        write_serial(&mut mapper, 0x8000, 0b0_1000);                          //first bank fixed at $8000
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 3 * 16);
+
+        write_serial(&mut mapper, 0x8000, 0b0_0000);                          //32KiB switching
+        assert_eq!(mapper.cpu_read(0x8000), 2 * 16);
+        assert_eq!(mapper.cpu_read(0xC000), 3 * 16);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    }
+
+    #[test]
+    fn test_reset_bit_clears_shift_register() {
+        let mut mapper = Mmc1::new(&banked_rom(1, 8, 2));
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0xE000, 1);
+        mapper.cpu_write(0xE000, 0x80);
+        write_serial(&mut mapper, 0xE000, 1);
+        assert_eq!(mapper.cpu_read(0x8000), 16);
+    }
+
+    #[test]
+    fn test_chr_modes_and_mirroring() {
+        let mut mapper = Mmc1::new(&banked_rom(1, 2, 2));
+        write_serial(&mut mapper, 0x8000, 0b1_1110);                          //4KiB CHR banks, vertical mirroring
+        write_serial(&mut mapper, 0xA000, 3);
+        write_serial(&mut mapper, 0xC000, 1);
+        assert_eq!(mapper.ppu_read(0x0000), 12);
+        assert_eq!(mapper.ppu_read(0x1000), 4);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        write_serial(&mut mapper, 0x8000, 0b0_1111);                          //8KiB CHR banks, horizontal mirroring
+        assert_eq!(mapper.ppu_read(0x0000), 8);
+        assert_eq!(mapper.ppu_read(0x1000), 12);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_surom_outer_bank() {
+        let mut mapper = Mmc1::new(&banked_rom(1, 32, 0));
+        assert_eq!(mapper.cpu_read(0xC000), 15 * 16);                         //the last bank of the first 256KiB
+        write_serial(&mut mapper, 0xA000, 0b1_0000);
+        assert_eq!(mapper.cpu_read(0xC000), 0xF0);                            //the fill byte wraps every 256KiB
+        assert_eq!(mapper.cpu_read(0x8000), 0x00);
+    }
+}
@@ -0,0 +1,265 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    mapper 4 (https://www.nesdev.org/wiki/MMC3)
+    registers are picked by the address range and whether the address is even or odd
+    $8000 even  bank select      CP---RRR  CHR A12 inversion, PRG mode, target R0-R7
+    $8000 odd   bank data
+    $A000 even  mirroring        0 vertical, 1 horizontal
+    $A001 odd   PRG RAM protect  E-------W enable, write protect
+    $C000 even  IRQ latch
+    $C001 odd   IRQ reload
+    $E000 even  IRQ disable and acknowledge
+    $E001 odd   IRQ enable
+*/
+pub struct Mmc3 {
+    memory: CartridgeMemory,
+    bank_select: u8,
+    registers: [u8; 8],                                                       //R0-R1 2KiB CHR, R2-R5 1KiB CHR, R6-R7 8KiB PRG
+    mirroring: Mirroring,
+    four_screen: bool,                                                        //boards with extra nametable RAM ignore $A000
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(rom: &Rom) -> Self {
+        Mmc3 {
+            memory: CartridgeMemory::from_rom(rom),
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: rom.screen_mirroring,
+            four_screen: rom.screen_mirroring == Mirroring::FourScreen,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn prg_bank_8k(&self, addr: u16) -> usize {
+        let second_last = self.memory.prg_bank_count(0x2000).saturating_sub(2);   //an 8KiB ROM only has the one bank, read_prg wraps the +1
+        let swap = self.bank_select & 0b0100_0000 != 0;
+        match (addr, swap) {
+            (0x8000 ..= 0x9FFF, false) | (0xC000 ..= 0xDFFF, true) => self.registers[6] as usize & 0x3F,
+            (0xA000 ..= 0xBFFF, _) => self.registers[7] as usize & 0x3F,
+            (0xC000 ..= 0xDFFF, false) | (0x8000 ..= 0x9FFF, true) => second_last,
+            _ => second_last + 1,
+        }
+    }
+
+    fn chr_bank_1k(&self, addr: u16) -> usize {
+        let addr = if self.bank_select & 0b1000_0000 != 0 { addr ^ 0x1000 } else { addr };
+        let slot = (addr / 0x400) as usize;
+        match slot {
+            0 | 1 => (self.registers[0] & 0xFE) as usize + slot,
+            2 | 3 => (self.registers[1] & 0xFE) as usize + slot - 2,
+            _ => self.registers[slot - 2] as usize,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF if self.prg_ram_enabled => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xFFFF => self.memory.read_prg(self.prg_bank_8k(addr), 0x2000, addr),
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        let even = addr & 1 == 0;
+        match (addr, even) {
+            (PRG_RAM ..= 0x7FFF, _) if self.prg_ram_enabled && !self.prg_ram_write_protect => {
+                self.memory.write_prg_ram(addr, data);
+            }
+            (0x8000 ..= 0x9FFF, true) => self.bank_select = data,
+            (0x8000 ..= 0x9FFF, false) => self.registers[(self.bank_select & 0b111) as usize] = data,
+            (0xA000 ..= 0xBFFF, true) if !self.four_screen => {
+                self.mirroring = if data & 1 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            (0xA000 ..= 0xBFFF, false) => {
+                self.prg_ram_enabled = data & 0b1000_0000 != 0;
+                self.prg_ram_write_protect = data & 0b0100_0000 != 0;
+            }
+            (0xC000 ..= 0xDFFF, true) => self.irq_latch = data,
+            (0xC000 ..= 0xDFFF, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xE000 ..= 0xFFFF, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xE000 ..= 0xFFFF, false) => self.irq_enabled = true,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(self.chr_bank_1k(addr), 0x400, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(self.chr_bank_1k(addr), 0x400, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn notify_a12_rise(&mut self) {                                          //once per scanline while backgrounds use $0000 and sprites $1000
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Snapshot for Mmc3 {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        w.u8(self.bank_select);
+        w.bytes(&self.registers);
+        self.mirroring.save(w);
+        w.bool(self.four_screen);
+        w.bool(self.prg_ram_enabled);
+        w.bool(self.prg_ram_write_protect);
+        w.u8(self.irq_latch);
+        w.u8(self.irq_counter);
+        w.bool(self.irq_reload);
+        w.bool(self.irq_enabled);
+        w.bool(self.irq_pending);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.bank_select = r.u8()?;
+        r.bytes_into(&mut self.registers)?;
+        self.mirroring.load(r)?;
+        self.four_screen = r.bool()?;
+        self.prg_ram_enabled = r.bool()?;
+        self.prg_ram_write_protect = r.bool()?;
+        self.irq_latch = r.u8()?;
+        self.irq_counter = r.u8()?;
+        self.irq_reload = r.bool()?;
+        self.irq_enabled = r.bool()?;
+        self.irq_pending = r.bool()?;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test::banked_rom;
+
+    #[test]
+    fn test_prg_banking_modes() {
+        let mut mapper = Mmc3::new(&banked_rom(4, 8, 8));                     //16 banks of 8KiB
+        mapper.cpu_write(0x8000, 6);
+        mapper.cpu_write(0x8001, 3);
+        mapper.cpu_write(0x8000, 7);
+        mapper.cpu_write(0x8001, 5);
+        assert_eq!(mapper.cpu_read(0x8000), 3 * 8);
+        assert_eq!(mapper.cpu_read(0xA000), 5 * 8);
+        assert_eq!(mapper.cpu_read(0xC000), 14 * 8);
+        assert_eq!(mapper.cpu_read(0xE000), 15 * 8);
+
+        mapper.cpu_write(0x8000, 0b0100_0000);                                //swap $8000 and $C000
+        assert_eq!(mapper.cpu_read(0x8000), 14 * 8);
+        assert_eq!(mapper.cpu_read(0xC000), 3 * 8);
+        assert_eq!(mapper.cpu_read(0xE000), 15 * 8);
+    }
+
+    #[test]
+    fn test_single_8k_prg_bank() {
+        let mut rom = banked_rom(4, 1, 1);
+        rom.prg_rom.truncate(0x2000);
+        let mut mapper = Mmc3::new(&rom);                                     //both fixed banks land on the only one
+        for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+            assert_eq!(mapper.cpu_read(addr), 0);
+        }
+        assert_eq!(mapper.cpu_read(0xFC00), 7);
+    }
+
+    #[test]
+    fn test_chr_banking_and_inversion() {
+        let mut mapper = Mmc3::new(&banked_rom(4, 2, 8));                     //64 banks of 1KiB
+        for (register, bank) in [(0, 9), (1, 20), (2, 40), (3, 41), (4, 42), (5, 43)] {
+            mapper.cpu_write(0x8000, register);
+            mapper.cpu_write(0x8001, bank);
+        }
+        assert_eq!(mapper.ppu_read(0x0000), 8);                               //2KiB banks ignore the low bit
+        assert_eq!(mapper.ppu_read(0x0400), 9);
+        assert_eq!(mapper.ppu_read(0x0C00), 21);
+        assert_eq!(mapper.ppu_read(0x1C00), 43);
+
+        mapper.cpu_write(0x8000, 0b1000_0000);
+        assert_eq!(mapper.ppu_read(0x0000), 40);
+        assert_eq!(mapper.ppu_read(0x1000), 8);
+    }
+
+    #[test]
+    fn test_mirroring_and_prg_ram_protect() {
+        let mut mapper = Mmc3::new(&banked_rom(4, 2, 1));
+        mapper.cpu_write(0xA000, 1);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+        mapper.cpu_write(0xA000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+
+        mapper.cpu_write(0x6000, 0x12);
+        mapper.cpu_write(0xA001, 0b1100_0000);
+        mapper.cpu_write(0x6000, 0x34);
+        assert_eq!(mapper.cpu_read(0x6000), 0x12);
+        mapper.cpu_write(0xA001, 0);
+        assert_eq!(mapper.cpu_read(0x6000), 0);
+    }
+
+    #[test]
+    fn test_scanline_irq_counter() {
+        let mut mapper = Mmc3::new(&banked_rom(4, 2, 1));
+        mapper.cpu_write(0xC000, 2);
+        mapper.cpu_write(0xC001, 0);
+        mapper.cpu_write(0xE001, 0);
+
+        mapper.notify_a12_rise();                                             //reload to 2
+        mapper.notify_a12_rise();
+        assert!(!mapper.irq());
+        mapper.notify_a12_rise();                                             //reaches 0
+        assert!(mapper.irq());
+
+        mapper.cpu_write(0xE000, 0);                                          //acknowledge and disable
+        assert!(!mapper.irq());
+        for _ in 0..3 {
+            mapper.notify_a12_rise();
+        }
+        assert!(!mapper.irq());
+    }
+}
@@ -0,0 +1,212 @@
+pub mod axrom;
+pub mod cnrom;
+pub mod mmc1;
+pub mod mmc3;
+pub mod nrom;
+pub mod uxrom;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::{Mirroring, Rom, RomError};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use axrom::AxRom;
+use cnrom::CnRom;
+use mmc1::Mmc1;
+use mmc3::Mmc3;
+use nrom::Nrom;
+use uxrom::UxRom;
+
+/*
+    MAPPERS (https://www.nesdev.org/wiki/Mapper)
+    the cartridge sees the CPU bus from $6000 (PRG RAM and PRG ROM) and the PPU bus
+    from $0000 to $1FFF (CHR), and it decides how the nametables are mirrored
+*/
+pub const PRG_RAM: u16 = 0x6000;
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_RAM_SIZE: usize = 0x2000;
+
+pub trait Mapper: Snapshot {                                                  //save states hold the bank registers and the cartridge RAM
+    fn cpu_read(&mut self, addr: u16) -> u8;                                  //$6000 - $FFFF
+    fn cpu_write(&mut self, addr: u16, data: u8);
+    fn ppu_read(&mut self, addr: u16) -> u8;                                  //$0000 - $1FFF
+    fn ppu_write(&mut self, addr: u16, data: u8);
+    fn mirroring(&self) -> Mirroring;
+
+    fn irq(&self) -> bool {                                                   //level of the cartridge's /IRQ output
+        false
+    }
+
+    fn notify_a12_rise(&mut self) {}                                          //PPU address line 12 went high after being low for a while
+
+    fn notify_scanline(&mut self) {}                                          //the PPU finished fetching a rendered scanline (dot 260)
+}
+
+pub type SharedMapper = Rc<RefCell<dyn Mapper>>;                              //the CPU bus and the PPU both talk to the same cartridge
+
+pub fn mapper_for(rom: &Rom) -> Result<SharedMapper, RomError> {
+    let min_prg_size = match rom.mapper {                                     //the smallest PRG bank the board switches in
+        0 ..= 3 => 0x4000,
+        4 => 0x2000,
+        7 => 0x8000,
+        id => return Err(RomError::UnsupportedMapper(id)),
+    };
+    if rom.prg_rom.is_empty() {
+        return Err(RomError::NoPrgRom);
+    }
+    if rom.prg_rom.len() < min_prg_size {
+        return Err(RomError::PrgRomTooSmall { size: rom.prg_rom.len(), minimum: min_prg_size });
+    }
+    let mapper: SharedMapper = match rom.mapper {
+        0 => Rc::new(RefCell::new(Nrom::new(rom))),
+        1 => Rc::new(RefCell::new(Mmc1::new(rom))),
+        2 => Rc::new(RefCell::new(UxRom::new(rom))),
+        3 => Rc::new(RefCell::new(CnRom::new(rom))),
+        4 => Rc::new(RefCell::new(Mmc3::new(rom))),
+        7 => Rc::new(RefCell::new(AxRom::new(rom))),
+        _ => unreachable!("checked above"),
+    };
+    Ok(mapper)
+}
+
+pub struct CartridgeMemory {                                                  //the memory every board has, mappers only differ in how they bank it
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_is_ram: bool,                                                     //boards without CHR ROM have writable pattern tables
+    pub prg_ram: Vec<u8>,
+}
+
+impl CartridgeMemory {
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>) -> Self {
+        let chr_is_ram = chr_rom.is_empty();
+        CartridgeMemory {
+            prg_rom,
+            chr: if chr_is_ram { vec![0; CHR_RAM_SIZE] } else { chr_rom },
+            chr_is_ram,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+        }
+    }
+
+    pub fn from_rom(rom: &Rom) -> Self {
+        let mut memory = CartridgeMemory::new(rom.prg_rom.clone(), rom.chr_rom.clone());
+        if memory.chr_is_ram && rom.chr_ram_size > CHR_RAM_SIZE {
+            memory.chr = vec![0; rom.chr_ram_size];
+        }
+        if let Some(trainer) = &rom.trainer {                                 //the trainer lives in PRG RAM at $7000-$71FF
+            memory.prg_ram[0x1000 .. 0x1000 + trainer.len()].copy_from_slice(trainer);
+        }
+        memory
+    }
+
+    pub fn read_prg(&self, bank: usize, bank_size: usize, addr: u16) -> u8 {  //bank numbers wrap around the ROM like the missing address lines do
+        let banks = (self.prg_rom.len() / bank_size).max(1);
+        let index = (bank % banks) * bank_size + (addr as usize % bank_size);
+        self.prg_rom[index % self.prg_rom.len()]                              //a 16KiB NROM shows up twice in a 32KiB window
+    }
+
+    pub fn prg_bank_count(&self, bank_size: usize) -> usize {
+        (self.prg_rom.len() / bank_size).max(1)
+    }
+
+    fn chr_index(&self, bank: usize, bank_size: usize, addr: u16) -> usize {
+        let banks = (self.chr.len() / bank_size).max(1);
+        ((bank % banks) * bank_size + (addr as usize % bank_size)) % self.chr.len()
+    }
+
+    pub fn read_chr(&self, bank: usize, bank_size: usize, addr: u16) -> u8 {
+        self.chr[self.chr_index(bank, bank_size, addr)]
+    }
+
+    pub fn write_chr(&mut self, bank: usize, bank_size: usize, addr: u16, data: u8) {
+        if self.chr_is_ram {
+            let index = self.chr_index(bank, bank_size, addr);
+            self.chr[index] = data;
+        }
+    }
+
+    pub fn read_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram[(addr - PRG_RAM) as usize % PRG_RAM_SIZE]
+    }
+
+    pub fn write_prg_ram(&mut self, addr: u16, data: u8) {
+        self.prg_ram[(addr - PRG_RAM) as usize % PRG_RAM_SIZE] = data;
+    }
+}
+
+impl Snapshot for CartridgeMemory {                                           //PRG and CHR ROM come from the ROM file, only the RAM is saved
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.prg_ram);
+        w.bytes(if self.chr_is_ram { &self.chr } else { &[] });
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.prg_ram)?;
+        if self.chr_is_ram {
+            r.bytes_into(&mut self.chr)?;
+        } else {
+            r.bytes_into(&mut [])?;
+        }
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::cartridge::test::{create_rom, TestRom};
+    use crate::cartridge::{CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+
+    pub fn banked_rom(mapper: u8, prg_banks_16k: usize, chr_banks_8k: usize) -> Rom {  //every 1KiB of PRG and CHR is filled with its own index
+        let prg_rom: Vec<u8> = (0..prg_banks_16k * PRG_ROM_PAGE_SIZE).map(|i| (i / 0x400) as u8).collect();
+        let chr_rom: Vec<u8> = (0..chr_banks_8k * CHR_ROM_PAGE_SIZE).map(|i| (i / 0x400) as u8).collect();
+        let raw = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, prg_banks_16k as u8, chr_banks_8k as u8, (mapper & 0x0F) << 4, mapper & 0xF0,
+                00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            prg_rom,
+            chr_rom,
+        });
+        Rom::new(&raw).unwrap()
+    }
+
+    #[test]
+    fn test_mapper_for_known_and_unknown_ids() {
+        for id in [0, 1, 2, 3, 4, 7] {
+            assert!(mapper_for(&banked_rom(id, 2, 1)).is_ok());
+        }
+        match mapper_for(&banked_rom(5, 2, 1)) {
+            Err(RomError::UnsupportedMapper(5)) => {}
+            _ => panic!("mapper 5 should be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_mapper_for_rejects_missing_prg_rom() {
+        let mut rom = banked_rom(0, 1, 1);
+        rom.prg_rom.clear();
+        assert!(matches!(mapper_for(&rom), Err(RomError::NoPrgRom)));
+
+        let mut rom = banked_rom(4, 1, 1);
+        rom.prg_rom.truncate(0x1000);
+        assert!(matches!(mapper_for(&rom), Err(RomError::PrgRomTooSmall { size: 0x1000, minimum: 0x2000 })));
+        assert!(matches!(mapper_for(&banked_rom(7, 1, 0)), Err(RomError::PrgRomTooSmall { minimum: 0x8000, .. })));
+    }
+
+    #[test]
+    fn test_chr_ram_is_writable_and_chr_rom_is_not() {
+        let mut memory = CartridgeMemory::new(vec![0; PRG_ROM_PAGE_SIZE], vec![]);
+        memory.write_chr(0, 0x2000, 0x0123, 0x45);
+        assert_eq!(memory.read_chr(0, 0x2000, 0x0123), 0x45);
+
+        let mut memory = CartridgeMemory::new(vec![0; PRG_ROM_PAGE_SIZE], vec![9; CHR_ROM_PAGE_SIZE]);
+        memory.write_chr(0, 0x2000, 0x0123, 0x45);
+        assert_eq!(memory.read_chr(0, 0x2000, 0x0123), 9);
+    }
+}
@@ -0,0 +1,66 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom, PRG_ROM_PAGE_SIZE};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+//mapper 0 (https://www.nesdev.org/wiki/NROM), no bank switching at all
+pub struct Nrom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+}
+
+impl Nrom {
+    pub fn new(rom: &Rom) -> Self {
+        Nrom {
+            memory: CartridgeMemory::from_rom(rom),
+            mirroring: rom.screen_mirroring,
+        }
+    }
+
+    pub fn with_chr(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {        //a board with blank PRG, for driving the PPU on its own
+        Nrom {
+            memory: CartridgeMemory::new(vec![0; PRG_ROM_PAGE_SIZE], chr_rom),
+            mirroring,
+        }
+    }
+}
+
+impl Mapper for Nrom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xFFFF => self.memory.read_prg(0, 0x8000, addr),    //16KiB boards mirror their single bank into $C000-$FFFF
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        if let PRG_RAM ..= 0x7FFF = addr {
+            self.memory.write_prg_ram(addr, data);
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for Nrom {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        self.mirroring.save(w);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.mirroring.load(r)?;
+        Ok(())
+    }
+}
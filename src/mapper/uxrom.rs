@@ -0,0 +1,99 @@
+use super::{CartridgeMemory, Mapper, PRG_RAM};
+use crate::cartridge::{Mirroring, Rom};
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+
+/*
+    mapper 2 (https://www.nesdev.org/wiki/UxROM)
+    $8000 - $BFFF   switchable 16KiB PRG bank, selected by any write to $8000-$FFFF
+    $C000 - $FFFF   fixed to the last PRG bank
+*/
+pub struct UxRom {
+    memory: CartridgeMemory,
+    mirroring: Mirroring,
+    prg_bank: usize,
+}
+
+impl UxRom {
+    pub fn new(rom: &Rom) -> Self {
+        UxRom {
+            memory: CartridgeMemory::from_rom(rom),
+            mirroring: rom.screen_mirroring,
+            prg_bank: 0,
+        }
+    }
+}
+
+impl Mapper for UxRom {
+    fn cpu_read(&mut self, addr: u16) -> u8 {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.read_prg_ram(addr),
+            0x8000 ..= 0xBFFF => self.memory.read_prg(self.prg_bank, 0x4000, addr),
+            0xC000 ..= 0xFFFF => {
+                let last = self.memory.prg_bank_count(0x4000) - 1;
+                self.memory.read_prg(last, 0x4000, addr)
+            }
+            _ => 0,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            PRG_RAM ..= 0x7FFF => self.memory.write_prg_ram(addr, data),
+            0x8000 ..= 0xFFFF => self.prg_bank = data as usize,
+            _ => {}
+        }
+    }
+
+    fn ppu_read(&mut self, addr: u16) -> u8 {
+        self.memory.read_chr(0, 0x2000, addr)
+    }
+
+    fn ppu_write(&mut self, addr: u16, data: u8) {
+        self.memory.write_chr(0, 0x2000, addr, data);
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+impl Snapshot for UxRom {
+    fn save(&self, w: &mut StateWriter) {
+        self.memory.save(w);
+        self.mirroring.save(w);
+        w.u32(self.prg_bank as u32);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        self.memory.load(r)?;
+        self.mirroring.load(r)?;
+        self.prg_bank = r.u32()? as usize;
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mapper::test::banked_rom;
+
+    #[test]
+    fn test_switchable_and_fixed_banks() {
+        let mut mapper = UxRom::new(&banked_rom(2, 8, 0));
+        assert_eq!(mapper.cpu_read(0x8000), 0);
+        assert_eq!(mapper.cpu_read(0xC000), 7 * 16);                         //last 16KiB bank
+
+        mapper.cpu_write(0x8000, 3);
+        assert_eq!(mapper.cpu_read(0x8000), 3 * 16);
+        assert_eq!(mapper.cpu_read(0xBFFF), 3 * 16 + 15);
+        assert_eq!(mapper.cpu_read(0xFFFF), 7 * 16 + 15);
+
+        mapper.ppu_write(0x0010, 0x77);                                       //CHR RAM
+        assert_eq!(mapper.ppu_read(0x0010), 0x77);
+    }
+}
@@ -12,13 +12,17 @@ pub struct OpCode {
 impl OpCode {
     fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressMode) -> Self {
         OpCode {
-            code: code,
-            mnemonic: mnemonic,
-            len: len,
-            cycles: cycles,
-            mode: mode,
+            code,
+            mnemonic,
+            len,
+            cycles,
+            mode,
         }
     }
+
+    pub fn is_unofficial(&self) -> bool {                                     //undocumented opcodes carry a * in front of their mnemonic
+        self.mnemonic.starts_with('*')
+    }
 }
 
 lazy_static! {
@@ -34,14 +38,14 @@ lazy_static! {
         OpCode::new(0x71, "ADC", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
 
         //AND, Logical AND
-        OpCode::new(0x69, "AND", 2, 2, AddressMode::Immeditate),
-        OpCode::new(0x65, "AND", 2, 3, AddressMode::ZeroPage),
-        OpCode::new(0x75, "AND", 2, 4, AddressMode::ZeroPageX),
-        OpCode::new(0x6D, "AND", 3, 4, AddressMode::Absolute),
-        OpCode::new(0x7D, "AND", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
-        OpCode::new(0x79, "AND", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
-        OpCode::new(0x61, "AND", 2, 6, AddressMode::IndirectX),
-        OpCode::new(0x71, "AND", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
+        OpCode::new(0x29, "AND", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x25, "AND", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x35, "AND", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x2D, "AND", 3, 4, AddressMode::Absolute),
+        OpCode::new(0x3D, "AND", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0x39, "AND", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
+        OpCode::new(0x21, "AND", 2, 6, AddressMode::IndirectX),
+        OpCode::new(0x31, "AND", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
 
         //ASL, Arithmetic Shift Left
         OpCode::new(0x0A, "ASL", 1, 2, AddressMode::NoneAddress),
@@ -60,7 +64,7 @@ lazy_static! {
         //BMI, Branch if Minus
         OpCode::new(0x30, "BMI", 2, 2, AddressMode::NoneAddress),
         //BNE, Branch if not equal
-        OpCode::new(0xD0, "BME", 2, 2, AddressMode::NoneAddress),
+        OpCode::new(0xD0, "BNE", 2, 2, AddressMode::NoneAddress),
         //BPL, Branch if Positive
         OpCode::new(0x10, "BPL", 2, 2, AddressMode::NoneAddress),
         //BVC, Branch if Overflow Clear
@@ -138,10 +142,10 @@ lazy_static! {
         OpCode::new(0xEE, "INC", 3, 6, AddressMode::Absolute),
         OpCode::new(0xFE, "INC", 3, 7, AddressMode::AbsoluteX),
 
-        //INX, Decrement X Register
+        //INX, Increment X Register
         OpCode::new(0xE8, "INX", 1, 2, AddressMode::NoneAddress),
 
-        //INY, Decrement X Register
+        //INY, Increment Y Register
         OpCode::new(0xC8, "INY", 1, 2, AddressMode::NoneAddress),
 
         //JMP, Jump
@@ -173,10 +177,10 @@ lazy_static! {
         OpCode::new(0xA4, "LDY", 2, 3, AddressMode::ZeroPage),
         OpCode::new(0xB4, "LDY", 2, 4, AddressMode::ZeroPageX),
         OpCode::new(0xAC, "LDY", 3, 4, AddressMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4, AddressMode::AbsoluteX), 
+        OpCode::new(0xBC, "LDY", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
 
         //LSR, Logical Shift Right
-        OpCode::new(0x4A, "LSR", 1, 2, AddressMode::Immeditate),
+        OpCode::new(0x4A, "LSR", 1, 2, AddressMode::NoneAddress),
         OpCode::new(0x46, "LSR", 2, 5, AddressMode::ZeroPage),
         OpCode::new(0x56, "LSR", 2, 6, AddressMode::ZeroPageX),
         OpCode::new(0x4E, "LSR", 3, 6, AddressMode::Absolute),
@@ -186,14 +190,14 @@ lazy_static! {
         OpCode::new(0xEA, "NOP", 1, 2, AddressMode::NoneAddress),
 
         //ORA, Logical Inclusive OR
-        OpCode::new(0x09, "LDA", 2, 2, AddressMode::Immeditate),
-        OpCode::new(0x05, "LDA", 2, 3, AddressMode::ZeroPage),
-        OpCode::new(0x15, "LDA", 2, 4, AddressMode::ZeroPageX),
-        OpCode::new(0x0D, "LDA", 3, 4, AddressMode::Absolute),
-        OpCode::new(0x1D, "LDA", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
-        OpCode::new(0x19, "LDA", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
-        OpCode::new(0x01, "LDA", 2, 6, AddressMode::IndirectX),
-        OpCode::new(0x11, "LDA", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
+        OpCode::new(0x09, "ORA", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x05, "ORA", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x15, "ORA", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x0D, "ORA", 3, 4, AddressMode::Absolute),
+        OpCode::new(0x1D, "ORA", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0x19, "ORA", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
+        OpCode::new(0x01, "ORA", 2, 6, AddressMode::IndirectX),
+        OpCode::new(0x11, "ORA", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
 
         //PHA, Push Accumulator
         OpCode::new(0x48, "PHA", 1, 3, AddressMode::NoneAddress),
@@ -208,14 +212,14 @@ lazy_static! {
         OpCode::new(0x28, "PLP", 1, 4, AddressMode::NoneAddress),
 
         //ROL, Rotate Left
-        OpCode::new(0x2A, "ROL", 1, 2, AddressMode::Immeditate),
+        OpCode::new(0x2A, "ROL", 1, 2, AddressMode::NoneAddress),
         OpCode::new(0x26, "ROL", 2, 5, AddressMode::ZeroPage),
         OpCode::new(0x36, "ROL", 2, 6, AddressMode::ZeroPageX),
         OpCode::new(0x2E, "ROL", 3, 6, AddressMode::Absolute),
         OpCode::new(0x3E, "ROL", 3, 7, AddressMode::AbsoluteX), 
 
         //ROR, Rotate Right
-        OpCode::new(0x6A, "ROR", 1, 2, AddressMode::Immeditate),
+        OpCode::new(0x6A, "ROR", 1, 2, AddressMode::NoneAddress),
         OpCode::new(0x66, "ROR", 2, 5, AddressMode::ZeroPage),
         OpCode::new(0x76, "ROR", 2, 6, AddressMode::ZeroPageX),
         OpCode::new(0x6E, "ROR", 3, 6, AddressMode::Absolute),
@@ -244,26 +248,26 @@ lazy_static! {
         OpCode::new(0xF8, "SED", 1, 2, AddressMode::NoneAddress),
 
         //SEI, Set Interrupt Disable
-        OpCode::new(0x60, "SEI", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x78, "SEI", 1, 2, AddressMode::NoneAddress),
 
         //STA, Store Accumulator
         OpCode::new(0x85, "STA", 2, 3, AddressMode::ZeroPage),
         OpCode::new(0x95, "STA", 2, 4, AddressMode::ZeroPageX),
         OpCode::new(0x8D, "STA", 3, 4, AddressMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 6, AddressMode::AbsoluteX), 
-        OpCode::new(0x99, "STA", 3, 6, AddressMode::AbsoluteY), 
+        OpCode::new(0x9D, "STA", 3, 5, AddressMode::AbsoluteX),
+        OpCode::new(0x99, "STA", 3, 5, AddressMode::AbsoluteY),
         OpCode::new(0x81, "STA", 2, 6, AddressMode::IndirectX),
         OpCode::new(0x91, "STA", 2, 6, AddressMode::IndirectY),
 
-        //STA, Store Accumulator
+        //STX, Store X Register
         OpCode::new(0x86, "STX", 2, 3, AddressMode::ZeroPage),
         OpCode::new(0x96, "STX", 2, 4, AddressMode::ZeroPageY),
         OpCode::new(0x8E, "STX", 3, 4, AddressMode::Absolute),
 
-        //STY, Store Accumulator
-        OpCode::new(0x86, "STY", 2, 3, AddressMode::ZeroPage),
-        OpCode::new(0x96, "STY", 2, 4, AddressMode::ZeroPageX),
-        OpCode::new(0x8E, "STY", 3, 4, AddressMode::Absolute),
+        //STY, Store Y Register
+        OpCode::new(0x84, "STY", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x94, "STY", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x8C, "STY", 3, 4, AddressMode::Absolute),
 
         //TAX, Transfer Accumulator to X
         OpCode::new(0xAA, "TAX", 1, 2, AddressMode::NoneAddress),
@@ -282,6 +286,144 @@ lazy_static! {
 
         //TYA, Transfer Y to Accumulator
         OpCode::new(0x98, "TYA", 1, 2, AddressMode::NoneAddress),
+
+
+        /*
+            UNOFFICIAL OPCODES
+            Undocumented opcodes are prefixed with * the same way nestest.log prints them
+            (https://www.nesdev.org/undocumented_opcodes.txt)
+        */
+
+        //NOP variants, the ones with an operand still perform the read
+        OpCode::new(0x1A, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x3A, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x5A, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x7A, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0xDA, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0xFA, "*NOP", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x80, "*NOP", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x82, "*NOP", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x89, "*NOP", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0xC2, "*NOP", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0xE2, "*NOP", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x04, "*NOP", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x44, "*NOP", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x64, "*NOP", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x14, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x34, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x54, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x74, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0xD4, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0xF4, "*NOP", 2, 4, AddressMode::ZeroPageX),
+        OpCode::new(0x0C, "*NOP", 3, 4, AddressMode::Absolute),
+        OpCode::new(0x1C, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0x3C, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0x5C, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0x7C, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0xDC, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+        OpCode::new(0xFC, "*NOP", 3, 4, AddressMode::AbsoluteX), //+1 cycle if page crossed
+
+        //LAX, Load Accumulator and X
+        OpCode::new(0xA7, "*LAX", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0xB7, "*LAX", 2, 4, AddressMode::ZeroPageY),
+        OpCode::new(0xAF, "*LAX", 3, 4, AddressMode::Absolute),
+        OpCode::new(0xBF, "*LAX", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
+        OpCode::new(0xA3, "*LAX", 2, 6, AddressMode::IndirectX),
+        OpCode::new(0xB3, "*LAX", 2, 5, AddressMode::IndirectY), //+1 cycle if page crossed
+
+        //SAX, Store A AND X
+        OpCode::new(0x87, "*SAX", 2, 3, AddressMode::ZeroPage),
+        OpCode::new(0x97, "*SAX", 2, 4, AddressMode::ZeroPageY),
+        OpCode::new(0x8F, "*SAX", 3, 4, AddressMode::Absolute),
+        OpCode::new(0x83, "*SAX", 2, 6, AddressMode::IndirectX),
+
+        //SBC, same as the official immediate SBC
+        OpCode::new(0xEB, "*SBC", 2, 2, AddressMode::Immeditate),
+
+        //DCP, Decrement Memory then Compare
+        OpCode::new(0xC7, "*DCP", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0xD7, "*DCP", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0xCF, "*DCP", 3, 6, AddressMode::Absolute),
+        OpCode::new(0xDF, "*DCP", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0xDB, "*DCP", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0xC3, "*DCP", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0xD3, "*DCP", 2, 8, AddressMode::IndirectY),
+
+        //ISB, Increment Memory then Subtract with Carry
+        OpCode::new(0xE7, "*ISB", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0xF7, "*ISB", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0xEF, "*ISB", 3, 6, AddressMode::Absolute),
+        OpCode::new(0xFF, "*ISB", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0xFB, "*ISB", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0xE3, "*ISB", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0xF3, "*ISB", 2, 8, AddressMode::IndirectY),
+
+        //SLO, Arithmetic Shift Left then OR
+        OpCode::new(0x07, "*SLO", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0x17, "*SLO", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0x0F, "*SLO", 3, 6, AddressMode::Absolute),
+        OpCode::new(0x1F, "*SLO", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0x1B, "*SLO", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0x03, "*SLO", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0x13, "*SLO", 2, 8, AddressMode::IndirectY),
+
+        //RLA, Rotate Left then AND
+        OpCode::new(0x27, "*RLA", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0x37, "*RLA", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0x2F, "*RLA", 3, 6, AddressMode::Absolute),
+        OpCode::new(0x3F, "*RLA", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0x3B, "*RLA", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0x23, "*RLA", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0x33, "*RLA", 2, 8, AddressMode::IndirectY),
+
+        //SRE, Logical Shift Right then Exclusive OR
+        OpCode::new(0x47, "*SRE", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0x57, "*SRE", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0x4F, "*SRE", 3, 6, AddressMode::Absolute),
+        OpCode::new(0x5F, "*SRE", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0x5B, "*SRE", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0x43, "*SRE", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0x53, "*SRE", 2, 8, AddressMode::IndirectY),
+
+        //RRA, Rotate Right then Add with Carry
+        OpCode::new(0x67, "*RRA", 2, 5, AddressMode::ZeroPage),
+        OpCode::new(0x77, "*RRA", 2, 6, AddressMode::ZeroPageX),
+        OpCode::new(0x6F, "*RRA", 3, 6, AddressMode::Absolute),
+        OpCode::new(0x7F, "*RRA", 3, 7, AddressMode::AbsoluteX),
+        OpCode::new(0x7B, "*RRA", 3, 7, AddressMode::AbsoluteY),
+        OpCode::new(0x63, "*RRA", 2, 8, AddressMode::IndirectX),
+        OpCode::new(0x73, "*RRA", 2, 8, AddressMode::IndirectY),
+
+        //Immediate combined operations
+        OpCode::new(0x0B, "*ANC", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x2B, "*ANC", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x4B, "*ALR", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0x6B, "*ARR", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0xCB, "*AXS", 2, 2, AddressMode::Immeditate),
+
+        //Unstable opcodes, these use the most common behavior seen on real chips
+        OpCode::new(0x8B, "*XAA", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0xAB, "*LXA", 2, 2, AddressMode::Immeditate),
+        OpCode::new(0xBB, "*LAS", 3, 4, AddressMode::AbsoluteY), //+1 cycle if page crossed
+        OpCode::new(0x9B, "*TAS", 3, 5, AddressMode::AbsoluteY),
+        OpCode::new(0x9F, "*AHX", 3, 5, AddressMode::AbsoluteY),
+        OpCode::new(0x93, "*AHX", 2, 6, AddressMode::IndirectY),
+        OpCode::new(0x9C, "*SHY", 3, 5, AddressMode::AbsoluteX),
+        OpCode::new(0x9E, "*SHX", 3, 5, AddressMode::AbsoluteY),
+
+        //JAM, halts the CPU until reset
+        OpCode::new(0x02, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x12, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x22, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x32, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x42, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x52, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x62, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x72, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0x92, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0xB2, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0xD2, "*JAM", 1, 2, AddressMode::NoneAddress),
+        OpCode::new(0xF2, "*JAM", 1, 2, AddressMode::NoneAddress),
     ];
 
     pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
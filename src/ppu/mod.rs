@@ -0,0 +1,1107 @@
+pub mod frame;
+pub mod palette;
+pub mod registers;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::cartridge::Mirroring;
+use crate::mapper::nrom::Nrom;
+use crate::mapper::SharedMapper;
+use crate::savestate::{Snapshot, StateError, StateReader, StateWriter};
+use frame::Frame;
+use registers::{ControlRegister, MaskRegister, StatusRegister};
+
+/*
+    PPU TIMING (https://www.nesdev.org/wiki/PPU_rendering)
+    A frame is 262 scanlines of 341 dots, the PPU runs 3 dots for every CPU cycle
+    scanlines 0-239 are visible, 240 is idle, vblank starts on 241 and 261 is the pre-render line
+*/
+const DOTS_PER_SCANLINE: u16 = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+const MAX_SPRITES_PER_LINE: usize = 8;
+const MAPPER_SCANLINE_DOT: u16 = 260;                                         //where MMC3 style boards see the sprite fetches begin
+const A12_FILTER_DOTS: u64 = 10;                                              //A12 has to stay low this long for a rise to count, like the MMC3's M2 filter
+
+#[derive(Clone, Copy, Default)]
+struct SpriteLine {                                                           //a sprite picked during evaluation for the next scanline
+    x: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,                                                           //already flipped so bit 7 is the leftmost pixel
+    attributes: u8,
+    is_sprite_zero: bool,
+}
+
+pub struct NesPPU {
+    mapper: SharedMapper,                                                     //pattern tables and nametable mirroring come from the cartridge
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 4096],                                                     //2KiB on the console, the other half is only used by four screen boards
+    pub oam_data: [u8; 256],
+    pub oam_addr: u8,
+
+    pub ctrl: ControlRegister,
+    pub mask: MaskRegister,
+    pub status: StatusRegister,
+
+    //loopy's internal scroll registers (https://www.nesdev.org/wiki/PPU_scrolling)
+    v: u16,                                                                   //current VRAM address
+    t: u16,                                                                   //temporary VRAM address, the top left of the screen
+    x: u8,                                                                    //fine x scroll
+    w: bool,                                                                  //first or second write toggle for $2005/$2006
+
+    internal_data_buf: u8,
+    open_bus: u8,                                                             //the data bus latch, write only registers read back whatever was last written
+
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame_count: u64,
+    odd_frame: bool,
+    cycle: u64,                                                               //dots since power on
+    a12_high_at: Option<u64>,                                                 //last dot a pattern fetch had A12 set
+
+    //background pipeline
+    next_tile_id: u8,
+    next_tile_attrib: u8,
+    next_tile_lo: u8,
+    next_tile_hi: u8,
+    bg_shifter_pattern_lo: u16,
+    bg_shifter_pattern_hi: u16,
+    bg_shifter_attrib_lo: u16,
+    bg_shifter_attrib_hi: u16,
+
+    sprites: [SpriteLine; MAX_SPRITES_PER_LINE],
+    sprite_count: usize,
+
+    pub frame: Frame,
+}
+
+impl NesPPU {
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {              //an NROM board around the given CHR, empty CHR means 8KiB of CHR RAM
+        NesPPU::with_mapper(Rc::new(RefCell::new(Nrom::with_chr(chr_rom, mirroring))))
+    }
+
+    pub fn with_mapper(mapper: SharedMapper) -> Self {
+        NesPPU {
+            mapper,
+            palette_table: [0; 32],
+            vram: [0; 4096],
+            oam_data: [0; 256],
+            oam_addr: 0,
+            ctrl: ControlRegister::new(),
+            mask: MaskRegister::new(),
+            status: StatusRegister::new(),
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            internal_data_buf: 0,
+            open_bus: 0,
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            odd_frame: false,
+            cycle: 0,
+            a12_high_at: None,
+            next_tile_id: 0,
+            next_tile_attrib: 0,
+            next_tile_lo: 0,
+            next_tile_hi: 0,
+            bg_shifter_pattern_lo: 0,
+            bg_shifter_pattern_hi: 0,
+            bg_shifter_attrib_lo: 0,
+            bg_shifter_attrib_hi: 0,
+            sprites: [SpriteLine::default(); MAX_SPRITES_PER_LINE],
+            sprite_count: 0,
+            frame: Frame::new(),
+        }
+    }
+
+    pub fn new_empty_rom() -> Self {                                          //8KiB of CHR RAM, for when no cartridge is inserted
+        NesPPU::new(vec![], Mirroring::Horizontal)
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.borrow().mirroring()
+    }
+
+    pub fn mapper(&self) -> &SharedMapper {                                   //the board the PPU fetches from, an NROM with CHR RAM when no cartridge is inserted
+        &self.mapper
+    }
+
+    pub fn nmi_line(&self) -> bool {                                          //the PPU holds /NMI low while in vblank with NMI enabled
+        self.status.is_in_vblank() && self.ctrl.generate_vblank_nmi()
+    }
+
+    pub fn vram_addr(&self) -> u16 {
+        self.v
+    }
+
+
+    /*
+        CPU FACING REGISTERS $2000 - $2007
+
+    */
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        self.open_bus = value;
+        self.ctrl.update(value);
+        self.t = (self.t & 0xF3FF) | (((value & 0b11) as u16) << 10);        //nametable select goes into t bits 10-11
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.open_bus = value;
+        self.mask.update(value);
+    }
+
+    pub fn read_status(&mut self) -> u8 {
+        let data = (self.status.snapshot() & 0xE0) | (self.open_bus & 0x1F);
+        self.status.remove(StatusRegister::VBLANK_STARTED);
+        self.w = false;
+        self.open_bus = data;
+        data
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.open_bus = value;
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.open_bus = value;
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn read_oam_data(&mut self) -> u8 {
+        let mut data = self.oam_data[self.oam_addr as usize];
+        if self.oam_addr & 0b11 == 2 {                                        //unimplemented attribute bits read back as 0
+            data &= 0xE3;
+        }
+        self.open_bus = data;
+        data
+    }
+
+    pub fn write_to_scroll(&mut self, value: u8) {
+        self.open_bus = value;
+        if !self.w {
+            self.t = (self.t & 0xFFE0) | ((value >> 3) as u16);               //coarse x
+            self.x = value & 0b111;                                           //fine x
+        } else {
+            self.t = (self.t & 0x8C1F)
+                | (((value & 0b111) as u16) << 12)                            //fine y
+                | (((value >> 3) as u16) << 5);                               //coarse y
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        self.open_bus = value;
+        if !self.w {
+            self.t = (self.t & 0x00FF) | (((value & 0x3F) as u16) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | value as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_to_data(&mut self, value: u8) {
+        self.open_bus = value;
+        let addr = self.v;
+        self.write_vram(addr, value);
+        self.increment_vram_addr();
+    }
+
+    pub fn read_data(&mut self) -> u8 {
+        let addr = self.v & 0x3FFF;
+        self.increment_vram_addr();
+
+        let data = if addr >= 0x3F00 {                                        //palette reads skip the buffer, the buffer gets the nametable underneath
+            self.internal_data_buf = self.read_vram(addr - 0x1000);
+            (self.read_vram(addr) & 0x3F) | (self.open_bus & 0xC0)
+        } else {
+            let result = self.internal_data_buf;
+            self.internal_data_buf = self.read_vram(addr);
+            result
+        };
+        self.open_bus = data;
+        data
+    }
+
+    pub fn read_open_bus(&self) -> u8 {                                       //reads of the write only registers
+        self.open_bus
+    }
+
+    pub fn write_oam_dma(&mut self, data: &[u8; 256]) {                       //$4014 copies a whole CPU page into OAM starting at oam_addr
+        for x in data.iter() {
+            self.oam_data[self.oam_addr as usize] = *x;
+            self.oam_addr = self.oam_addr.wrapping_add(1);
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        self.v = self.v.wrapping_add(self.ctrl.vram_addr_increment() as u16) & 0x7FFF;
+    }
+
+
+    /*
+        PPU MEMORY MAP
+        $0000 - $1FFF   pattern tables (CHR)
+        $2000 - $2FFF   nametables, mirrored up to $3EFF
+        $3F00 - $3F1F   palette RAM, mirrored up to $3FFF
+
+    */
+    fn read_vram(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0 ..= 0x1FFF => {
+                self.watch_a12(addr);
+                self.mapper.borrow_mut().ppu_read(addr)
+            }
+            0x2000 ..= 0x3EFF => self.vram[self.mirror_vram_addr(addr) as usize],
+            _ => self.palette_table[Self::mirror_palette_addr(addr)],
+        }
+    }
+
+    fn write_vram(&mut self, addr: u16, value: u8) {
+        let addr = addr & 0x3FFF;
+        match addr {
+            0 ..= 0x1FFF => {
+                self.watch_a12(addr);
+                self.mapper.borrow_mut().ppu_write(addr, value);
+            }
+            0x2000 ..= 0x3EFF => {
+                let mirrored = self.mirror_vram_addr(addr) as usize;
+                self.vram[mirrored] = value;
+            }
+            _ => self.palette_table[Self::mirror_palette_addr(addr)] = value,
+        }
+    }
+
+    fn watch_a12(&mut self, addr: u16) {                                      //tells the mapper when A12 rises after being low for long enough
+        if addr & 0x1000 == 0 {
+            return;
+        }
+        let filtered = match self.a12_high_at {
+            Some(high_at) => self.cycle - high_at < A12_FILTER_DOTS,
+            None => false,
+        };
+        if !filtered {
+            self.mapper.borrow_mut().notify_a12_rise();
+        }
+        self.a12_high_at = Some(self.cycle);
+    }
+
+    // Horizontal:
+    //   [ A ] [ a ]
+    //   [ B ] [ b ]
+
+    // Vertical:
+    //   [ A ] [ B ]
+    //   [ a ] [ b ]
+    pub fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        let mirrored_vram = addr & 0b10111111111111;                          // mirror down 0x3000-0x3eff to 0x2000 - 0x2eff
+        let vram_index = mirrored_vram - 0x2000;                              // to vram vector
+        let name_table = vram_index / 0x400;                                  // to the name table index
+        match (self.mirroring(), name_table) {
+            (Mirroring::SingleScreenLower, _) => vram_index % 0x400,
+            (Mirroring::SingleScreenUpper, _) => vram_index % 0x400 + 0x400,
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+            (Mirroring::Horizontal, 2) => vram_index - 0x400,
+            (Mirroring::Horizontal, 1) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            _ => vram_index,
+        }
+    }
+
+    fn mirror_palette_addr(addr: u16) -> usize {                              //$3F10/$3F14/$3F18/$3F1C share the backdrop entries of the background palettes
+        let index = (addr & 0x1F) as usize;
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+            _ => index,
+        }
+    }
+
+
+    /*
+        TIMING
+
+    */
+    pub fn tick(&mut self, dots: u16) {
+        for _ in 0..dots {
+            self.step();
+        }
+    }
+
+    fn step(&mut self) {
+        let rendering = self.mask.rendering_enabled();
+        let visible_line = self.scanline < 240;
+        let pre_render = self.scanline == PRE_RENDER_SCANLINE;
+
+        if pre_render && self.dot == 1 {
+            self.status.remove(StatusRegister::VBLANK_STARTED);
+            self.status.remove(StatusRegister::SPRITE_ZERO_HIT);
+            self.status.remove(StatusRegister::SPRITE_OVERFLOW);
+        }
+
+        if (visible_line || pre_render) && rendering {
+            self.background_pipeline(pre_render);
+
+            if self.dot == 257 {
+                if visible_line {
+                    self.evaluate_sprites();
+                } else {
+                    self.sprite_count = 0;                                    //nothing is drawn on scanline 0 from sprites
+                    self.dummy_sprite_fetches();
+                }
+            }
+
+            if self.dot == MAPPER_SCANLINE_DOT {
+                self.mapper.borrow_mut().notify_scanline();
+            }
+        }
+
+        if visible_line && (1..=256).contains(&self.dot) {
+            self.render_pixel();
+        }
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            self.status.insert(StatusRegister::VBLANK_STARTED);
+            self.frame_count += 1;
+        }
+
+        self.cycle += 1;
+        self.dot += 1;
+        if pre_render && self.dot == 340 && self.odd_frame && rendering {    //odd frames skip the last dot of the pre-render line
+            self.dot += 1;
+        }
+        if self.dot >= DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline > PRE_RENDER_SCANLINE {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+            }
+        }
+    }
+
+    fn background_pipeline(&mut self, pre_render: bool) {
+        let dot = self.dot;
+
+        if (2..258).contains(&dot) || (321..338).contains(&dot) {
+            self.update_shifters();
+
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.next_tile_id = self.read_vram(0x2000 | (self.v & 0x0FFF));
+                }
+                2 => {
+                    let attrib_addr = 0x23C0
+                        | (self.v & 0x0C00)
+                        | ((self.v >> 4) & 0x38)
+                        | ((self.v >> 2) & 0x07);
+                    let mut attrib = self.read_vram(attrib_addr);
+                    if self.coarse_y() & 0x02 != 0 {
+                        attrib >>= 4;
+                    }
+                    if self.coarse_x() & 0x02 != 0 {
+                        attrib >>= 2;
+                    }
+                    self.next_tile_attrib = attrib & 0x03;
+                }
+                4 => {
+                    let addr = self.background_tile_addr();
+                    self.next_tile_lo = self.read_vram(addr);
+                }
+                6 => {
+                    let addr = self.background_tile_addr() + 8;
+                    self.next_tile_hi = self.read_vram(addr);
+                }
+                7 => self.increment_scroll_x(),
+                _ => {}
+            }
+        }
+
+        if dot == 256 {
+            self.increment_scroll_y();
+        }
+
+        if dot == 257 {
+            self.load_background_shifters();
+            self.v = (self.v & !0x041F) | (self.t & 0x041F);                  //copy the horizontal bits of t into v
+        }
+
+        if pre_render && (280..=304).contains(&dot) {
+            self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);                  //copy the vertical bits of t into v
+        }
+    }
+
+    fn background_tile_addr(&self) -> u16 {
+        self.ctrl.background_pattern_addr() + (self.next_tile_id as u16) * 16 + self.fine_y()
+    }
+
+    fn coarse_x(&self) -> u16 {
+        self.v & 0x001F
+    }
+
+    fn coarse_y(&self) -> u16 {
+        (self.v >> 5) & 0x001F
+    }
+
+    fn fine_y(&self) -> u16 {
+        (self.v >> 12) & 0x0007
+    }
+
+    fn increment_scroll_x(&mut self) {                                        //wraps into the horizontally adjacent nametable
+        if self.coarse_x() == 31 {
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_scroll_y(&mut self) {                                        //row 29 wraps into the vertically adjacent nametable, 30 and 31 wrap in place
+        if self.fine_y() < 7 {
+            self.v += 0x1000;
+        } else {
+            self.v &= !0x7000;
+            let mut y = self.coarse_y();
+            if y == 29 {
+                y = 0;
+                self.v ^= 0x0800;
+            } else if y == 31 {
+                y = 0;
+            } else {
+                y += 1;
+            }
+            self.v = (self.v & !0x03E0) | (y << 5);
+        }
+    }
+
+    fn load_background_shifters(&mut self) {
+        self.bg_shifter_pattern_lo = (self.bg_shifter_pattern_lo & 0xFF00) | self.next_tile_lo as u16;
+        self.bg_shifter_pattern_hi = (self.bg_shifter_pattern_hi & 0xFF00) | self.next_tile_hi as u16;
+        self.bg_shifter_attrib_lo = (self.bg_shifter_attrib_lo & 0xFF00)
+            | if self.next_tile_attrib & 0b01 != 0 { 0xFF } else { 0x00 };
+        self.bg_shifter_attrib_hi = (self.bg_shifter_attrib_hi & 0xFF00)
+            | if self.next_tile_attrib & 0b10 != 0 { 0xFF } else { 0x00 };
+    }
+
+    fn update_shifters(&mut self) {
+        if self.mask.show_background() {
+            self.bg_shifter_pattern_lo <<= 1;
+            self.bg_shifter_pattern_hi <<= 1;
+            self.bg_shifter_attrib_lo <<= 1;
+            self.bg_shifter_attrib_hi <<= 1;
+        }
+    }
+
+
+    /*
+        SPRITES
+
+    */
+    fn evaluate_sprites(&mut self) {                                          //picks the first 8 sprites that cover the next scanline
+        let height = self.ctrl.sprite_size() as u16;
+        let line = self.scanline;
+        self.sprite_count = 0;
+
+        for i in 0..64 {
+            let y = self.oam_data[i * 4] as u16;
+            if line < y || line - y >= height {
+                continue;
+            }
+            if self.sprite_count == MAX_SPRITES_PER_LINE {
+                self.status.insert(StatusRegister::SPRITE_OVERFLOW);
+                break;
+            }
+
+            let tile = self.oam_data[i * 4 + 1];
+            let attributes = self.oam_data[i * 4 + 2];
+            let mut row = line - y;
+            if attributes & 0b1000_0000 != 0 {                                //vertical flip
+                row = height - 1 - row;
+            }
+
+            let addr = if height == 8 {
+                self.ctrl.sprite_pattern_addr() + tile as u16 * 16 + row
+            } else {
+                let bank = (tile as u16 & 1) * 0x1000;                        //8x16 sprites pick their table with bit 0 of the tile
+                let mut tile = (tile & 0xFE) as u16;
+                if row >= 8 {
+                    tile += 1;
+                    row -= 8;
+                }
+                bank + tile * 16 + row
+            };
+
+            let mut pattern_lo = self.read_vram(addr);
+            let mut pattern_hi = self.read_vram(addr + 8);
+            if attributes & 0b0100_0000 != 0 {                                //horizontal flip
+                pattern_lo = pattern_lo.reverse_bits();
+                pattern_hi = pattern_hi.reverse_bits();
+            }
+
+            self.sprites[self.sprite_count] = SpriteLine {
+                x: self.oam_data[i * 4 + 3],
+                pattern_lo,
+                pattern_hi,
+                attributes,
+                is_sprite_zero: i == 0,
+            };
+            self.sprite_count += 1;
+        }
+        self.dummy_sprite_fetches();
+    }
+
+    fn dummy_sprite_fetches(&mut self) {                                      //empty sprite slots still fetch tile $FF, which is what clocks MMC3 IRQs on quiet lines
+        for _ in self.sprite_count..MAX_SPRITES_PER_LINE {
+            let addr = if self.ctrl.sprite_size() == 8 {
+                self.ctrl.sprite_pattern_addr() + 0xFF * 16
+            } else {
+                0x1000 + 0xFE * 16
+            };
+            self.read_vram(addr);
+            self.read_vram(addr + 8);
+        }
+    }
+
+    fn sprite_pixel(&self, x: u8) -> Option<(u8, u8, bool, bool)> {           //(pixel, palette, behind background, is sprite zero) of the first opaque sprite
+        for sprite in &self.sprites[..self.sprite_count] {
+            let offset = x.wrapping_sub(sprite.x);
+            if x < sprite.x || offset >= 8 {
+                continue;
+            }
+            let lo = (sprite.pattern_lo >> (7 - offset)) & 1;
+            let hi = (sprite.pattern_hi >> (7 - offset)) & 1;
+            let pixel = (hi << 1) | lo;
+            if pixel != 0 {
+                let palette = (sprite.attributes & 0b11) + 4;
+                let behind = sprite.attributes & 0b0010_0000 != 0;
+                return Some((pixel, palette, behind, sprite.is_sprite_zero));
+            }
+        }
+        None
+    }
+
+
+    /*
+        PIXEL OUTPUT
+
+    */
+    fn render_pixel(&mut self) {
+        let x = (self.dot - 1) as u8;
+        let y = self.scanline as usize;
+
+        let mut bg_pixel = 0;
+        let mut bg_palette = 0;
+        if self.mask.show_background()
+            && (x >= 8 || self.mask.contains(registers::MaskRegister::LEFTMOST_8PXL_BACKGROUND))
+        {
+            let bit_mux: u16 = 0x8000 >> self.x;
+            let p0 = ((self.bg_shifter_pattern_lo & bit_mux) > 0) as u8;
+            let p1 = ((self.bg_shifter_pattern_hi & bit_mux) > 0) as u8;
+            bg_pixel = (p1 << 1) | p0;
+            let pal0 = ((self.bg_shifter_attrib_lo & bit_mux) > 0) as u8;
+            let pal1 = ((self.bg_shifter_attrib_hi & bit_mux) > 0) as u8;
+            bg_palette = (pal1 << 1) | pal0;
+        }
+
+        let mut sprite = None;
+        if self.mask.show_sprites()
+            && (x >= 8 || self.mask.contains(registers::MaskRegister::LEFTMOST_8PXL_SPRITE))
+        {
+            sprite = self.sprite_pixel(x);
+        }
+
+        let (pixel, palette) = match sprite {
+            None if bg_pixel == 0 => (0, 0),
+            None => (bg_pixel, bg_palette),
+            Some((sp_pixel, sp_palette, behind, is_sprite_zero)) => {
+                if bg_pixel != 0 && is_sprite_zero && x != 255 {
+                    self.status.insert(StatusRegister::SPRITE_ZERO_HIT);
+                }
+                if bg_pixel == 0 || !behind {
+                    (sp_pixel, sp_palette)
+                } else {
+                    (bg_pixel, bg_palette)
+                }
+            }
+        };
+
+        let mut color = self.read_vram(0x3F00 + ((palette as u16) << 2) + pixel as u16);
+        if self.mask.contains(registers::MaskRegister::GREYSCALE) {
+            color &= 0x30;
+        }
+        self.frame.set_pixel(x as usize, y, palette::SYSTEM_PALETTE[(color & 0x3F) as usize]);
+    }
+}
+
+
+/*
+    SAVE STATE
+    the frame is output, not state, the next one redraws it from scratch
+*/
+impl Snapshot for NesPPU {
+    fn save(&self, w: &mut StateWriter) {
+        w.bytes(&self.palette_table);
+        w.bytes(&self.vram);
+        w.bytes(&self.oam_data);
+        w.u8(self.oam_addr);
+        w.u8(self.ctrl.bits());
+        w.u8(self.mask.bits());
+        w.u8(self.status.bits());
+        w.u16(self.v);
+        w.u16(self.t);
+        w.u8(self.x);
+        w.bool(self.w);
+        w.u8(self.internal_data_buf);
+        w.u8(self.open_bus);
+        w.u16(self.scanline);
+        w.u16(self.dot);
+        w.u64(self.frame_count);
+        w.bool(self.odd_frame);
+        w.u64(self.cycle);
+        w.bool(self.a12_high_at.is_some());
+        w.u64(self.a12_high_at.unwrap_or(0));
+
+        w.u8(self.next_tile_id);
+        w.u8(self.next_tile_attrib);
+        w.u8(self.next_tile_lo);
+        w.u8(self.next_tile_hi);
+        w.u16(self.bg_shifter_pattern_lo);
+        w.u16(self.bg_shifter_pattern_hi);
+        w.u16(self.bg_shifter_attrib_lo);
+        w.u16(self.bg_shifter_attrib_hi);
+
+        for sprite in self.sprites.iter() {
+            w.u8(sprite.x);
+            w.u8(sprite.pattern_lo);
+            w.u8(sprite.pattern_hi);
+            w.u8(sprite.attributes);
+            w.bool(sprite.is_sprite_zero);
+        }
+        w.u8(self.sprite_count as u8);
+    }
+
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError> {
+        r.bytes_into(&mut self.palette_table)?;
+        r.bytes_into(&mut self.vram)?;
+        r.bytes_into(&mut self.oam_data)?;
+        self.oam_addr = r.u8()?;
+        self.ctrl = ControlRegister::from_bits_truncate(r.u8()?);
+        self.mask = MaskRegister::from_bits_truncate(r.u8()?);
+        self.status = StatusRegister::from_bits_truncate(r.u8()?);
+        self.v = r.u16()?;
+        self.t = r.u16()?;
+        self.x = r.u8()?;
+        self.w = r.bool()?;
+        self.internal_data_buf = r.u8()?;
+        self.open_bus = r.u8()?;
+        self.scanline = r.u16()?;
+        self.dot = r.u16()?;
+        self.frame_count = r.u64()?;
+        self.odd_frame = r.bool()?;
+        self.cycle = r.u64()?;
+        let a12_high = r.bool()?;
+        let a12_high_at = r.u64()?;
+        self.a12_high_at = if a12_high { Some(a12_high_at) } else { None };
+
+        self.next_tile_id = r.u8()?;
+        self.next_tile_attrib = r.u8()?;
+        self.next_tile_lo = r.u8()?;
+        self.next_tile_hi = r.u8()?;
+        self.bg_shifter_pattern_lo = r.u16()?;
+        self.bg_shifter_pattern_hi = r.u16()?;
+        self.bg_shifter_attrib_lo = r.u16()?;
+        self.bg_shifter_attrib_hi = r.u16()?;
+
+        for sprite in self.sprites.iter_mut() {
+            sprite.x = r.u8()?;
+            sprite.pattern_lo = r.u8()?;
+            sprite.pattern_hi = r.u8()?;
+            sprite.attributes = r.u8()?;
+            sprite.is_sprite_zero = r.bool()?;
+        }
+        self.sprite_count = r.u8()? as usize;
+        if self.sprite_count > MAX_SPRITES_PER_LINE || self.scanline > PRE_RENDER_SCANLINE || self.dot >= DOTS_PER_SCANLINE {
+            return Err(StateError::Corrupt("PPU position or sprite count out of range".to_string()));
+        }
+        Ok(())
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+pub mod test {
+    use super::*;
+
+    #[test]
+    fn test_ppu_vram_writes() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.write_to_data(0x66);
+
+        assert_eq!(ppu.vram[0x0305], 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.vram_addr(), 0x2306);
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_cross_page() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x0200] = 0x77;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+    }
+
+    #[test]
+    fn test_ppu_vram_reads_step_32() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b100);
+        ppu.vram[0x01ff] = 0x66;
+        ppu.vram[0x01ff + 32] = 0x77;
+        ppu.vram[0x01ff + 64] = 0x88;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0xff);
+
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+        assert_eq!(ppu.read_data(), 0x88);
+    }
+
+    // Horizontal: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 a ]
+    //   [0x2800 B ] [0x2C00 b ]
+    #[test]
+    fn test_vram_horizontal_mirror() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x66); //write to a
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x77); //write to B
+
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load into buffer
+        assert_eq!(ppu.read_data(), 0x66); //read from A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load into buffer
+        assert_eq!(ppu.read_data(), 0x77); //read from b
+    }
+
+    // Vertical: https://wiki.nesdev.com/w/index.php/Mirroring
+    //   [0x2000 A ] [0x2400 B ]
+    //   [0x2800 a ] [0x2C00 b ]
+    #[test]
+    fn test_vram_vertical_mirror() {
+        let mut ppu = NesPPU::new(vec![0; 2048], Mirroring::Vertical);
+
+        ppu.write_to_ppu_addr(0x20);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x66); //write to A
+
+        ppu.write_to_ppu_addr(0x2C);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.write_to_data(0x77); //write to b
+
+        ppu.write_to_ppu_addr(0x28);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load into buffer
+        assert_eq!(ppu.read_data(), 0x66); //read from a
+
+        ppu.write_to_ppu_addr(0x24);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load into buffer
+        assert_eq!(ppu.read_data(), 0x77); //read from B
+    }
+
+    #[test]
+    fn test_read_status_resets_latch() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x21);
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load_into_buffer
+        assert_ne!(ppu.read_data(), 0x66);
+
+        ppu.read_status();
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load_into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_ppu_vram_mirroring() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0);
+        ppu.vram[0x0305] = 0x66;
+
+        ppu.write_to_ppu_addr(0x63); //0x6305 -> 0x2305
+        ppu.write_to_ppu_addr(0x05);
+
+        ppu.read_data(); //load into_buffer
+        assert_eq!(ppu.read_data(), 0x66);
+    }
+
+    #[test]
+    fn test_read_status_resets_vblank() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.status.insert(StatusRegister::VBLANK_STARTED);
+
+        let status = ppu.read_status();
+
+        assert_eq!(status >> 7, 1);
+        assert_eq!(ppu.status.snapshot() >> 7, 0);
+    }
+
+    #[test]
+    fn test_oam_read_write() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_to_oam_data(0x66);
+        ppu.write_to_oam_data(0x77);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+    }
+
+    #[test]
+    fn test_oam_dma() {
+        let mut ppu = NesPPU::new_empty_rom();
+
+        let mut data = [0x66; 256];
+        data[0] = 0x77;
+        data[255] = 0x88;
+
+        ppu.write_to_oam_addr(0x10);
+        ppu.write_oam_dma(&data);
+
+        ppu.write_to_oam_addr(0xf); //wrap around
+        assert_eq!(ppu.read_oam_data(), 0x88);
+
+        ppu.write_to_oam_addr(0x10);
+        assert_eq!(ppu.read_oam_data(), 0x77);
+
+        ppu.write_to_oam_addr(0x11);
+        assert_eq!(ppu.read_oam_data(), 0x66);
+    }
+
+    #[test]
+    fn test_palette_mirroring() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x10);
+        ppu.write_to_data(0x2c);
+
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0x00);
+        assert_eq!(ppu.read_data(), 0x2c);                                    //palette reads are not buffered
+        ppu.write_to_ppu_addr(0x3f);
+        ppu.write_to_ppu_addr(0xe0);
+        assert_eq!(ppu.read_data() & 0x3f, 0x2c);                             //the top two bits come from open bus
+    }
+
+    #[test]
+    fn test_scroll_writes_fill_t_and_x() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b10);
+        ppu.write_to_scroll(0b0111_1101);                                      //coarse x 15, fine x 5
+        ppu.write_to_scroll(0b0101_1110);                                      //coarse y 11, fine y 6
+        assert_eq!(ppu.x, 5);
+        assert_eq!(ppu.t, (0b110 << 12) | (0b10 << 10) | (11 << 5) | 15);
+
+        ppu.write_to_ppu_addr(0x3d);                                           //$2006 shares the same t and w
+        ppu.write_to_ppu_addr(0xf0);
+        assert_eq!(ppu.v, 0x3df0);
+    }
+
+    fn run_scanlines(ppu: &mut NesPPU, lines: u16) {
+        for _ in 0..lines {
+            ppu.tick(DOTS_PER_SCANLINE);
+        }
+    }
+
+    #[test]
+    fn test_vblank_timing_and_nmi_line() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_ctrl(0b1000_0000);
+
+        run_scanlines(&mut ppu, VBLANK_SCANLINE);
+        ppu.tick(1);
+        assert!(!ppu.status.is_in_vblank());
+        ppu.tick(1);
+        assert!(ppu.status.is_in_vblank());
+        assert!(ppu.nmi_line());
+        assert_eq!(ppu.frame_count, 1);
+
+        run_scanlines(&mut ppu, PRE_RENDER_SCANLINE - VBLANK_SCANLINE);
+        assert!(!ppu.status.is_in_vblank());
+        assert!(!ppu.nmi_line());
+    }
+
+    #[test]
+    fn test_odd_frames_are_one_dot_shorter_when_rendering() {
+        let mut ppu = NesPPU::new_empty_rom();
+        ppu.write_to_mask(0b0000_1000);
+
+        run_scanlines(&mut ppu, 262);
+        assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+        run_scanlines(&mut ppu, 261);
+        ppu.tick(DOTS_PER_SCANLINE - 1);
+        assert_eq!((ppu.scanline, ppu.dot), (0, 0));
+    }
+
+    fn solid_tile_ppu() -> NesPPU {                                          //tile 1 is solid color 3, tile 0 is blank
+        let mut chr = vec![0; 0x2000];
+        for b in chr[16..32].iter_mut() {
+            *b = 0xff;
+        }
+        let mut ppu = NesPPU::new(chr, Mirroring::Horizontal);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[3] = 0x30;
+        ppu.palette_table[0x13] = 0x16;
+        ppu
+    }
+
+    #[test]
+    fn test_background_renders_into_frame() {
+        let mut ppu = solid_tile_ppu();
+        ppu.vram[0] = 1;                                                      //top left tile of the first nametable
+        ppu.write_to_mask(0b0000_1010);
+
+        run_scanlines(&mut ppu, 262);                                    //pre-render line loads the first tiles
+        run_scanlines(&mut ppu, 262);
+
+        assert_eq!(ppu.frame.pixel(0, 0), palette::SYSTEM_PALETTE[0x30]);
+        assert_eq!(ppu.frame.pixel(7, 7), palette::SYSTEM_PALETTE[0x30]);
+        assert_eq!(ppu.frame.pixel(8, 0), palette::SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.frame.pixel(0, 8), palette::SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_fine_x_scroll_shifts_background() {
+        let mut ppu = solid_tile_ppu();
+        ppu.vram[1] = 1;
+        ppu.write_to_mask(0b0000_1010);
+        ppu.write_to_scroll(3);
+        ppu.write_to_scroll(0);
+
+        run_scanlines(&mut ppu, 2 * 262);
+
+        assert_eq!(ppu.frame.pixel(4, 0), palette::SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.frame.pixel(5, 0), palette::SYSTEM_PALETTE[0x30]);
+        assert_eq!(ppu.frame.pixel(12, 0), palette::SYSTEM_PALETTE[0x30]);
+        assert_eq!(ppu.frame.pixel(13, 0), palette::SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_sprite_rendering_and_sprite_zero_hit() {
+        let mut ppu = solid_tile_ppu();
+        ppu.vram[0] = 1;
+        ppu.oam_data[0..4].copy_from_slice(&[0, 1, 0, 4]);                    //sprite 0 at (4, 1) overlapping the solid background tile
+        ppu.oam_data[4..8].copy_from_slice(&[20, 1, 0, 100]);
+        ppu.write_to_mask(0b0001_1110);
+
+        run_scanlines(&mut ppu, 262);
+        run_scanlines(&mut ppu, 240);
+
+        assert!(ppu.status.contains(StatusRegister::SPRITE_ZERO_HIT));
+        assert_eq!(ppu.frame.pixel(100, 21), palette::SYSTEM_PALETTE[0x16]);
+        assert_eq!(ppu.frame.pixel(100, 20), palette::SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.frame.pixel(107, 28), palette::SYSTEM_PALETTE[0x16]);
+        assert_eq!(ppu.frame.pixel(108, 21), palette::SYSTEM_PALETTE[0x0f]);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let mut ppu = solid_tile_ppu();
+        for i in 0..9 {
+            ppu.oam_data[i * 4..i * 4 + 4].copy_from_slice(&[50, 1, 0, (i * 10) as u8]);
+        }
+        for i in 9..64 {
+            ppu.oam_data[i * 4] = 0xff;
+        }
+        ppu.write_to_mask(0b0001_0000);
+
+        run_scanlines(&mut ppu, 262);
+        run_scanlines(&mut ppu, 50);
+        assert!(!ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+        ppu.tick(DOTS_PER_SCANLINE);
+        assert!(ppu.status.contains(StatusRegister::SPRITE_OVERFLOW));
+    }
+
+    #[test]
+    fn test_8x16_sprites() {
+        let mut chr = vec![0; 0x2000];
+        for b in chr[0x1000 + 48..0x1000 + 64].iter_mut() {                  //tile 3 of the right table is the bottom half of sprite $03
+            *b = 0xff;
+        }
+        let mut ppu = NesPPU::new(chr, Mirroring::Horizontal);
+        ppu.palette_table[0] = 0x0f;
+        ppu.palette_table[0x13] = 0x16;
+        ppu.oam_data[0..4].copy_from_slice(&[9, 0x03, 0, 0]);
+        for i in 1..64 {
+            ppu.oam_data[i * 4] = 0xff;
+        }
+        ppu.write_to_ctrl(0b0010_0000);
+        ppu.write_to_mask(0b0001_0100);
+
+        run_scanlines(&mut ppu, 2 * 262);
+
+        assert_eq!(ppu.frame.pixel(0, 17), palette::SYSTEM_PALETTE[0x0f]);
+        assert_eq!(ppu.frame.pixel(0, 18), palette::SYSTEM_PALETTE[0x16]);
+        assert_eq!(ppu.frame.pixel(7, 25), palette::SYSTEM_PALETTE[0x16]);
+        assert_eq!(ppu.frame.pixel(0, 26), palette::SYSTEM_PALETTE[0x0f]);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::CPU::Mem;
+
+/*
+    RAM SEARCH
+    narrows the 2KiB of work RAM down to the addresses that behave like a game variable,
+    the way the FCEUX RAM search does. every filter compares RAM as it is now against the
+    snapshot taken by the last filter (or by snapshot/restart), drops the candidates that
+    don't match and takes a new snapshot. a 2 byte value is little endian, the one at $07FF
+    takes its high byte from $0000 like the CPU would see through the mirror
+
+    memory is read with Mem::peek so searching never disturbs the game
+*/
+const RAM_SIZE: usize = 0x800;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSize {
+    Byte,
+    Word,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueType {
+    pub size: ValueSize,
+    pub signed: bool,
+}
+
+impl ValueType {
+    pub const U8: ValueType = ValueType { size: ValueSize::Byte, signed: false };
+    pub const S8: ValueType = ValueType { size: ValueSize::Byte, signed: true };
+    pub const U16: ValueType = ValueType { size: ValueSize::Word, signed: false };
+    pub const S16: ValueType = ValueType { size: ValueSize::Word, signed: true };
+
+    pub fn parse(text: &str) -> Option<ValueType> {                           //u8, s8, u16 or s16
+        match text.to_ascii_lowercase().as_str() {
+            "u8" => Some(ValueType::U8),
+            "s8" => Some(ValueType::S8),
+            "u16" => Some(ValueType::U16),
+            "s16" => Some(ValueType::S16),
+            _ => None,
+        }
+    }
+
+    fn decode(&self, lo: u8, hi: u8) -> i32 {
+        match (self.size, self.signed) {
+            (ValueSize::Byte, false) => lo as i32,
+            (ValueSize::Byte, true) => lo as i8 as i32,
+            (ValueSize::Word, false) => u16::from_le_bytes([lo, hi]) as i32,
+            (ValueSize::Word, true) => i16::from_le_bytes([lo, hi]) as i32,
+        }
+    }
+
+    pub fn read<M: Mem>(&self, mem: &M, addr: u16) -> i32 {
+        self.decode(mem.peek(addr), mem.peek(addr.wrapping_add(1)))
+    }
+
+    fn read_ram(&self, ram: &[u8], addr: u16) -> i32 {
+        let addr = addr as usize;
+        self.decode(ram[addr], ram[(addr + 1) % RAM_SIZE])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Equal,                                                                    //the same as in the snapshot
+    Changed,
+    Increased,
+    Decreased,
+    Value(i32),                                                               //read as the search's value type
+}
+
+impl Filter {
+    fn keeps(&self, before: i32, now: i32) -> bool {
+        match *self {
+            Filter::Equal => now == before,
+            Filter::Changed => now != before,
+            Filter::Increased => now > before,
+            Filter::Decreased => now < before,
+            Filter::Value(value) => now == value,
+        }
+    }
+}
+
+fn read_ram<M: Mem>(mem: &M) -> Vec<u8> {
+    (0..RAM_SIZE as u16).map(|addr| mem.peek(addr)).collect()
+}
+
+pub struct RamSearch {
+    value_type: ValueType,
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,                                                     //ascending
+}
+
+impl RamSearch {
+    pub fn new<M: Mem>(mem: &M, value_type: ValueType) -> Self {              //every address is a candidate to start with
+        RamSearch { value_type, snapshot: read_ram(mem), candidates: (0..RAM_SIZE as u16).collect() }
+    }
+
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+
+    pub fn set_value_type(&mut self, value_type: ValueType) {                 //the candidates stay, only the way they are read changes
+        self.value_type = value_type;
+    }
+
+    pub fn restart<M: Mem>(&mut self, mem: &M) {
+        *self = RamSearch::new(mem, self.value_type);
+    }
+
+    pub fn snapshot<M: Mem>(&mut self, mem: &M) {                             //the next filter compares against RAM as it is now
+        self.snapshot = read_ram(mem);
+    }
+
+    pub fn filter<M: Mem>(&mut self, mem: &M, filter: Filter) -> usize {      //hands back how many candidates are left
+        let now = read_ram(mem);
+        let value_type = self.value_type;
+        let before = &self.snapshot;
+        self.candidates
+            .retain(|&addr| filter.keeps(value_type.read_ram(before, addr), value_type.read_ram(&now, addr)));
+        self.snapshot = now;
+        self.candidates.len()
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    pub fn snapshot_value(&self, addr: u16) -> i32 {                          //the value at addr when the last snapshot was taken
+        self.value_type.read_ram(&self.snapshot, addr & (RAM_SIZE as u16 - 1))
+    }
+}
+
+/*
+    WATCH LIST
+    addresses to keep an eye on while the game runs, call update once a frame. any address
+    can be watched, though outside of RAM and PRG RAM Bus::peek has nothing useful to say
+*/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub addr: u16,
+    pub value_type: ValueType,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchValue {
+    pub addr: u16,
+    pub value: i32,
+    pub changed: bool,                                                        //since the last update, false on the first
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct WatchList {
+    watches: Vec<Watch>,
+    last: Vec<Option<i32>>,                                                   //one per watch, None until it has been read
+}
+
+impl WatchList {
+    pub fn new() -> Self {
+        WatchList::default()
+    }
+
+    pub fn add(&mut self, watch: Watch) -> usize {
+        self.watches.push(watch);
+        self.last.push(None);
+        self.watches.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watch> {
+        if index >= self.watches.len() {
+            return None;
+        }
+        self.last.remove(index);
+        Some(self.watches.remove(index))
+    }
+
+    pub fn clear(&mut self) {
+        self.watches.clear();
+        self.last.clear();
+    }
+
+    pub fn list(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    pub fn update<M: Mem>(&mut self, mem: &M) -> Vec<WatchValue> {            //one value per watch, in the order they were added
+        self.watches
+            .iter()
+            .zip(self.last.iter_mut())
+            .map(|(watch, last)| {
+                let value = watch.value_type.read(mem, watch.addr);
+                let changed = last.is_some_and(|last| last != value);
+                *last = Some(value);
+                WatchValue { addr: watch.addr, value, changed }
+            })
+            .collect()
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_value_types() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x07FF, 0xFE);
+        bus.mem_write(0x0000, 0x80);
+        assert_eq!(ValueType::U8.read(&bus, 0x07FF), 0xFE);
+        assert_eq!(ValueType::S8.read(&bus, 0x07FF), -2);
+        assert_eq!(ValueType::U16.read(&bus, 0x07FF), 0x80FE);                //the high byte comes through the mirror
+        assert_eq!(ValueType::S16.read(&bus, 0x07FF), -32514);
+        assert_eq!(ValueType::parse("S16"), Some(ValueType::S16));
+        assert_eq!(ValueType::parse("u32"), None);
+    }
+
+    #[test]
+    fn test_search_finds_a_counter() {
+        let mut bus = Bus::new();
+        bus.mem_write(0x0042, 3);                                             //lives
+        bus.mem_write(0x0100, 7);
+        let mut search = RamSearch::new(&bus, ValueType::U8);
+        assert_eq!(search.len(), 0x800);
+
+        bus.mem_write(0x0042, 2);
+        bus.mem_write(0x0100, 6);
+        assert_eq!(search.filter(&bus, Filter::Decreased), 2);
+        bus.mem_write(0x0100, 9);
+        assert_eq!(search.filter(&bus, Filter::Equal), 1);
+        assert_eq!(search.candidates(), [0x0042]);
+        assert_eq!(search.snapshot_value(0x0042), 2);
+
+        search.restart(&bus);
+        assert_eq!(search.filter(&bus, Filter::Value(9)), 1);
+        bus.mem_write(0x0100, 0xFF);
+        search.set_value_type(ValueType::S8);
+        assert_eq!(search.filter(&bus, Filter::Decreased), 1);                //9 to -1 when read signed
+        assert_eq!(search.filter(&bus, Filter::Changed), 0);
+        assert!(search.is_empty());
+
+        bus.mem_write(0x0300, 0x00);
+        bus.mem_write(0x0301, 0x01);
+        let mut search = RamSearch::new(&bus, ValueType::U16);
+        bus.mem_write(0x0300, 0xFF);
+        bus.mem_write(0x0301, 0x00);
+        search.filter(&bus, Filter::Decreased);
+        assert!(search.candidates().contains(&0x0300));                       //0x0100 down to 0x00FF
+        assert!(!search.candidates().contains(&0x02FF));                      //0x0000 up to 0xFF00
+    }
+
+    #[test]
+    fn test_watch_list() {
+        let mut bus = Bus::new();
+        let mut watches = WatchList::new();
+        watches.add(Watch { addr: 0x0010, value_type: ValueType::U8, name: "x".to_string() });
+        let speed = watches.add(Watch { addr: 0x0020, value_type: ValueType::S16, name: "speed".to_string() });
+        bus.mem_write(0x0010, 5);
+        assert_eq!(watches.update(&bus)[0], WatchValue { addr: 0x0010, value: 5, changed: false });
+        bus.mem_write(0x0021, 0xFF);
+        let values = watches.update(&bus);
+        assert_eq!((values[0].changed, values[1].value, values[1].changed), (false, -256, true));
+        assert_eq!(watches.remove(speed).unwrap().name, "speed");
+        assert_eq!(watches.update(&bus).len(), 1);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::VecDeque;
+
+use crate::joypad::JoypadButton;
+use crate::savestate::StateError;
+use crate::CPU::{StopReason, CPU};
+
+/*
+    REWIND
+    the newest snapshot is kept whole, every older one is stored as the XOR against the
+    snapshot after it, run length encoded. consecutive states differ in a few hundred bytes
+    so most of a delta is runs of zeros. going back undoes the newest delta, dropping the
+    oldest costs nothing since nothing depends on it
+
+    snapshots are only taken every `interval` frames, the controller input of every frame
+    in between is logged so stepping back one frame loads the snapshot before it and
+    replays the frames up to the one that was asked for
+
+    call record once at the start of every frame, after the input for it has been set
+*/
+const INPUT_SIZE: usize = 2;                                                  //one byte per controller in the memory estimate
+
+struct Delta {
+    frame: u64,
+    len: usize,                                                               //length of the older state, in case it differs
+    data: Vec<u8>,
+}
+
+pub struct Rewind {
+    interval: u64,
+    budget: usize,
+    latest: Vec<u8>,                                                          //empty until the first snapshot
+    latest_frame: u64,
+    deltas: VecDeque<Delta>,                                                  //oldest first
+    first_input_frame: u64,
+    inputs: VecDeque<[JoypadButton; 2]>,                                      //one entry per frame from first_input_frame on
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {                        //budget is in bytes and covers snapshots and the input log
+        Rewind {
+            interval: interval.max(1) as u64,
+            budget,
+            latest: vec![],
+            latest_frame: 0,
+            deltas: VecDeque::new(),
+            first_input_frame: 0,
+            inputs: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.latest.clear();
+        self.deltas.clear();
+        self.inputs.clear();
+        self.delta_bytes = 0;
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.latest.len() + self.delta_bytes + self.inputs.len() * INPUT_SIZE
+    }
+
+    pub fn oldest_frame(&self) -> Option<u64> {
+        if self.latest.is_empty() {
+            return None;
+        }
+        Some(self.deltas.front().map_or(self.latest_frame, |delta| delta.frame))
+    }
+
+    pub fn frames_available(&self, cpu: &CPU) -> u64 {                        //how far back step_back can go from here
+        match self.oldest_frame() {
+            Some(oldest) => cpu.bus.ppu().frame_count.saturating_sub(oldest),
+            None => 0,
+        }
+    }
+
+    fn next_input_frame(&self) -> u64 {
+        self.first_input_frame + self.inputs.len() as u64
+    }
+
+    pub fn record(&mut self, cpu: &CPU) {
+        let frame = cpu.bus.ppu().frame_count;
+        let input = [cpu.bus.joypad1().buttons(), cpu.bus.joypad2().buttons()];
+
+        if !self.latest.is_empty() && (frame < self.latest_frame || frame > self.next_input_frame()) {
+            self.clear();                                                     //a state was loaded or frames went unrecorded
+        }
+        if self.latest.is_empty() {
+            self.first_input_frame = frame;
+        }
+        self.inputs.truncate((frame - self.first_input_frame) as usize);      //recording the same frame again replaces its input
+        self.inputs.push_back(input);
+
+        if self.latest.is_empty() || frame >= self.latest_frame + self.interval {
+            self.push_snapshot(frame, cpu.save_state());
+        }
+    }
+
+    fn push_snapshot(&mut self, frame: u64, state: Vec<u8>) {
+        if !self.latest.is_empty() {
+            let delta = Delta {
+                frame: self.latest_frame,
+                len: self.latest.len(),
+                data: compress(&xor(&self.latest, &state)),
+            };
+            self.delta_bytes += delta.data.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = state;
+        self.latest_frame = frame;
+
+        while self.memory_used() > self.budget {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.delta_bytes -= oldest.data.len(),
+                None => break,                                                //the newest snapshot always stays
+            }
+            let oldest = self.oldest_frame().unwrap_or(self.latest_frame);
+            self.inputs.drain(..(oldest - self.first_input_frame) as usize);
+            self.first_input_frame = oldest;
+        }
+    }
+
+    fn pop_snapshot(&mut self) -> bool {                                      //makes the snapshot before the newest one the newest
+        match self.deltas.pop_back() {
+            Some(delta) => {
+                self.delta_bytes -= delta.data.len();
+                let mut older = xor(&self.latest, &decompress(&delta.data, self.latest.len().max(delta.len)));
+                older.truncate(delta.len);
+                self.latest = older;
+                self.latest_frame = delta.frame;
+                true
+            }
+            None => false,
+        }
+    }
+
+    //goes back to the start of the previous frame. false when that frame isn't in the buffer,
+    //in which case the machine is left alone
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Result<bool, StateError> {
+        let frame = cpu.bus.ppu().frame_count;
+        let target = match self.oldest_frame() {
+            Some(oldest) if frame > oldest && frame <= self.next_input_frame() => frame - 1,
+            _ => return Ok(false),
+        };
+        while self.latest_frame > target && self.pop_snapshot() {}
+
+        cpu.load_state(&self.latest)?;
+        for replayed in self.latest_frame..target {
+            let [pad1, pad2] = self.inputs[(replayed - self.first_input_frame) as usize];
+            cpu.bus.joypad1_mut().set_buttons(pad1);
+            cpu.bus.joypad2_mut().set_buttons(pad2);
+            if cpu.run_frame() != StopReason::FrameComplete {
+                return Err(StateError::Corrupt(format!("replaying frame {} did not finish it", replayed)));
+            }
+        }
+        let [pad1, pad2] = self.inputs[(target - self.first_input_frame) as usize];
+        cpu.bus.joypad1_mut().set_buttons(pad1);                              //the frame we landed on starts with the input it had
+        cpu.bus.joypad2_mut().set_buttons(pad2);
+        self.inputs.truncate((target + 1 - self.first_input_frame) as usize);
+        Ok(true)
+    }
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {                                       //the shorter side counts as zero padded
+    (0..a.len().max(b.len()))
+        .map(|i| a.get(i).copied().unwrap_or(0) ^ b.get(i).copied().unwrap_or(0))
+        .collect()
+}
+
+/*
+    DELTA ENCODING
+    a list of (zero run, literal run, literal bytes) with both runs as LEB128 varints
+*/
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(&byte) = data.get(*pos) {
+        *pos += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut pos = 0;
+    while pos < data.len() {
+        let zeros = data[pos..].iter().take_while(|&&byte| byte == 0).count();
+        let start = pos + zeros;
+        let mut end = start;
+        while end < data.len() && data[end..].iter().take(4).any(|&byte| byte != 0) {  //short zero gaps are cheaper as literals
+            end += 1;
+        }
+        end = data[start..end].iter().rposition(|&byte| byte != 0).map_or(start, |last| start + last + 1);
+        push_varint(&mut out, zeros);
+        push_varint(&mut out, end - start);
+        out.extend_from_slice(&data[start..end]);
+        pos = end;
+    }
+    out
+}
+
+fn decompress(data: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    while pos < data.len() {
+        let zeros = read_varint(data, &mut pos);
+        let literals = read_varint(data, &mut pos);
+        out.resize(out.len() + zeros, 0);
+        out.extend_from_slice(&data[pos..(pos + literals).min(data.len())]);
+        pos += literals;
+    }
+    out.resize(len, 0);
+    out
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cartridge::test::joypad_echo_rom;
+
+    fn machine() -> CPU {
+        let mut cpu = CPU::with_bus(Bus::with_rom(joypad_echo_rom()).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    fn buttons_for(frame: u64) -> JoypadButton {
+        JoypadButton::from_bits_truncate((frame * 37 % 256) as u8)
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        let mut data = vec![0u8; 5000];
+        data[3] = 1;
+        data[4] = 2;
+        data[7] = 3;
+        data[4000] = 0xFF;
+        let packed = compress(&data);
+        assert!(packed.len() < 20);
+        assert_eq!(decompress(&packed, data.len()), data);
+        assert_eq!(decompress(&compress(&[]), 0), Vec::<u8>::new());
+        assert_eq!(decompress(&compress(&[9; 300]), 300), vec![9; 300]);
+    }
+
+    #[test]
+    fn test_step_back_frame_by_frame() {
+        let mut cpu = machine();
+        let mut rewind = Rewind::new(4, 1 << 20);
+        let mut states = vec![];
+        for frame in 0..30 {
+            cpu.bus.joypad1_mut().set_buttons(buttons_for(frame));
+            rewind.record(&cpu);
+            states.push((cpu.bus.ppu().frame_count, cpu.save_state()));
+            cpu.run_frame();
+        }
+        assert_ne!(states[10].1, states[11].1);
+
+        rewind.record(&cpu);
+        for expected in states.iter().rev().take(20) {
+            assert!(rewind.step_back(&mut cpu).unwrap());
+            assert_eq!(cpu.bus.ppu().frame_count, expected.0);
+            assert!(cpu.save_state() == expected.1, "frame {} differs", expected.0);
+        }
+    }
+
+    #[test]
+    fn test_recording_after_stepping_back_branches() {
+        let mut cpu = machine();
+        let mut rewind = Rewind::new(3, 1 << 20);
+        for frame in 0..10 {
+            cpu.bus.joypad1_mut().set_buttons(buttons_for(frame));
+            rewind.record(&cpu);
+            cpu.run_frame();
+        }
+        rewind.record(&cpu);
+        rewind.step_back(&mut cpu).unwrap();
+        rewind.step_back(&mut cpu).unwrap();
+        let frame = cpu.bus.ppu().frame_count;
+
+        cpu.bus.joypad1_mut().set_buttons(JoypadButton::START);              //a different input than the first time through
+        rewind.record(&cpu);
+        let branched = cpu.save_state();
+        cpu.run_frame();
+        rewind.record(&cpu);
+        assert!(rewind.step_back(&mut cpu).unwrap());
+        assert_eq!(cpu.bus.ppu().frame_count, frame);
+        assert!(cpu.save_state() == branched);
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let mut cpu = machine();
+        let state_size = cpu.save_state().len();
+        let mut rewind = Rewind::new(1, state_size + 2000);
+        for frame in 0..100 {
+            cpu.bus.joypad1_mut().set_buttons(buttons_for(frame));
+            rewind.record(&cpu);
+            assert!(rewind.memory_used() <= state_size + 2000);
+            cpu.run_frame();
+        }
+        rewind.record(&cpu);
+        let available = rewind.frames_available(&cpu);
+        assert!(available > 5 && available < 100, "{} frames kept", available);
+
+        for _ in 0..available {
+            assert!(rewind.step_back(&mut cpu).unwrap());
+        }
+        assert!(!rewind.step_back(&mut cpu).unwrap());                        //nothing older is left
+    }
+}
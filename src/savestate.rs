@@ -0,0 +1,396 @@
+use std::fmt;
+
+use crate::bus::Bus;
+use crate::CPU::CPU;
+
+/*
+    SAVE STATES
+    a state is a small header followed by one chunk per component
+
+        "NSST"          magic
+        u16             format version
+        u32             CRC-32 of the PRG and CHR ROM the state was taken with
+        chunks          4 byte tag, u32 length, payload
+
+    every number is little endian. components write themselves through the Snapshot trait,
+    a chunk that is missing or not read back to its last byte is an error, chunks with an
+    unknown tag are skipped. the framebuffer and the audio resampler are output rather than
+    state and are left alone, the next frame redraws the screen anyway
+*/
+const MAGIC: [u8; 4] = *b"NSST";
+pub const STATE_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 10;
+
+const CHUNK_CPU: [u8; 4] = *b"CPU ";
+const CHUNK_BUS: [u8; 4] = *b"BUS ";
+const CHUNK_PPU: [u8; 4] = *b"PPU ";
+const CHUNK_APU: [u8; 4] = *b"APU ";
+const CHUNK_JOYPAD_1: [u8; 4] = *b"PAD1";
+const CHUNK_JOYPAD_2: [u8; 4] = *b"PAD2";
+const CHUNK_MAPPER: [u8; 4] = *b"MAPR";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    NotAState,                                                                //missing the magic bytes
+    UnsupportedVersion { found: u16, supported: u16 },
+    WrongRom { expected: u32, found: u32 },                                  //CRC-32 of the loaded ROM and of the one in the state
+    MissingChunk(String),
+    Corrupt(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion { found, supported } => {
+                write!(f, "save state version {} is not supported, expected version {}", found, supported)
+            }
+            StateError::WrongRom { expected, found } => write!(
+                f,
+                "save state was made with a different ROM (CRC-32 {:08x}, the loaded ROM is {:08x})",
+                found, expected
+            ),
+            StateError::MissingChunk(tag) => write!(f, "save state has no '{}' chunk", tag),
+            StateError::Corrupt(reason) => write!(f, "save state is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+pub trait Snapshot {
+    fn save(&self, w: &mut StateWriter);
+    fn load(&mut self, r: &mut StateReader) -> Result<(), StateError>;
+}
+
+/*
+    READING AND WRITING
+
+*/
+#[derive(Default)]
+pub struct StateWriter {
+    buf: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        StateWriter { buf: vec![] }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.buf.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {                                     //length prefixed so the reader can check it
+        self.u32(data.len() as u32);
+        self.buf.extend_from_slice(data);
+    }
+
+    fn chunk(&mut self, tag: [u8; 4], component: &dyn Snapshot) {
+        let mut inner = StateWriter::new();
+        component.save(&mut inner);
+        self.buf.extend_from_slice(&tag);
+        self.bytes(&inner.buf);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        StateReader { data, pos: 0 }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.remaining() < len {
+            return Err(StateError::Corrupt(format!("ran out of data at byte {}", self.pos)));
+        }
+        let slice = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(slice)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], StateError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn bytes_into(&mut self, target: &mut [u8]) -> Result<(), StateError> {  //the saved length has to match the target exactly
+        let data = self.bytes()?;
+        if data.len() != target.len() {
+            return Err(StateError::Corrupt(format!("expected {} bytes but found {}", target.len(), data.len())));
+        }
+        target.copy_from_slice(data);
+        Ok(())
+    }
+}
+
+/*
+    CRC-32 (the zlib one, reflected polynomial 0xEDB88320)
+
+*/
+lazy_static! {
+    static ref CRC_TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut crc = i as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+            *entry = crc;
+        }
+        table
+    };
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8))
+}
+
+fn rom_checksum(bus: &Bus) -> u32 {                                           //0 for raw programs without a cartridge
+    match bus.rom() {
+        Some(rom) => crc32(&[rom.prg_rom.as_slice(), rom.chr_rom.as_slice()].concat()),
+        None => 0,
+    }
+}
+
+/*
+    WHOLE MACHINE
+
+*/
+pub fn save_state(cpu: &CPU<Bus>) -> Vec<u8> {
+    let mut w = StateWriter::new();
+    w.buf.extend_from_slice(&MAGIC);
+    w.u16(STATE_VERSION);
+    w.u32(rom_checksum(&cpu.bus));
+
+    w.chunk(CHUNK_CPU, cpu);
+    w.chunk(CHUNK_BUS, &cpu.bus);
+    w.chunk(CHUNK_PPU, cpu.bus.ppu());
+    w.chunk(CHUNK_APU, cpu.bus.apu());
+    w.chunk(CHUNK_JOYPAD_1, cpu.bus.joypad1());
+    w.chunk(CHUNK_JOYPAD_2, cpu.bus.joypad2());
+    w.chunk(CHUNK_MAPPER, &*cpu.bus.ppu().mapper().borrow());                //the PPU's board is the inserted cartridge, or its own NROM without one
+    w.into_bytes()
+}
+
+fn tag_name(tag: [u8; 4]) -> String {
+    String::from_utf8_lossy(&tag).trim_end().to_string()
+}
+
+fn load_chunk(chunks: &[([u8; 4], &[u8])], tag: [u8; 4], component: &mut dyn Snapshot) -> Result<(), StateError> {
+    let data = match chunks.iter().find(|(t, _)| *t == tag) {
+        Some((_, data)) => data,
+        None => return Err(StateError::MissingChunk(tag_name(tag))),
+    };
+    let mut r = StateReader::new(data);
+    component.load(&mut r)?;
+    if r.remaining() != 0 {
+        return Err(StateError::Corrupt(format!("'{}' chunk has {} bytes left over", tag_name(tag), r.remaining())));
+    }
+    Ok(())
+}
+
+//the header and the chunk framing are checked before anything is touched, a chunk that
+//fails part way through can leave the machine half loaded
+pub fn load_state(cpu: &mut CPU<Bus>, data: &[u8]) -> Result<(), StateError> {
+    if data.len() < HEADER_SIZE || data[..4] != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let mut r = StateReader::new(&data[4..]);
+    let version = r.u16()?;
+    if version != STATE_VERSION {
+        return Err(StateError::UnsupportedVersion { found: version, supported: STATE_VERSION });
+    }
+    let found = r.u32()?;
+    let expected = rom_checksum(&cpu.bus);
+    if found != expected {
+        return Err(StateError::WrongRom { expected, found });
+    }
+
+    let mut chunks = vec![];
+    while r.remaining() > 0 {
+        let tag: [u8; 4] = r.take(4)?.try_into().unwrap();
+        chunks.push((tag, r.bytes()?));
+    }
+    for tag in [CHUNK_CPU, CHUNK_BUS, CHUNK_PPU, CHUNK_APU, CHUNK_JOYPAD_1, CHUNK_JOYPAD_2, CHUNK_MAPPER] {
+        if !chunks.iter().any(|(t, _)| *t == tag) {
+            return Err(StateError::MissingChunk(tag_name(tag)));
+        }
+    }
+
+    load_chunk(&chunks, CHUNK_CPU, cpu)?;
+    load_chunk(&chunks, CHUNK_BUS, &mut cpu.bus)?;
+    load_chunk(&chunks, CHUNK_PPU, cpu.bus.ppu_mut())?;
+    load_chunk(&chunks, CHUNK_APU, cpu.bus.apu_mut())?;
+    load_chunk(&chunks, CHUNK_JOYPAD_1, cpu.bus.joypad1_mut())?;
+    load_chunk(&chunks, CHUNK_JOYPAD_2, cpu.bus.joypad2_mut())?;
+    let mapper = cpu.bus.ppu().mapper().clone();
+    load_chunk(&chunks, CHUNK_MAPPER, &mut *mapper.borrow_mut())?;
+    Ok(())
+}
+
+impl CPU {
+    pub fn save_state(&self) -> Vec<u8> {
+        save_state(self)
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        load_state(self, data)
+    }
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::joypad::JoypadButton;
+    use crate::mapper::test::banked_rom;
+    use crate::CPU::Mem;
+
+    fn running_cpu(rom: crate::cartridge::Rom) -> CPU<Bus> {
+        let mut cpu = CPU::with_bus(Bus::with_rom(rom).unwrap());
+        cpu.reset();
+        cpu
+    }
+
+    //counts in RAM, turns the PPU and a pulse channel on, then loops forever
+    fn busy_program() -> Vec<u8> {
+        crate::asm!(
+            "        LDA #$1E",
+            "        STA $2001",
+            "        LDA #$BF",
+            "        STA $4000",
+            "        LDA #$01",
+            "        STA $4015",
+            "        STA $4003",
+            "loop:   INC $10",
+            "        LDX $10",
+            "        STA $0300,X",
+            "        JMP loop",
+        )
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_round_trip_resumes_identically() {
+        let mut cpu = running_cpu(test_rom(busy_program()));
+        cpu.run_cycles(50_000);
+        cpu.bus.joypad1_mut().set_buttons(JoypadButton::START);
+        let state = cpu.save_state();
+
+        cpu.run_cycles(40_000);
+        let expected = (cpu.save_state(), cpu.bus.ppu().frame.data.clone());
+
+        let mut other = running_cpu(test_rom(busy_program()));
+        other.load_state(&state).unwrap();
+        assert_eq!(other.save_state(), state);
+        other.run_cycles(40_000);
+        assert_eq!(other.save_state(), expected.0);
+        assert_eq!(other.bus.ppu().frame.data, expected.1);                   //a whole frame was drawn since the load
+    }
+
+    #[test]
+    fn test_mapper_registers_and_prg_ram_are_restored() {
+        let mut cpu = running_cpu(banked_rom(2, 8, 0));
+        cpu.mem_write(0x8000, 5);                                             //UxROM bank 5 at $8000
+        cpu.mem_write(0x6000, 0x42);
+        let state = cpu.save_state();
+
+        cpu.mem_write(0x8000, 1);
+        cpu.mem_write(0x6000, 0);
+        cpu.load_state(&state).unwrap();
+        assert_eq!(cpu.mem_read(0x8000), 5 * 16);
+        assert_eq!(cpu.mem_read(0x6000), 0x42);
+    }
+
+    #[test]
+    fn test_rejects_other_roms_versions_and_garbage() {
+        let mut cpu = running_cpu(test_rom(busy_program()));
+        let state = cpu.save_state();
+
+        let mut other = running_cpu(test_rom(vec![0xea, 0x4c, 0x00, 0x80]));
+        match other.load_state(&state) {
+            Err(StateError::WrongRom { .. }) => {}
+            other => panic!("expected a ROM mismatch, got {:?}", other),
+        }
+
+        let mut newer = state.clone();
+        newer[4] = STATE_VERSION as u8 + 1;
+        assert_eq!(
+            cpu.load_state(&newer),
+            Err(StateError::UnsupportedVersion { found: STATE_VERSION + 1, supported: STATE_VERSION })
+        );
+        assert_eq!(cpu.load_state(b"hello world"), Err(StateError::NotAState));
+        assert!(matches!(cpu.load_state(&state[..state.len() - 3]), Err(StateError::Corrupt(_))));
+        assert!(cpu.load_state(&state).is_ok());
+    }
+
+    #[test]
+    fn test_missing_chunk() {
+        let mut cpu = running_cpu(test_rom(busy_program()));
+        let mut w = StateWriter::new();
+        w.buf.extend_from_slice(&MAGIC);
+        w.u16(STATE_VERSION);
+        w.u32(rom_checksum(&cpu.bus));
+        w.chunk(CHUNK_CPU, &cpu);
+        assert_eq!(cpu.load_state(&w.into_bytes()), Err(StateError::MissingChunk("BUS".to_string())));
+    }
+}
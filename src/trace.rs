@@ -0,0 +1,195 @@
+use crate::bus::Bus;
+use crate::opcodes;
+use crate::CPU::{operand_address, AddressMode, CPU};
+
+/*
+    EXECUTION TRACE
+    one line per instruction in the nestest.log layout, taken before the instruction runs
+    C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    memory is looked at through Bus::peek and the CPU is only borrowed, so tracing never
+    acknowledges a register read, trips open bus or a cheat, or moves the program counter
+*/
+pub fn trace(cpu: &CPU<Bus>) -> String {
+    let begin = cpu.program_counter;
+    let code = cpu.bus.peek(begin);
+    let opcode = opcodes::OPCODES_MAP
+        .get(&code)
+        .unwrap_or_else(|| panic!("OpCode {:x} is not recognized", code));
+
+    let mut hex_dump = vec![code];
+    for i in 1..opcode.len as u16 {
+        hex_dump.push(cpu.bus.peek(begin.wrapping_add(i)));
+    }
+
+    let (mem_addr, stored_value) = match &opcode.mode {
+        AddressMode::Immeditate | AddressMode::NoneAddress => (0, 0),
+        mode => {
+            let operand = begin.wrapping_add(1);
+            let (addr, _) = operand_address(mode, operand, cpu.register_x, cpu.register_y, |addr| cpu.bus.peek(addr));
+            (addr, cpu.bus.peek(addr))
+        }
+    };
+
+    let operand = match opcode.len {
+        1 => match code {
+            0x0a | 0x4a | 0x2a | 0x6a => "A ".to_string(),
+            _ => String::new(),
+        },
+        2 => {
+            let address = hex_dump[1];
+            match opcode.mode {
+                AddressMode::Immeditate => format!("#${:02x}", address),
+                AddressMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
+                AddressMode::ZeroPageX => format!("${:02x},X @ {:02x} = {:02x}", address, mem_addr, stored_value),
+                AddressMode::ZeroPageY => format!("${:02x},Y @ {:02x} = {:02x}", address, mem_addr, stored_value),
+                AddressMode::IndirectX => format!(
+                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
+                    address,
+                    address.wrapping_add(cpu.register_x),
+                    mem_addr,
+                    stored_value
+                ),
+                AddressMode::IndirectY => format!(
+                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
+                    address,
+                    mem_addr.wrapping_sub(cpu.register_y as u16),
+                    mem_addr,
+                    stored_value
+                ),
+                _ => {                                                        //branches show where they go
+                    let target = begin.wrapping_add(2).wrapping_add(address as i8 as u16);
+                    format!("${:04x}", target)
+                }
+            }
+        }
+        3 => {
+            let address = u16::from_le_bytes([hex_dump[1], hex_dump[2]]);
+            match (&opcode.mode, code) {
+                (_, 0x4c) | (_, 0x20) => format!("${:04x}", address),
+                (AddressMode::NoneAddress, 0x6c) => {                          //JMP ($xxFF) wraps inside the page like the real CPU
+                    let lo = cpu.bus.peek(address) as u16;
+                    let hi = cpu.bus.peek((address & 0xFF00) | (address.wrapping_add(1) & 0x00FF)) as u16;
+                    format!("(${:04x}) = {:04x}", address, (hi << 8) | lo)
+                }
+                (AddressMode::Absolute, _) => format!("${:04x} = {:02x}", mem_addr, stored_value),
+                (AddressMode::AbsoluteX, _) => format!("${:04x},X @ {:04x} = {:02x}", address, mem_addr, stored_value),
+                (AddressMode::AbsoluteY, _) => format!("${:04x},Y @ {:04x} = {:02x}", address, mem_addr, stored_value),
+                _ => format!("${:04x}", address),
+            }
+        }
+        _ => String::new(),
+    };
+
+    let hex_str = hex_dump.iter().map(|z| format!("{:02x}", z)).collect::<Vec<String>>().join(" ");
+    let asm_str = format!("{:04x}  {:8} {: >4} {}", begin, hex_str, opcode.mnemonic, operand)
+        .trim_end()
+        .to_string();
+    let ppu = cpu.bus.ppu();
+
+    format!(
+        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x} PPU:{:>3},{:>3} CYC:{}",
+        asm_str,
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status.bits(),
+        cpu.stack_pointer,
+        ppu.scanline,
+        ppu.dot,
+        cpu.cycles,
+    )
+    .to_ascii_uppercase()
+}
+
+
+/*
+    TEST CASES
+
+*/
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::CPU::Mem;
+
+    fn trace_program(program: Vec<u8>, setup: impl FnOnce(&mut CPU<Bus>)) -> Vec<String> {
+        let mut cpu = CPU::with_bus(Bus::with_rom(test_rom(program)).unwrap());
+        cpu.config.halt_on_brk = true;                                        //the programs end in a BRK
+        cpu.reset();
+        setup(&mut cpu);
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu));
+        });
+        result
+    }
+
+    #[test]
+    fn test_format_trace() {
+        let result = trace_program(vec![0xa2, 0x01, 0xca, 0x88, 0x00], |cpu| {
+            cpu.register_a = 1;
+            cpu.register_x = 2;
+            cpu.register_y = 3;
+        });
+        assert_eq!(
+            "8000  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD PPU:  0, 21 CYC:7",
+            result[0]
+        );
+        assert_eq!(
+            "8002  CA        DEX                             A:01 X:01 Y:03 P:24 SP:FD PPU:  0, 27 CYC:9",
+            result[1]
+        );
+        assert_eq!(
+            "8003  88        DEY                             A:01 X:00 Y:03 P:26 SP:FD PPU:  0, 33 CYC:11",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_mem_access() {
+        //ORA ($33),Y
+        let result = trace_program(vec![0x11, 0x33, 0x00], |cpu| {
+            cpu.mem_write(0x33, 0x00);
+            cpu.mem_write(0x34, 0x04);
+            cpu.mem_write(0x400, 0xAA);
+        });
+        assert_eq!(
+            "8000  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7",
+            result[0]
+        );
+    }
+
+    #[test]
+    fn test_format_jumps_branches_and_unofficial() {
+        let result = trace_program(
+            vec![0x4c, 0x05, 0x80, 0x00, 0x00, 0xd0, 0x01, 0x00, 0x04, 0x10, 0x6c, 0xff, 0x02, 0x0a],
+            |cpu| {
+                cpu.mem_write(0x10, 0x7e);
+                cpu.mem_write(0x2ff, 0x0d);                                   //JMP ($02FF) takes its high byte from $0200
+                cpu.mem_write(0x200, 0x80);
+                cpu.status.remove(crate::CPU::CpuFlags::ZERO);
+            },
+        );
+        assert!(result[0].starts_with("8000  4C 05 80  JMP $8005                       A:00"));
+        assert!(result[1].starts_with("8005  D0 01     BNE $8008                       A:00"));
+        assert!(result[2].starts_with("8008  04 10    *NOP $10 = 7E                    A:00"));
+        assert!(result[3].starts_with("800A  6C FF 02  JMP ($02FF) = 800D              A:00"));
+        assert!(result[4].starts_with("800D  0A        ASL A                           A:00"));
+    }
+
+    #[test]
+    fn test_trace_has_no_side_effects() {
+        let program = crate::asm!("loop:   JMP loop", "check:  LDA $2002");
+        let mut cpu = CPU::with_bus(Bus::with_rom(test_rom(program)).unwrap());
+        cpu.reset();
+        cpu.run_frame();                                                      //stops as vblank starts
+        cpu.program_counter = 0x8003;
+        let status = cpu.bus.ppu().status.bits();
+        assert!(status & 0x80 != 0);
+
+        let line = trace(&cpu);
+        assert!(line.starts_with("8003  AD 02 20  LDA $2002 = FF"), "{}", line);
+        assert_eq!(cpu.bus.ppu().status.bits(), status);                      //reading $2002 would have cleared vblank
+        assert_eq!(cpu.program_counter, 0x8003);
+    }
+}
@@ -0,0 +1,184 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use nes_emulator_rust::bus::Bus;
+use nes_emulator_rust::cartridge::{Rom, CHR_ROM_PAGE_SIZE, PRG_ROM_PAGE_SIZE};
+use nes_emulator_rust::trace::trace;
+use nes_emulator_rust::CPU::{StopReason, CPU};
+
+/*
+    TEST ROM HARNESS
+    the community test ROMs aren't redistributable, so they are read from a local fixtures
+    directory (tests/fixtures, or wherever NES_TEST_ROMS points). the tests that need them
+    are #[ignore]d and run with --ignored, where a missing ROM is a failure.
+
+    blargg's newer ROMs report through $6000:  $80 running, $81 wants a reset, anything
+    else is the final result (0 = passed), $6001-$6003 hold DE B0 61 once the protocol is
+    live and $6004 onwards is the zero terminated text the ROM would print.
+    the 2005 era ROMs (sprite_hit, cpu_timing) only write a result code to $00F8, 1 = passed.
+*/
+const STATUS_ADDR: u16 = 0x6000;
+const SIGNATURE_ADDR: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const TEXT_ADDR: u16 = 0x6004;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+const RESET_DELAY_FRAMES: u64 = 6;                                            //blargg asks for at least 100ms before pressing reset
+const LEGACY_RESULT_ADDR: u16 = 0x00F8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Status6000,
+    ResultF8,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed { code: u8, text: String },
+    TimedOut { frames: u64, text: String },
+    Crashed(StopReason),
+}
+
+pub fn fixtures_dir() -> PathBuf {
+    match env::var_os("NES_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests").join("fixtures"),
+    }
+}
+
+pub fn fixture(relative: &str) -> PathBuf {
+    let path = fixtures_dir().join(relative);
+    if !path.exists() {
+        panic!("missing test ROM {}, see tests/fixtures/README.md", path.display());
+    }
+    path
+}
+
+pub fn load_rom(relative: &str) -> Rom {
+    let path = fixture(relative);
+    Rom::from_file(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+pub fn program_rom(program: Vec<u8>) -> Rom {                                 //NROM with the program at $8000 and the reset vector pointing at it
+    let mut prg_rom = program;
+    prg_rom.resize(PRG_ROM_PAGE_SIZE, 0);
+    prg_rom[0x3FFC] = 0x00;
+    prg_rom[0x3FFD] = 0x80;
+    let mut raw = vec![0x4E, 0x45, 0x53, 0x1A, 0x01, 0x01, 0x00, 0x00, 00, 00, 00, 00, 00, 00, 00, 00];
+    raw.extend(prg_rom);
+    raw.extend(vec![0; CHR_ROM_PAGE_SIZE]);
+    Rom::new(&raw).expect("a plain NROM image")
+}
+
+pub fn new_cpu(rom: Rom) -> CPU<Bus> {
+    let mut cpu = CPU::with_bus(Bus::with_rom(rom).expect("mapper should be supported"));
+    cpu.reset();
+    cpu
+}
+
+fn read_text(bus: &Bus) -> String {
+    let mut text = String::new();
+    let mut addr = TEXT_ADDR;
+    while addr < 0x8000 {
+        let byte = bus.peek(addr);
+        if byte == 0 {
+            break;
+        }
+        text.push(byte as char);
+        addr += 1;
+    }
+    text.trim().to_string()
+}
+
+pub fn run_blargg(rom: Rom, protocol: Protocol, max_frames: u64) -> Outcome {
+    let mut cpu = new_cpu(rom);
+    let mut outcome = None;
+    let mut reset_at: Option<u64> = None;
+
+    let reason = cpu.run_until(|cpu| {
+        let frame = cpu.bus.ppu().frame_count;
+        if frame >= max_frames {
+            outcome = Some(Outcome::TimedOut { frames: frame, text: read_text(&cpu.bus) });
+            return true;
+        }
+
+        match protocol {
+            Protocol::Status6000 => {
+                let live = (0..3).all(|i| cpu.bus.peek(SIGNATURE_ADDR + i) == SIGNATURE[i as usize]);
+                if !live {
+                    return false;
+                }
+                match cpu.bus.peek(STATUS_ADDR) {
+                    STATUS_RUNNING => {}
+                    STATUS_NEEDS_RESET => match reset_at {
+                        None => reset_at = Some(frame + RESET_DELAY_FRAMES),
+                        Some(at) if frame >= at => {
+                            reset_at = None;
+                            cpu.reset();
+                        }
+                        Some(_) => {}
+                    },
+                    0 => outcome = Some(Outcome::Passed),
+                    code => outcome = Some(Outcome::Failed { code, text: read_text(&cpu.bus) }),
+                }
+            }
+            Protocol::ResultF8 => match cpu.bus.peek(LEGACY_RESULT_ADDR) {
+                0 => {}
+                1 => outcome = Some(Outcome::Passed),
+                code => outcome = Some(Outcome::Failed { code, text: String::new() }),
+            },
+        }
+        outcome.is_some()
+    });
+
+    match (reason, outcome) {
+        (StopReason::Condition, Some(outcome)) => outcome,
+        (reason, _) => Outcome::Crashed(reason),
+    }
+}
+
+pub fn assert_blargg(relative: &str, protocol: Protocol, max_frames: u64) {
+    match run_blargg(load_rom(relative), protocol, max_frames) {
+        Outcome::Passed => {}
+        other => panic!("{} did not pass: {:?}", relative, other),
+    }
+}
+
+/*
+    NESTEST
+    automation mode starts at $C000 without a PPU, every traced line has to match the golden log
+*/
+pub fn assert_nestest(rom_path: &str, log_path: &str) {
+    let (rom, log_path) = (load_rom(rom_path), fixture(log_path));
+    let golden = fs::read_to_string(&log_path).expect("nestest.log should be readable");
+    let golden: Vec<&str> = golden.lines().map(|line| line.trim_end()).filter(|line| !line.is_empty()).collect();
+
+    let mut cpu = new_cpu(rom);
+    cpu.program_counter = 0xC000;
+    let mut line = 0;
+    let mut mismatch: Option<(usize, String)> = None;
+
+    let reason = cpu.run_until(|cpu| {
+        if line >= golden.len() {
+            return true;
+        }
+        let traced = trace(cpu);
+        if traced != golden[line] {
+            mismatch = Some((line + 1, traced));
+            return true;
+        }
+        line += 1;
+        false
+    });
+
+    if let Some((number, traced)) = mismatch {
+        panic!("nestest.log line {} differs\n  expected: {}\n    actual: {}", number, golden[number - 1], traced);
+    }
+    if reason != StopReason::Condition {
+        panic!("nestest stopped after {} lines: {:?}", line, reason);
+    }
+    assert_eq!(cpu.bus.peek(0x0002), 0, "nestest official opcode result code");
+    assert_eq!(cpu.bus.peek(0x0003), 0, "nestest unofficial opcode result code");
+}
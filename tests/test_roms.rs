@@ -0,0 +1,147 @@
+mod common;
+
+use common::{Outcome, Protocol};
+
+/*
+    CONFORMANCE SUITE
+    one test per ROM so a regression points straight at the sub-test that broke,
+    paths are relative to the fixtures directory (see tests/fixtures/README.md).
+    they only run with `cargo test -- --ignored` since the ROMs aren't in the repository
+*/
+macro_rules! blargg_tests {
+    ($protocol:expr, $frames:expr, { $($name:ident => $path:expr,)* }) => {
+        $(
+            #[test]
+            #[ignore = "needs the test ROMs in tests/fixtures"]
+            fn $name() {
+                common::assert_blargg($path, $protocol, $frames);
+            }
+        )*
+    };
+}
+
+#[test]
+#[ignore = "needs the test ROMs in tests/fixtures"]
+fn nestest() {
+    common::assert_nestest("nestest/nestest.nes", "nestest/nestest.log");
+}
+
+blargg_tests!(Protocol::Status6000, 3000, {
+    instr_test_01_basics => "instr_test-v5/rom_singles/01-basics.nes",
+    instr_test_02_implied => "instr_test-v5/rom_singles/02-implied.nes",
+    instr_test_03_immediate => "instr_test-v5/rom_singles/03-immediate.nes",
+    instr_test_04_zero_page => "instr_test-v5/rom_singles/04-zero_page.nes",
+    instr_test_05_zp_xy => "instr_test-v5/rom_singles/05-zp_xy.nes",
+    instr_test_06_absolute => "instr_test-v5/rom_singles/06-absolute.nes",
+    instr_test_07_abs_xy => "instr_test-v5/rom_singles/07-abs_xy.nes",
+    instr_test_08_ind_x => "instr_test-v5/rom_singles/08-ind_x.nes",
+    instr_test_09_ind_y => "instr_test-v5/rom_singles/09-ind_y.nes",
+    instr_test_10_branches => "instr_test-v5/rom_singles/10-branches.nes",
+    instr_test_11_stack => "instr_test-v5/rom_singles/11-stack.nes",
+    instr_test_12_jmp_jsr => "instr_test-v5/rom_singles/12-jmp_jsr.nes",
+    instr_test_13_rts => "instr_test-v5/rom_singles/13-rts.nes",
+    instr_test_14_rti => "instr_test-v5/rom_singles/14-rti.nes",
+    instr_test_15_brk => "instr_test-v5/rom_singles/15-brk.nes",
+    instr_test_16_special => "instr_test-v5/rom_singles/16-special.nes",
+});
+
+blargg_tests!(Protocol::Status6000, 1500, {
+    ppu_vbl_nmi_01_vbl_basics => "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    ppu_vbl_nmi_02_vbl_set_time => "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    ppu_vbl_nmi_03_vbl_clear_time => "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    ppu_vbl_nmi_04_nmi_control => "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    ppu_vbl_nmi_05_nmi_timing => "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes",
+    ppu_vbl_nmi_06_suppression => "ppu_vbl_nmi/rom_singles/06-suppression.nes",
+    ppu_vbl_nmi_07_nmi_on_timing => "ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes",
+    ppu_vbl_nmi_08_nmi_off_timing => "ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes",
+    ppu_vbl_nmi_09_even_odd_frames => "ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes",
+    ppu_vbl_nmi_10_even_odd_timing => "ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes",
+});
+
+blargg_tests!(Protocol::Status6000, 1500, {
+    apu_test_1_len_ctr => "apu_test/rom_singles/1-len_ctr.nes",
+    apu_test_2_len_table => "apu_test/rom_singles/2-len_table.nes",
+    apu_test_3_irq_flag => "apu_test/rom_singles/3-irq_flag.nes",
+    apu_test_4_jitter => "apu_test/rom_singles/4-jitter.nes",
+    apu_test_5_len_timing => "apu_test/rom_singles/5-len_timing.nes",
+    apu_test_6_irq_flag_timing => "apu_test/rom_singles/6-irq_flag_timing.nes",
+    apu_test_7_dmc_basics => "apu_test/rom_singles/7-dmc_basics.nes",
+    apu_test_8_dmc_rates => "apu_test/rom_singles/8-dmc_rates.nes",
+});
+
+blargg_tests!(Protocol::ResultF8, 1500, {
+    cpu_timing_test => "cpu_timing_test6/cpu_timing_test.nes",
+});
+
+blargg_tests!(Protocol::ResultF8, 600, {
+    sprite_hit_01_basics => "sprite_hit_tests_2005.10.05/01.basics.nes",
+    sprite_hit_02_alignment => "sprite_hit_tests_2005.10.05/02.alignment.nes",
+    sprite_hit_03_corners => "sprite_hit_tests_2005.10.05/03.corners.nes",
+    sprite_hit_04_flip => "sprite_hit_tests_2005.10.05/04.flip.nes",
+    sprite_hit_05_left_clip => "sprite_hit_tests_2005.10.05/05.left_clip.nes",
+    sprite_hit_06_right_edge => "sprite_hit_tests_2005.10.05/06.right_edge.nes",
+    sprite_hit_07_screen_bottom => "sprite_hit_tests_2005.10.05/07.screen_bottom.nes",
+    sprite_hit_08_double_height => "sprite_hit_tests_2005.10.05/08.double_height.nes",
+    sprite_hit_09_timing_basics => "sprite_hit_tests_2005.10.05/09.timing_basics.nes",
+    sprite_hit_10_timing_order => "sprite_hit_tests_2005.10.05/10.timing_order.nes",
+    sprite_hit_11_edge_timing => "sprite_hit_tests_2005.10.05/11.edge_timing.nes",
+});
+
+/*
+    HARNESS
+    small programs that report through the same protocols, so result detection is tested
+    even without the ROMs
+*/
+fn store(program: &mut Vec<u8>, value: u8, addr: u16) {                      //LDA #value, STA addr
+    program.extend([0xA9, value, 0x8D, addr as u8, (addr >> 8) as u8]);
+}
+
+fn spin(program: &mut Vec<u8>) {                                              //JMP to itself, the program starts at $8000
+    let here = 0x8000 + program.len() as u16;
+    program.extend([0x4C, here as u8, (here >> 8) as u8]);
+}
+
+fn status_6000_program(status: u8, text: &str) -> Vec<u8> {
+    let mut program = vec![];
+    store(&mut program, 0x80, 0x6000);
+    store(&mut program, 0xDE, 0x6001);
+    store(&mut program, 0xB0, 0x6002);
+    store(&mut program, 0x61, 0x6003);
+    for (i, byte) in text.bytes().chain([0]).enumerate() {
+        store(&mut program, byte, 0x6004 + i as u16);
+    }
+    store(&mut program, status, 0x6000);
+    spin(&mut program);
+    program
+}
+
+fn f8_program(result: u8) -> Vec<u8> {
+    let mut program = vec![];
+    store(&mut program, result, 0x00F8);
+    spin(&mut program);
+    program
+}
+
+#[test]
+fn harness_detects_status_6000_results() {
+    let rom = common::program_rom(status_6000_program(0, "Passed"));
+    assert_eq!(common::run_blargg(rom, Protocol::Status6000, 60), Outcome::Passed);
+
+    let rom = common::program_rom(status_6000_program(3, "Failed #3"));
+    let outcome = common::run_blargg(rom, Protocol::Status6000, 60);
+    assert_eq!(outcome, Outcome::Failed { code: 3, text: "Failed #3".to_string() });
+
+    let mut program = vec![];
+    spin(&mut program);                                                       //never signs on
+    let rom = common::program_rom(program);
+    assert!(matches!(common::run_blargg(rom, Protocol::Status6000, 10), Outcome::TimedOut { frames: 10, .. }));
+}
+
+#[test]
+fn harness_detects_f8_results() {
+    let rom = common::program_rom(f8_program(0x01));
+    assert_eq!(common::run_blargg(rom, Protocol::ResultF8, 60), Outcome::Passed);
+
+    let rom = common::program_rom(f8_program(0x04));
+    assert_eq!(common::run_blargg(rom, Protocol::ResultF8, 60), Outcome::Failed { code: 4, text: String::new() });
+}